@@ -0,0 +1,70 @@
+use std::alloc::{self, Layout};
+use std::error::Error;
+use std::fmt;
+use std::ptr::NonNull;
+
+/// A stand-in for std's `Allocator` trait, which is still unstable. An Arc
+/// created with `Arc::new_in` takes its memory from an `Allocator` and gives it
+/// back there when the last reference is dropped, so arenas and pools can back
+/// shared data.
+///
+/// # Safety
+///
+/// Memory returned by `allocate` must stay valid until it is passed back to
+/// `deallocate` on the same allocator, or a clone of it, with the same layout.
+pub unsafe trait Allocator {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<u8>, AllocError>;
+
+    /// Gives back memory returned by [`Allocator::allocate`].
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been allocated by this allocator with `layout`, and not
+    /// already deallocated.
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout);
+}
+
+// The global allocator, used by Arc::new.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Global;
+
+unsafe impl Allocator for Global {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        // The global allocator must not be asked for zero bytes, but any
+        // aligned, non-null pointer is a valid zero sized allocation.
+        if layout.size() == 0 {
+            return Ok(unsafe { NonNull::new_unchecked(layout.align() as *mut u8) });
+        }
+        NonNull::new(unsafe { alloc::alloc(layout) }).ok_or(AllocError)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        if layout.size() != 0 {
+            alloc::dealloc(ptr.as_ptr(), layout)
+        }
+    }
+}
+
+// Lets an allocator be shared by reference, such as an arena which outlives
+// every Arc allocated from it.
+unsafe impl<A: Allocator + ?Sized> Allocator for &A {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        (**self).allocate(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        (**self).deallocate(ptr, layout)
+    }
+}
+
+// Returned when an allocator has no memory left for a request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AllocError;
+
+impl fmt::Display for AllocError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("memory allocation failed")
+    }
+}
+
+impl Error for AllocError {}
@@ -0,0 +1,146 @@
+use std::marker::PhantomData;
+// Under loom, spinning yields to the other threads, see tests/loom.rs.
+#[cfg(loom)]
+use loom::{
+    hint,
+    sync::atomic::{fence, AtomicBool, AtomicPtr, AtomicUsize, Ordering},
+};
+#[cfg(not(loom))]
+use std::{
+    hint,
+    sync::atomic::{fence, AtomicBool, AtomicPtr, AtomicUsize, Ordering},
+};
+
+use crate::simple_arc::Arc;
+
+// A cell holding an Arc which can be loaded and replaced concurrently, for
+// read-mostly data such as configuration which is swapped out wholesale.
+//
+// The difficulty is that load has to increment the count of whichever Arc it
+// finds, but a concurrent store may drop that Arc's last reference in between.
+// Readers therefore announce themselves in one of two counters, and a writer
+// which has swapped out an Arc waits for the readers which might have seen it
+// to finish before dropping its reference.
+//
+// Loads never wait. Writers wait for the loads in progress, and for each other,
+// but as new loads count towards the other counter a steady stream of them
+// can't hold a writer up forever.
+pub struct AtomicArc<T> {
+    // Always a pointer from Arc::into_raw, holding one reference.
+    ptr: AtomicPtr<T>,
+    // Which of the two reader counters new loads should use.
+    generation: AtomicUsize,
+    readers: [AtomicUsize; 2],
+    // Only one writer at a time flips the generation.
+    writing: AtomicBool,
+    // Makes AtomicArc Send and Sync exactly when Arc<T> is.
+    _marker: PhantomData<Arc<T>>,
+}
+
+impl<T> AtomicArc<T> {
+    pub fn new(arc: Arc<T>) -> Self {
+        Self {
+            ptr: AtomicPtr::new(Arc::into_raw(arc) as *mut T),
+            generation: AtomicUsize::new(0),
+            readers: [AtomicUsize::new(0), AtomicUsize::new(0)],
+            writing: AtomicBool::new(false),
+            _marker: PhantomData,
+        }
+    }
+
+    // Returns a new reference to the Arc currently stored.
+    pub fn load(&self) -> Arc<T> {
+        let generation = self.enter();
+        let ptr = self.ptr.load(Ordering::SeqCst);
+        // A writer which swapped this pointer out waits for us to leave before
+        // dropping its reference, so the Arc is still alive here.
+        let arc = unsafe {
+            Arc::increment_strong_count(ptr);
+            Arc::from_raw(ptr)
+        };
+        self.readers[generation].fetch_sub(1, Ordering::SeqCst);
+        arc
+    }
+
+    // Replaces the stored Arc, dropping the old one.
+    pub fn store(&self, arc: Arc<T>) {
+        drop(self.swap(arc));
+    }
+
+    // Replaces the stored Arc, returning the old one.
+    pub fn swap(&self, arc: Arc<T>) -> Arc<T> {
+        let new = Arc::into_raw(arc) as *mut T;
+        self.lock_writers();
+        let old = self.ptr.swap(new, Ordering::SeqCst);
+        self.wait_for_readers();
+        self.unlock_writers();
+        // The reference the cell held is now ours.
+        unsafe { Arc::from_raw(old) }
+    }
+
+    // Stores new only if the cell still holds the same allocation as current,
+    // returning the Arc it replaced. Otherwise new is dropped and the Arc the
+    // cell holds instead is returned as the error, ready for a retry.
+    pub fn compare_and_swap(&self, current: &Arc<T>, new: Arc<T>) -> Result<Arc<T>, Arc<T>> {
+        let current = Arc::as_ptr(current);
+        let new = Arc::into_raw(new) as *mut T;
+        self.lock_writers();
+        // Only writers change the pointer, so nothing can change it between
+        // this check and the swap.
+        if !std::ptr::eq(self.ptr.load(Ordering::SeqCst), current) {
+            self.unlock_writers();
+            drop(unsafe { Arc::from_raw(new) });
+            return Err(self.load());
+        }
+        let old = self.ptr.swap(new, Ordering::SeqCst);
+        self.wait_for_readers();
+        self.unlock_writers();
+        Ok(unsafe { Arc::from_raw(old) })
+    }
+
+    // Registers a load in progress, returning the counter to decrement after.
+    fn enter(&self) -> usize {
+        loop {
+            let generation = self.generation.load(Ordering::SeqCst);
+            self.readers[generation].fetch_add(1, Ordering::SeqCst);
+            // Pairs with the fence in wait_for_readers: either the writer sees
+            // this increment, or we see its new pointer and generation.
+            fence(Ordering::SeqCst);
+            // A writer may have flipped the generation and checked this counter
+            // before we incremented it, in which case it isn't waiting for us.
+            if self.generation.load(Ordering::SeqCst) == generation {
+                return generation;
+            }
+            self.readers[generation].fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    // Called after swapping the pointer. Loads which start from here on use
+    // the other counter and can only see the new pointer, so once the old
+    // counter drains nobody can still be about to use the old one.
+    fn wait_for_readers(&self) {
+        let old = self.generation.fetch_xor(1, Ordering::SeqCst);
+        fence(Ordering::SeqCst);
+        while self.readers[old].load(Ordering::SeqCst) != 0 {
+            hint::spin_loop();
+        }
+    }
+
+    fn lock_writers(&self) {
+        while self.writing.swap(true, Ordering::Acquire) {
+            hint::spin_loop();
+        }
+    }
+
+    fn unlock_writers(&self) {
+        self.writing.store(false, Ordering::Release);
+    }
+}
+
+impl<T> Drop for AtomicArc<T> {
+    fn drop(&mut self) {
+        // We have exclusive access, so there are no loads in progress. A load
+        // rather than get_mut, which loom's atomics don't have.
+        drop(unsafe { Arc::from_raw(self.ptr.load(Ordering::Relaxed)) });
+    }
+}
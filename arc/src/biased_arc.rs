@@ -0,0 +1,145 @@
+use std::cell::Cell;
+use std::fmt;
+use std::ops::Deref;
+use std::ptr::NonNull;
+
+use crate::simple_arc::{overflowed, Arc, MAX_REF_COUNT};
+
+// An Arc handle whose clones on the current thread are counted with plain,
+// non-atomic arithmetic.
+//
+// Every clone and drop of an Arc is an atomic read-modify-write, which costs
+// far more than an ordinary increment even when only one thread ever touches
+// the count. A BiasedArc holds a single reference in the shared atomic count
+// on behalf of a whole group of clones, and counts the group itself in a
+// thread-local counter. Clones and drops within the group never touch the
+// atomic count. It is reconciled, by dropping the group's reference, only once
+// the last clone in the group is dropped.
+//
+// The group lives on one thread, so BiasedArc is neither Send nor Sync. To
+// hand the data to another thread, take a regular Arc with to_arc, which costs
+// one atomic increment, and turn it back into a BiasedArc there if that
+// thread clones it heavily too.
+pub struct BiasedArc<T: ?Sized> {
+    // From Arc::into_raw, holding the group's reference.
+    data: NonNull<T>,
+    // The number of BiasedArcs in the group, shared by all of them.
+    local: NonNull<Cell<usize>>,
+}
+
+impl<T> BiasedArc<T> {
+    pub fn new(data: T) -> Self {
+        Self::from(Arc::new(data))
+    }
+}
+
+impl<T: ?Sized> BiasedArc<T> {
+    // Returns a regular Arc to the same data, for sending to another thread.
+    pub fn to_arc(this: &Self) -> Arc<T> {
+        unsafe {
+            Arc::increment_strong_count(this.data.as_ptr());
+            Arc::from_raw(this.data.as_ptr())
+        }
+    }
+
+    // The number of BiasedArcs in this thread's group, which is exact as no
+    // other thread can change it.
+    pub fn local_count(this: &Self) -> usize {
+        unsafe { this.local.as_ref() }.get()
+    }
+}
+
+// Starts a new group holding the reference arc had.
+impl<T: ?Sized> From<Arc<T>> for BiasedArc<T> {
+    fn from(arc: Arc<T>) -> Self {
+        Self {
+            data: unsafe { NonNull::new_unchecked(Arc::into_raw(arc) as *mut T) },
+            local: NonNull::from(Box::leak(Box::new(Cell::new(1)))),
+        }
+    }
+}
+
+impl<T: ?Sized> Deref for BiasedArc<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // The group's reference keeps the data alive.
+        unsafe { self.data.as_ref() }
+    }
+}
+
+impl<T: ?Sized> Clone for BiasedArc<T> {
+    fn clone(&self) -> Self {
+        let local = unsafe { self.local.as_ref() };
+        // As for Arc, guard against the count overflowing from leaked clones.
+        let count = local.get();
+        local.set(count + 1);
+        if count > MAX_REF_COUNT {
+            overflowed(local);
+        }
+        Self {
+            data: self.data,
+            local: self.local,
+        }
+    }
+}
+
+impl<T: ?Sized> Drop for BiasedArc<T> {
+    fn drop(&mut self) {
+        let local = unsafe { self.local.as_ref() };
+        local.set(local.get() - 1);
+        if local.get() == 0 {
+            // This was the last of the group, so free its counter and give up
+            // the reference it held, which may free the data too.
+            unsafe {
+                drop(Box::from_raw(self.local.as_ptr()));
+                Arc::decrement_strong_count(self.data.as_ptr());
+            }
+        }
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for BiasedArc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized + fmt::Display> fmt::Display for BiasedArc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
+
+// As for Arc, only the non-aborting ways of handling overflow can be tested.
+#[cfg(all(test, any(feature = "overflow_saturate", feature = "overflow_panic")))]
+mod tests {
+    use super::*;
+
+    fn set_local_count<T>(arc: &BiasedArc<T>, count: usize) {
+        unsafe { arc.local.as_ref() }.set(count);
+    }
+
+    #[test]
+    #[cfg(feature = "overflow_saturate")]
+    fn overflow_saturates() {
+        use crate::simple_arc::SATURATED_REF_COUNT;
+
+        let arc = BiasedArc::new(1);
+        set_local_count(&arc, MAX_REF_COUNT + 1);
+        let clone = arc.clone();
+        drop(clone);
+        assert_eq!(BiasedArc::local_count(&arc), SATURATED_REF_COUNT - 1);
+        set_local_count(&arc, 1);
+    }
+
+    #[test]
+    #[cfg(all(feature = "overflow_panic", not(feature = "overflow_saturate")))]
+    fn overflow_panics() {
+        let arc = BiasedArc::new(1);
+        set_local_count(&arc, MAX_REF_COUNT + 1);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| arc.clone()));
+        assert!(result.is_err());
+        assert_eq!(BiasedArc::local_count(&arc), MAX_REF_COUNT + 1);
+        set_local_count(&arc, 1);
+    }
+}
@@ -0,0 +1,61 @@
+use std::fmt;
+
+use crate::simple_arc::Arc;
+
+// A value which is cheap to clone, and copied only when a shared clone is
+// written to, built on Arc::make_mut.
+//
+// Every clone reads the same allocation. write copies the data first if any
+// other clone still shares it, so changes through one clone are never seen by
+// the others, and writing to a clone nothing else shares is as cheap as
+// writing to a plain value.
+pub struct CowArc<T: Clone>(Arc<T>);
+
+impl<T: Clone> CowArc<T> {
+    pub fn new(data: T) -> Self {
+        Self(Arc::new(data))
+    }
+
+    pub fn read(&self) -> &T {
+        &self.0
+    }
+
+    // Copies the data first if another clone shares it, see Arc::make_mut.
+    pub fn write(&mut self) -> &mut T {
+        Arc::make_mut(&mut self.0)
+    }
+
+    // Whether a write would have to copy the data first. Another thread may
+    // drop or clone a shared clone at any moment, so this is only a snapshot.
+    pub fn is_shared(&self) -> bool {
+        Arc::strong_count(&self.0) != 1
+    }
+
+    pub fn into_arc(this: Self) -> Arc<T> {
+        this.0
+    }
+}
+
+impl<T: Clone> Clone for CowArc<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T: Clone> From<Arc<T>> for CowArc<T> {
+    fn from(arc: Arc<T>) -> Self {
+        Self(arc)
+    }
+}
+
+impl<T: Clone + Default> Default for CowArc<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: Clone + fmt::Debug> fmt::Debug for CowArc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.read(), f)
+    }
+}
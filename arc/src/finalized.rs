@@ -0,0 +1,64 @@
+use std::fmt;
+use std::mem::ManuallyDrop;
+use std::ops::{Deref, DerefMut};
+
+use crate::simple_arc::Arc;
+
+// Data paired with a callback which runs on it just before it is dropped.
+//
+// Inside an Arc, see Arc::new_with_finalizer, the data is dropped when the
+// last reference is, so the callback runs exactly once at that point. This
+// suits cleanup which needs to see the data itself, such as flushing a buffer
+// or releasing an external handle, without writing a Drop impl for it.
+//
+// If the data is taken out with Arc::try_unwrap or Arc::into_inner, the
+// callback goes with it and runs when the Finalized is dropped instead.
+pub struct Finalized<T, F: FnOnce(&mut T)> {
+    data: T,
+    // Taken in Drop, as FnOnce needs to be called by value.
+    finalizer: ManuallyDrop<F>,
+}
+
+impl<T, F: FnOnce(&mut T)> Finalized<T, F> {
+    pub fn new(data: T, finalizer: F) -> Self {
+        Self {
+            data,
+            finalizer: ManuallyDrop::new(finalizer),
+        }
+    }
+}
+
+impl<T, F: FnOnce(&mut T)> Arc<Finalized<T, F>> {
+    // Creates an Arc which calls finalizer on the data once the last reference
+    // to it is dropped, before dropping the data itself.
+    pub fn new_with_finalizer(data: T, finalizer: F) -> Self {
+        Arc::new(Finalized::new(data, finalizer))
+    }
+}
+
+impl<T, F: FnOnce(&mut T)> Deref for Finalized<T, F> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.data
+    }
+}
+
+impl<T, F: FnOnce(&mut T)> DerefMut for Finalized<T, F> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.data
+    }
+}
+
+impl<T, F: FnOnce(&mut T)> Drop for Finalized<T, F> {
+    fn drop(&mut self) {
+        // Drop only runs once, so the finalizer is still there to take.
+        let finalizer = unsafe { ManuallyDrop::take(&mut self.finalizer) };
+        finalizer(&mut self.data);
+    }
+}
+
+impl<T: fmt::Debug, F: FnOnce(&mut T)> fmt::Debug for Finalized<T, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.data, f)
+    }
+}
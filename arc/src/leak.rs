@@ -0,0 +1,91 @@
+use std::any;
+use std::backtrace::Backtrace;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+// Every Arc allocation which hasn't been freed yet, by address.
+static LIVE: Mutex<BTreeMap<usize, Allocation>> = Mutex::new(BTreeMap::new());
+
+struct Allocation {
+    type_name: &'static str,
+    backtrace: Backtrace,
+}
+
+fn live() -> MutexGuard<'static, BTreeMap<usize, Allocation>> {
+    // A panic while holding the lock can't leave the map inconsistent.
+    LIVE.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// Records a new allocation, along with where it was created.
+pub(crate) fn track<T: ?Sized>(ptr: *const ()) {
+    // Captured before locking, as it is by far the slowest part.
+    let allocation = Allocation {
+        type_name: any::type_name::<T>(),
+        backtrace: Backtrace::force_capture(),
+    };
+    live().insert(ptr as usize, allocation);
+}
+
+// Records that an allocation has been freed.
+pub(crate) fn untrack(ptr: *const ()) {
+    live().remove(&(ptr as usize));
+}
+
+// Returns every Arc allocation which is still alive, with the backtrace of
+// where each was created.
+//
+// Reference counting can't free a cycle of Arcs pointing at each other, and
+// nothing reports when that happens. Calling this once everything should have
+// been dropped, and asserting that the report is empty, makes such leaks show
+// up in tests. The registry is global, so Arcs from other tests running at the
+// same time will show up too.
+pub fn leak_report() -> LeakReport {
+    let leaks = live()
+        .iter()
+        .map(|(&address, allocation)| Leak {
+            address,
+            type_name: allocation.type_name,
+            backtrace: allocation.backtrace.to_string(),
+        })
+        .collect();
+    LeakReport { leaks }
+}
+
+#[derive(Debug)]
+pub struct LeakReport {
+    leaks: Vec<Leak>,
+}
+
+#[derive(Debug)]
+pub struct Leak {
+    pub address: usize,
+    // The type of the data, as given by std::any::type_name.
+    pub type_name: &'static str,
+    pub backtrace: String,
+}
+
+impl LeakReport {
+    pub fn is_empty(&self) -> bool {
+        self.leaks.is_empty()
+    }
+
+    pub fn leaks(&self) -> &[Leak] {
+        &self.leaks
+    }
+}
+
+impl fmt::Display for LeakReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} Arc allocations still alive", self.leaks.len())?;
+        for leak in &self.leaks {
+            writeln!(
+                f,
+                "\nArc<{}> at {:#x}, created at:",
+                leak.type_name, leak.address
+            )?;
+            writeln!(f, "{}", leak.backtrace)?;
+        }
+        Ok(())
+    }
+}
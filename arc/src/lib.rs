@@ -1 +1,14 @@
+pub mod allocator;
+pub mod atomic_arc;
+pub mod biased_arc;
+pub mod cow_arc;
+pub mod finalized;
+#[cfg(feature = "leak_detect")]
+pub mod leak;
+pub mod projected_arc;
 pub mod simple_arc;
+pub mod thin_arc;
+pub mod unique_arc;
+
+#[cfg(feature = "leak_detect")]
+pub use leak::leak_report;
@@ -0,0 +1,80 @@
+use std::fmt;
+use std::ops::Deref;
+use std::ptr::NonNull;
+
+use crate::simple_arc::Arc;
+
+// A reference to part of an Arc's data, such as a field, which keeps the whole
+// allocation alive. Created with Arc::project:
+//
+//     let name: ProjectedArc<str> = Arc::project(user, |user| user.name.as_str());
+//
+// This hands out a piece of shared data on its own without giving every field
+// its own Arc, or exposing the type it lives in.
+pub struct ProjectedArc<U: ?Sized> {
+    // Only needed to drop the allocation once we're done, so the type of the
+    // data it holds is erased.
+    owner: Arc<dyn Send + Sync>,
+    // Points into the data owner keeps alive.
+    field: NonNull<U>,
+}
+
+impl<T: Send + Sync + 'static> Arc<T> {
+    // Turns the Arc into a ProjectedArc of the part of its data f returns.
+    pub fn project<U: ?Sized>(arc: Self, f: impl FnOnce(&T) -> &U) -> ProjectedArc<U> {
+        // The data never moves and isn't dropped before owner is, so the
+        // reference stays valid for as long as the ProjectedArc holds it.
+        let field = NonNull::from(f(&arc));
+        ProjectedArc {
+            // Only unsizes the data, as map_unsize requires.
+            owner: unsafe { Arc::map_unsize(arc, |data| data as &(dyn Send + Sync)) },
+            field,
+        }
+    }
+}
+
+impl<U: ?Sized> ProjectedArc<U> {
+    // Narrows the projection further, still keeping the original allocation.
+    pub fn project<V: ?Sized>(projected: Self, f: impl FnOnce(&U) -> &V) -> ProjectedArc<V> {
+        let field = NonNull::from(f(&projected));
+        ProjectedArc {
+            owner: projected.owner,
+            field,
+        }
+    }
+}
+
+impl<U: ?Sized> Deref for ProjectedArc<U> {
+    type Target = U;
+    fn deref(&self) -> &U {
+        // owner keeps the data this points into alive.
+        unsafe { self.field.as_ref() }
+    }
+}
+
+// Clones share the original allocation, and point at the same part of it.
+impl<U: ?Sized> Clone for ProjectedArc<U> {
+    fn clone(&self) -> Self {
+        Self {
+            owner: self.owner.clone(),
+            field: self.field,
+        }
+    }
+}
+
+// The data the field lives in is Send and Sync, so only the field itself,
+// which is shared between every clone, needs checking.
+unsafe impl<U: ?Sized + Sync> Send for ProjectedArc<U> {}
+unsafe impl<U: ?Sized + Sync> Sync for ProjectedArc<U> {}
+
+impl<U: ?Sized + fmt::Debug> fmt::Debug for ProjectedArc<U> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<U: ?Sized + fmt::Display> fmt::Display for ProjectedArc<U> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
@@ -1,31 +1,286 @@
+use std::alloc::{self, Layout};
+use std::borrow::Borrow;
+use std::cmp::Ordering as CmpOrdering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::mem::{self, ManuallyDrop};
 use std::ops::Deref;
-use std::ptr::NonNull;
+use std::pin::Pin;
+use std::ptr::{self, NonNull};
+// loom swaps in atomics which explore every interleaving and ordering the
+// memory model allows, see tests/loom.rs.
+#[cfg(loom)]
+use loom::sync::atomic::{fence, AtomicUsize, Ordering};
+#[cfg(not(loom))]
 use std::sync::atomic::{fence, AtomicUsize, Ordering};
-use std::usize;
 
-struct ArcData<T> {
+use crate::allocator::{AllocError, Allocator, Global};
+
+// repr(C) fixes the field order, so that the data is always at the same offset
+// from the start of the allocation.
+#[repr(C)]
+struct ArcData<T: ?Sized> {
     ref_count: AtomicUsize,
     data: T,
 }
 
-pub struct Arc<T> {
+// The allocation comes from A, see Arc::new_in, and is handed back to it when
+// the last reference is dropped.
+pub struct Arc<T: ?Sized, A: Allocator = Global> {
     ptr: NonNull<ArcData<T>>,
+    alloc: A,
+    // NonNull alone makes Arc covariant in T, like &T, which is right since
+    // the data is never written through a shared Arc. It doesn't tell drop
+    // check that dropping an Arc may drop a T, though, which this does, so
+    // data borrowed by T must outlive the Arc as it would for a Box<T>.
+    //
+    // std's Arc also marks its Drop impl #[may_dangle], promising it only
+    // drops T rather than using it otherwise, which lets T's borrows end at
+    // the same time as the Arc. That is unstable, so here the borrows must
+    // strictly outlive the Arc instead, which is stricter but still sound.
+    _marker: PhantomData<ArcData<T>>,
+}
+
+// Counts can only get this high by leaking clones, e.g. with mem::forget, as
+// there isn't the memory for this many Arcs. Going much further would risk
+// the count wrapping around to zero and freeing the data while in use.
+pub(crate) const MAX_REF_COUNT: usize = usize::MAX / 2;
+
+// Called when a clone takes the count past MAX_REF_COUNT. By default this
+// aborts the process, the overflow_saturate and overflow_panic features pick
+// one of the alternatives instead, in that order if both are enabled. ThinArc
+// and BiasedArc share it, so the features cover every kind of Arc.
+#[cold]
+pub(crate) fn overflowed(ref_count: &impl RefCount) {
+    if cfg!(feature = "overflow_saturate") {
+        // Pin the count well above MAX_REF_COUNT, but far enough below
+        // usize::MAX that racing clones can't wrap it. Every Arc there is room
+        // for could be dropped without taking it back to zero, so the data is
+        // leaked rather than ever being freed, which is safe.
+        ref_count.saturate();
+    } else if cfg!(feature = "overflow_panic") {
+        // Undo our increment, so the count is still right for the Arcs which
+        // do exist, and unwind out of clone.
+        ref_count.undo_increment();
+        panic!("Arc reference count overflowed");
+    } else {
+        std::process::abort();
+    }
+}
+
+pub(crate) const SATURATED_REF_COUNT: usize = MAX_REF_COUNT + (usize::MAX - MAX_REF_COUNT) / 2;
+
+// The counts `overflowed` deals with: the atomic ones of Arc and ThinArc, and
+// BiasedArc's count of its local group.
+pub(crate) trait RefCount {
+    fn saturate(&self);
+    fn undo_increment(&self);
+}
+
+impl RefCount for AtomicUsize {
+    fn saturate(&self) {
+        self.store(SATURATED_REF_COUNT, Ordering::Relaxed);
+    }
+    fn undo_increment(&self) {
+        self.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// ThinArc keeps std's atomics under loom, as only Arc is checked with it.
+#[cfg(loom)]
+impl RefCount for std::sync::atomic::AtomicUsize {
+    fn saturate(&self) {
+        self.store(SATURATED_REF_COUNT, std::sync::atomic::Ordering::Relaxed);
+    }
+    fn undo_increment(&self) {
+        self.fetch_sub(1, std::sync::atomic::Ordering::Relaxed);
+    }
+}
+
+impl RefCount for std::cell::Cell<usize> {
+    fn saturate(&self) {
+        self.set(SATURATED_REF_COUNT);
+    }
+    fn undo_increment(&self) {
+        self.set(self.get() - 1);
+    }
+}
+
+// Offset of the data from the start of an ArcData, when the data has the given
+// alignment. Following repr(C), it is placed straight after the count, rounded
+// up to that alignment.
+fn data_offset(align: usize) -> usize {
+    let count = Layout::new::<AtomicUsize>();
+    let data = Layout::from_size_align(0, align).unwrap();
+    count.extend(data).unwrap().1
 }
 
 impl<T> Arc<T> {
     pub fn new(data: T) -> Self {
-        Self {
-            ptr: NonNull::from(Box::leak(Box::new(ArcData {
-                ref_count: AtomicUsize::new(0),
+        Self::new_in(data, Global)
+    }
+
+    // Like new, but returns an error if the allocation fails rather than
+    // aborting the process, for callers which can recover from running out.
+    pub fn try_new(data: T) -> Result<Self, AllocError> {
+        Self::try_new_in(data, Global)
+    }
+
+    // The data never moves once it is in the allocation, and nothing can get
+    // at the Arc inside the Pin to take it back out with try_unwrap or mutate
+    // it with get_mut, so the data stays pinned until it is dropped in place.
+    pub fn pin(data: T) -> Pin<Self> {
+        unsafe { Pin::new_unchecked(Arc::new(data)) }
+    }
+}
+
+impl<T, A: Allocator> Arc<T, A> {
+    // Like new, but allocating from alloc, which every clone then shares.
+    pub fn new_in(data: T, alloc: A) -> Self {
+        match Self::try_new_in(data, alloc) {
+            Ok(arc) => arc,
+            Err(_) => alloc::handle_alloc_error(Layout::new::<ArcData<T>>()),
+        }
+    }
+
+    // Like new_in, but returns an error if the allocator has run out.
+    pub fn try_new_in(data: T, alloc: A) -> Result<Self, AllocError> {
+        let ptr = alloc
+            .allocate(Layout::new::<ArcData<T>>())?
+            .cast::<ArcData<T>>();
+        unsafe {
+            ptr.as_ptr().write(ArcData {
+                ref_count: AtomicUsize::new(1),
                 data,
-            }))),
+            })
+        };
+        #[cfg(feature = "leak_detect")]
+        crate::leak::track::<T>(ptr.as_ptr().cast());
+        Ok(Self {
+            ptr,
+            alloc,
+            _marker: PhantomData,
+        })
+    }
+
+    // Clone-on-write: returns a mutable reference to the data, first replacing
+    // this Arc with a fresh copy of the data if any other Arc shares it. The
+    // other Arcs keep the original, unchanged.
+    pub fn make_mut(arc: &mut Self) -> &mut T
+    where
+        T: Clone,
+        A: Clone,
+    {
+        // Acquire pairs with the Release decrement in Drop, so that if the
+        // other Arcs have just been dropped, their accesses happen before ours.
+        if arc.data().ref_count.load(Ordering::Acquire) != 1 {
+            *arc = Arc::new_in(T::clone(arc), arc.alloc.clone());
+        }
+        // Either way this is now the only reference, and we hold it mutably so
+        // nothing can clone it in the meantime.
+        unsafe { &mut arc.ptr.as_mut().data }
+    }
+
+    // Moves the data out if this is the only reference, otherwise hands the
+    // Arc back unchanged.
+    pub fn try_unwrap(arc: Self) -> Result<T, Self> {
+        if arc
+            .data()
+            .ref_count
+            .compare_exchange(1, 0, Ordering::Relaxed, Ordering::Relaxed)
+            .is_err()
+        {
+            return Err(arc);
         }
+        fence(Ordering::Acquire);
+        Ok(unsafe { Self::take(arc) })
     }
 
-    pub fn data(&self) -> &ArcData<T> {
+    // Like try_unwrap, but drops this reference when it isn't the last one.
+    // When several threads call this on the last few clones, exactly one of
+    // them gets the data, whereas with try_unwrap they could all fail.
+    pub fn into_inner(arc: Self) -> Option<T> {
+        if arc.data().ref_count.fetch_sub(1, Ordering::Release) != 1 {
+            // Our reference is gone, but the allocator still needs dropping.
+            let mut arc = ManuallyDrop::new(arc);
+            unsafe { ptr::drop_in_place(&mut arc.alloc) };
+            return None;
+        }
+        fence(Ordering::Acquire);
+        Some(unsafe { Self::take(arc) })
+    }
+
+    // As in Drop, once the count reaches zero we can reclaim the allocation,
+    // but moving the data out of it rather than dropping it.
+    //
+    // Safety: the count must have reached zero, with the Acquire fence done.
+    unsafe fn take(arc: Self) -> T {
+        // The count is zero, so Drop must not run and free it again.
+        let arc = ManuallyDrop::new(arc);
+        let data = ptr::read(&arc.ptr.as_ref().data);
+        let alloc = ptr::read(&arc.alloc);
+        #[cfg(feature = "leak_detect")]
+        crate::leak::untrack(arc.ptr.as_ptr().cast());
+        alloc.deallocate(arc.ptr.cast(), Layout::new::<ArcData<T>>());
+        data
+    }
+}
+
+impl<T: ?Sized, A: Allocator> Arc<T, A> {
+    fn data(&self) -> &ArcData<T> {
         unsafe { self.ptr.as_ref() }
     }
 
+    // The number of Arcs sharing the data, including this one. Another thread
+    // may clone or drop one at any moment, so this is only a snapshot.
+    pub fn strong_count(arc: &Self) -> usize {
+        arc.data().ref_count.load(Ordering::Relaxed)
+    }
+
+    // A pointer to the data, without giving up the reference as into_raw does.
+    // The data never moves while any clone is alive, so the address identifies
+    // the allocation: every clone returns the same pointer, and no other live
+    // Arc can share it. This makes it usable as a key for identity-based maps,
+    // as long as the Arc is kept alive alongside it so the address isn't reused.
+    pub fn as_ptr(arc: &Self) -> *const T {
+        unsafe { ptr::addr_of!((*arc.ptr.as_ptr()).data) }
+    }
+
+    // Whether two Arcs are clones sharing the same allocation, as opposed to
+    // == which compares the data. Any pointer metadata, such as a vtable, is
+    // ignored, so an Arc and its map_unsize'd clone are still equal.
+    pub fn ptr_eq(a: &Self, b: &Self) -> bool {
+        ptr::addr_eq(Self::as_ptr(a), Self::as_ptr(b))
+    }
+
+    // Rebuilds an Arc from a pointer to its data, see from_raw.
+    unsafe fn from_data_ptr(ptr: *const T, alloc: A) -> Self {
+        // Step back from the data field to the start of the allocation. The
+        // data is still alive, so its alignment can be read through ptr.
+        let offset = data_offset(mem::align_of_val(&*ptr));
+        let data = ptr.byte_sub(offset) as *mut ArcData<T>;
+        Self {
+            ptr: NonNull::new_unchecked(data),
+            alloc,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Like get_mut without checking the count, for when the caller already
+    /// knows nothing else can access the data, such as while initialising it
+    /// in stages before sharing it. This skips the atomic load and fence.
+    ///
+    /// # Safety
+    ///
+    /// No other Arc, or reference obtained through one, may access the data
+    /// while the returned reference is in use, and any earlier accesses from
+    /// other threads must happen before it, for example by the clones having
+    /// been created and dropped on this thread.
+    pub unsafe fn get_mut_unchecked(arc: &mut Self) -> &mut T {
+        &mut arc.ptr.as_mut().data
+    }
+
     // arc: &mut Self is used here so that it must be called as Arc::get_mut(&mut value)
     // to avoid ambiguity with other methods on the underlying data (T).
     pub fn get_mut(arc: &mut Self) -> Option<&mut T> {
@@ -38,13 +293,176 @@ impl<T> Arc<T> {
             None
         }
     }
+
+    /// Converts to an Arc of an unsized type sharing the same allocation, such
+    /// as a trait object or a slice. std's Arc does this implicitly through
+    /// CoerceUnsized, which isn't stable, so f performs the coercion instead,
+    /// as in `Arc::map_unsize(arc, |x| x as &dyn Display)`.
+    ///
+    /// Panics if the reference f returns plainly isn't to the data, having a
+    /// different address, size or alignment.
+    ///
+    /// # Safety
+    ///
+    /// `f` must return the reference it is given, only unsized, as `x as &dyn
+    /// Trait` or `x as &[_]` do. The new Arc drops the data as a `U`, so a
+    /// reference to anything else, even a field which happens to have the same
+    /// address, size and alignment, would run the wrong destructor.
+    pub unsafe fn map_unsize<U: ?Sized>(arc: Self, f: impl FnOnce(&T) -> &U) -> Arc<U, A> {
+        let data: &T = &arc;
+        let coerced: &U = f(data);
+        // A field at the start of the data usually shares its address, but
+        // not its size or alignment, which the allocation's layout depends on.
+        // This can't tell a single-field wrapper from its field though, which
+        // is why the caller has to promise f unsizes rather than projects.
+        assert!(
+            ptr::addr_eq(coerced, data)
+                && mem::size_of_val(coerced) == mem::size_of_val(data)
+                && mem::align_of_val(coerced) == mem::align_of_val(data),
+            "map_unsize must return a reference to the data itself"
+        );
+        let coerced: *const U = coerced;
+        // Only the pointer metadata changed, so this is the same allocation,
+        // and the reference the original Arc held moves to the new one.
+        let arc = ManuallyDrop::new(arc);
+        unsafe { Arc::from_data_ptr(coerced, ptr::read(&arc.alloc)) }
+    }
+}
+
+// Raw pointers don't carry the allocator, so they only round-trip Arcs using
+// the global one.
+impl<T: ?Sized> Arc<T> {
+    // Consumes the Arc without decrementing the count, returning a pointer to
+    // the data. The pointer must eventually be passed to from_raw (or
+    // decrement_strong_count), or the data leaks.
+    pub fn into_raw(arc: Self) -> *const T {
+        Self::as_ptr(&ManuallyDrop::new(arc))
+    }
+
+    /// Rebuilds an Arc from a pointer returned by into_raw, taking over the
+    /// reference that into_raw gave up.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have come from `Arc::<T>::into_raw`, and each call must be
+    /// balanced by an into_raw or increment_strong_count.
+    pub unsafe fn from_raw(ptr: *const T) -> Self {
+        Self::from_data_ptr(ptr, Global)
+    }
+
+    /// Adds a reference to the Arc behind a pointer from into_raw, as if it
+    /// had been cloned and the clone turned back into a raw pointer.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have come from `Arc::<T>::into_raw`, and the Arc must still
+    /// be alive.
+    pub unsafe fn increment_strong_count(ptr: *const T) {
+        let arc = ManuallyDrop::new(Self::from_raw(ptr));
+        mem::forget(Arc::clone(&arc));
+    }
+
+    /// Drops a reference to the Arc behind a pointer from into_raw, freeing
+    /// the data if it was the last one.
+    ///
+    /// # Safety
+    ///
+    /// As for from_raw, the reference being dropped must be one that into_raw
+    /// or increment_strong_count accounted for.
+    pub unsafe fn decrement_strong_count(ptr: *const T) {
+        drop(Self::from_raw(ptr));
+    }
+}
+
+impl<T> Arc<[T]> {
+    // Allocates an ArcData holding len elements, with the count set to one and
+    // the elements left uninitialised for the caller to fill in.
+    fn allocate_slice(len: usize) -> NonNull<ArcData<[T]>> {
+        // The same layout Drop will compute from the pointer when freeing it.
+        let (layout, _) = Layout::new::<AtomicUsize>()
+            .extend(Layout::array::<T>(len).unwrap())
+            .unwrap();
+        let layout = layout.pad_to_align();
+        unsafe {
+            let mem = alloc::alloc(layout);
+            if mem.is_null() {
+                alloc::handle_alloc_error(layout);
+            }
+            // The length becomes the slice metadata of the ArcData pointer.
+            let ptr = ptr::slice_from_raw_parts_mut(mem as *mut T, len) as *mut ArcData<[T]>;
+            ptr::addr_of_mut!((*ptr).ref_count).write(AtomicUsize::new(1));
+            #[cfg(feature = "leak_detect")]
+            crate::leak::track::<[T]>(ptr.cast());
+            NonNull::new_unchecked(ptr)
+        }
+    }
+}
+
+// The elements are moved straight into a single allocation after the count,
+// rather than going through Arc<Vec<T>> and its separate buffer.
+impl<T> From<Vec<T>> for Arc<[T]> {
+    fn from(mut vec: Vec<T>) -> Self {
+        let len = vec.len();
+        let ptr = Arc::allocate_slice(len);
+        unsafe {
+            let elements = ptr::addr_of_mut!((*ptr.as_ptr()).data) as *mut T;
+            ptr::copy_nonoverlapping(vec.as_ptr(), elements, len);
+            // The elements now belong to the Arc, so the Vec must only free
+            // its buffer.
+            vec.set_len(0);
+        }
+        Arc {
+            ptr,
+            alloc: Global,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T: Clone> From<&[T]> for Arc<[T]> {
+    fn from(slice: &[T]) -> Self {
+        // Cloning into a Vec first means a panicking clone can't leave a half
+        // initialised Arc behind.
+        Arc::from(slice.to_vec())
+    }
+}
+
+impl<T> FromIterator<T> for Arc<[T]> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        Arc::from(iter.into_iter().collect::<Vec<T>>())
+    }
+}
+
+// Shared text, with the bytes copied straight after the count. Arc<[u8]> from
+// a Vec<u8> is covered by From<Vec<T>> above.
+impl From<&str> for Arc<str> {
+    fn from(s: &str) -> Self {
+        let ptr = Arc::<[u8]>::allocate_slice(s.len());
+        unsafe {
+            let bytes = ptr::addr_of_mut!((*ptr.as_ptr()).data) as *mut u8;
+            ptr::copy_nonoverlapping(s.as_ptr(), bytes, s.len());
+            // str has the same layout and metadata as [u8], and the bytes came
+            // from a str so they are valid UTF-8.
+            Arc {
+                ptr: NonNull::new_unchecked(ptr.as_ptr() as *mut ArcData<str>),
+                alloc: Global,
+                _marker: PhantomData,
+            }
+        }
+    }
+}
+
+impl From<String> for Arc<str> {
+    fn from(s: String) -> Self {
+        Arc::from(s.as_str())
+    }
 }
 
 // Implement [`Deref`] so that the Arc transparently behaves like a reference to T.
 // We cannot implement DerefMut here because Arc is shared ownership, not exclusive
 // ownership. If we have DerefMut here, the structure could be altered by another
 // referenced Arc.
-impl<T> Deref for Arc<T> {
+impl<T: ?Sized, A: Allocator> Deref for Arc<T, A> {
     type Target = T;
     fn deref(&self) -> &Self::Target {
         &self.data().data
@@ -52,25 +470,382 @@ impl<T> Deref for Arc<T> {
 }
 
 // Clone provides the same data pointer, but we atomically increment the reference count.
-impl<T> Clone for Arc<T> {
+impl<T: ?Sized, A: Allocator + Clone> Clone for Arc<T, A> {
     fn clone(&self) -> Self {
-        if self.data().ref_count.fetch_add(1, Ordering::Relaxed) > usize::MAX / 2 {
-            std::process::abort();
+        let ref_count = &self.data().ref_count;
+        if ref_count.fetch_add(1, Ordering::Relaxed) > MAX_REF_COUNT {
+            overflowed(ref_count);
+        }
+        Self {
+            ptr: self.ptr,
+            alloc: self.alloc.clone(),
+            _marker: PhantomData,
         }
-        Self { ptr: self.ptr }
     }
 }
 
-impl<T> Drop for Arc<T> {
+impl<T: ?Sized, A: Allocator> Drop for Arc<T, A> {
     fn drop(&mut self) {
         if self.data().ref_count.fetch_sub(1, Ordering::Release) == 1 {
             fence(Ordering::Acquire);
-            // from_raw reclaims exclusive ownership so that we can drop the full
-            // structure. We can only do this knowing we have the final reference.
-            unsafe { drop(Box::from_raw(self.ptr.as_ptr())) }
+            // We have the final reference, so we can drop the data in place and
+            // hand the memory back to the allocator it came from.
+            unsafe {
+                let layout = Layout::for_value(self.ptr.as_ref());
+                ptr::drop_in_place(self.ptr.as_ptr());
+                #[cfg(feature = "leak_detect")]
+                crate::leak::untrack(self.ptr.as_ptr().cast());
+                self.alloc.deallocate(self.ptr.cast(), layout);
+            }
         }
     }
 }
 
-unsafe impl<T: Send + Sync> Send for Arc<T> {}
-unsafe impl<T: Send + Sync> Sync for Arc<T> {}
+// Moving an Arc only moves the pointer, never the data, so an Arc is Unpin
+// even when T isn't. Pinning the data takes Pin<Arc<T>>, see Arc::pin.
+impl<T: ?Sized, A: Allocator> Unpin for Arc<T, A> {}
+
+// Any clone may drop the last reference, so A is used from every thread which
+// holds one.
+unsafe impl<T: ?Sized + Send + Sync, A: Allocator + Send> Send for Arc<T, A> {}
+unsafe impl<T: ?Sized + Send + Sync, A: Allocator + Sync> Sync for Arc<T, A> {}
+
+// The remaining traits all forward to the data, so an Arc compares, hashes and
+// formats exactly like the value it points to, as std's Arc does.
+
+impl<T: ?Sized + fmt::Debug, A: Allocator> fmt::Debug for Arc<T, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized + fmt::Display, A: Allocator> fmt::Display for Arc<T, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized + PartialEq, A: Allocator> PartialEq for Arc<T, A> {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl<T: ?Sized + Eq, A: Allocator> Eq for Arc<T, A> {}
+
+impl<T: ?Sized + PartialOrd, A: Allocator> PartialOrd for Arc<T, A> {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        (**self).partial_cmp(&**other)
+    }
+}
+
+impl<T: ?Sized + Ord, A: Allocator> Ord for Arc<T, A> {
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        (**self).cmp(&**other)
+    }
+}
+
+impl<T: ?Sized + Hash, A: Allocator> Hash for Arc<T, A> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (**self).hash(state)
+    }
+}
+
+impl<T: Default> Default for Arc<T> {
+    fn default() -> Self {
+        Arc::new(T::default())
+    }
+}
+
+impl<T> From<T> for Arc<T> {
+    fn from(data: T) -> Self {
+        Arc::new(data)
+    }
+}
+
+// Borrow lets an Arc<T> key a map which is looked up by &T, which relies on
+// the Eq, Ord and Hash impls above matching T's own.
+impl<T: ?Sized, A: Allocator> Borrow<T> for Arc<T, A> {
+    fn borrow(&self) -> &T {
+        self
+    }
+}
+
+impl<T: ?Sized, A: Allocator> AsRef<T> for Arc<T, A> {
+    fn as_ref(&self) -> &T {
+        self
+    }
+}
+
+// Serializes as the data itself, so an Arc<T> field reads and writes the same
+// format as a plain T field. Sharing isn't preserved, deserializing gives
+// every field its own allocation.
+#[cfg(feature = "serde")]
+impl<T: ?Sized + serde::Serialize, A: Allocator> serde::Serialize for Arc<T, A> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        (**self).serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T: serde::Deserialize<'de>> serde::Deserialize<'de> for Arc<T> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        T::deserialize(deserializer).map(Arc::new)
+    }
+}
+
+// Run these under Miri too, which catches use-after-free, leaks and
+// misaligned accesses in the unsafe code above:
+//
+//     cargo +nightly miri test
+//
+// They use std's atomics, so they are left out of loom builds.
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::thread;
+
+    // Counts how many times it is dropped.
+    struct DetectDrop<'a>(&'a AtomicUsize);
+
+    impl Drop for DetectDrop<'_> {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn drops_once_after_last_clone() {
+        let drops = AtomicUsize::new(0);
+        let a = Arc::new(DetectDrop(&drops));
+        let b = a.clone();
+        assert_eq!(Arc::strong_count(&a), 2);
+        thread::scope(|s| {
+            s.spawn(move || drop(b));
+        });
+        assert_eq!(drops.load(Ordering::Relaxed), 0);
+        drop(a);
+        assert_eq!(drops.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn get_mut_only_when_unique() {
+        let mut a = Arc::new(1);
+        let b = a.clone();
+        assert!(Arc::get_mut(&mut a).is_none());
+        drop(b);
+        *Arc::get_mut(&mut a).unwrap() += 1;
+        assert_eq!(*a, 2);
+    }
+
+    #[test]
+    fn make_mut_copies_when_shared() {
+        let mut a = Arc::new(vec![1]);
+        let b = a.clone();
+        Arc::make_mut(&mut a).push(2);
+        assert_eq!(*a, [1, 2]);
+        assert_eq!(*b, [1]);
+        Arc::make_mut(&mut a).push(3);
+        assert_eq!(*a, [1, 2, 3]);
+    }
+
+    #[test]
+    fn try_unwrap_and_into_inner() {
+        let a = Arc::new(String::from("x"));
+        let b = a.clone();
+        let a = Arc::try_unwrap(a).unwrap_err();
+        assert_eq!(Arc::into_inner(b), None);
+        assert_eq!(Arc::try_unwrap(a).unwrap(), "x");
+        assert_eq!(Arc::into_inner(Arc::new(1)), Some(1));
+    }
+
+    #[test]
+    fn raw_round_trip() {
+        let drops = AtomicUsize::new(0);
+        let ptr = Arc::into_raw(Arc::new(DetectDrop(&drops)));
+        unsafe {
+            Arc::increment_strong_count(ptr);
+            Arc::decrement_strong_count(ptr);
+            assert_eq!(drops.load(Ordering::Relaxed), 0);
+            drop(Arc::from_raw(ptr));
+        }
+        assert_eq!(drops.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn raw_round_trip_over_aligned() {
+        #[repr(align(64))]
+        struct Aligned(u8);
+        let ptr = Arc::into_raw(Arc::new(Aligned(7)));
+        assert_eq!(ptr as usize % 64, 0);
+        let arc = unsafe { Arc::from_raw(ptr) };
+        assert_eq!(arc.0, 7);
+    }
+
+    #[test]
+    fn map_unsize_to_trait_object() {
+        let drops = AtomicUsize::new(0);
+        let arc = Arc::new((DetectDrop(&drops), 5));
+        let shown: Arc<dyn fmt::Debug> =
+            unsafe { Arc::map_unsize(Arc::new(5), |x| x as &dyn fmt::Debug) };
+        assert_eq!(format!("{shown:?}"), "5");
+        let clone = arc.clone();
+        let any: Arc<dyn Send + Sync> =
+            unsafe { Arc::map_unsize(arc, |x| x as &(dyn Send + Sync)) };
+        assert_eq!(Arc::strong_count(&any), 2);
+        drop(clone);
+        assert_eq!(drops.load(Ordering::Relaxed), 0);
+        drop(any);
+        assert_eq!(drops.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn map_unsize_to_slice() {
+        let drops = AtomicUsize::new(0);
+        let arc = Arc::new([DetectDrop(&drops), DetectDrop(&drops), DetectDrop(&drops)]);
+        let slice: Arc<[DetectDrop]> = unsafe { Arc::map_unsize(arc, |x| x as &[DetectDrop]) };
+        assert_eq!(slice.len(), 3);
+        drop(slice);
+        assert_eq!(drops.load(Ordering::Relaxed), 3);
+
+        let numbers: Arc<[u64]> =
+            unsafe { Arc::map_unsize(Arc::new([1u64, 2, 3]), |x| x as &[u64]) };
+        assert_eq!(*numbers, [1, 2, 3]);
+        let empty: Arc<[u64]> = unsafe { Arc::map_unsize(Arc::new([]), |x| x as &[u64]) };
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    #[should_panic]
+    fn map_unsize_rejects_fields() {
+        let arc = Arc::new((1u8, 2u32));
+        let _ = unsafe { Arc::map_unsize(arc, |x| &x.0) };
+    }
+
+    #[test]
+    fn pointer_identity() {
+        let a = Arc::new(1);
+        let b = a.clone();
+        let c = Arc::new(1);
+        assert!(Arc::ptr_eq(&a, &b));
+        assert!(!Arc::ptr_eq(&a, &c));
+        assert_eq!(Arc::as_ptr(&a), &*b as *const i32);
+        assert_eq!(Arc::as_ptr(&a), Arc::into_raw(b));
+        unsafe { Arc::decrement_strong_count(Arc::as_ptr(&a)) };
+    }
+
+    #[test]
+    fn slices() {
+        let drops = AtomicUsize::new(0);
+        let arc: Arc<[DetectDrop]> = (0..3).map(|_| DetectDrop(&drops)).collect();
+        assert_eq!(arc.len(), 3);
+        let empty: Arc<[String]> = Vec::new().into();
+        assert!(empty.is_empty());
+        let copied = Arc::<[u8]>::from(&b"abc"[..]);
+        assert_eq!(&*copied, b"abc");
+        drop(arc);
+        assert_eq!(drops.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn strings() {
+        let a: Arc<str> = Arc::from("hello");
+        let b: Arc<str> = Arc::from(String::from("hello"));
+        assert_eq!(a, b);
+        assert_eq!(&*a, "hello");
+        assert_eq!(&*Arc::<str>::from(""), "");
+        let bytes: Arc<[u8]> = Arc::from(b"hello".to_vec());
+        assert_eq!(&*bytes, a.as_bytes());
+    }
+
+    #[test]
+    fn covariant() {
+        fn shorten<'a>(arc: Arc<&'static str>) -> Arc<&'a str> {
+            arc
+        }
+        let s = String::from("short");
+        let mut arc = shorten(Arc::new("static"));
+        assert_eq!(*Arc::make_mut(&mut arc), "static");
+        *Arc::make_mut(&mut arc) = &s;
+        assert_eq!(*arc, "short");
+    }
+
+    #[test]
+    #[cfg(feature = "overflow_saturate")]
+    fn overflow_saturates() {
+        let arc = Arc::new(1);
+        arc.data().ref_count.store(MAX_REF_COUNT, Ordering::Relaxed);
+        let clones: Vec<_> = (0..3).map(|_| arc.clone()).collect();
+        drop(clones);
+        assert_eq!(Arc::strong_count(&arc), SATURATED_REF_COUNT - 3);
+        // Put the count back so the test doesn't leak, for Miri's sake.
+        arc.data().ref_count.store(1, Ordering::Relaxed);
+    }
+
+    #[test]
+    #[cfg(all(feature = "overflow_panic", not(feature = "overflow_saturate")))]
+    fn overflow_panics() {
+        let arc = Arc::new(1);
+        arc.data()
+            .ref_count
+            .store(MAX_REF_COUNT + 1, Ordering::Relaxed);
+        let result = std::panic::catch_unwind(|| arc.clone());
+        assert!(result.is_err());
+        assert_eq!(Arc::strong_count(&arc), MAX_REF_COUNT + 1);
+        arc.data().ref_count.store(1, Ordering::Relaxed);
+    }
+
+    #[test]
+    fn zero_sized() {
+        let a = Arc::new(());
+        let b = a.clone();
+        drop(a);
+        assert_eq!(Arc::try_unwrap(b), Ok(()));
+    }
+
+    #[test]
+    fn new_in_returns_memory_to_allocator() {
+        #[derive(Default)]
+        struct Counting(AtomicUsize);
+
+        unsafe impl Allocator for Counting {
+            fn allocate(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+                self.0.fetch_add(1, Ordering::Relaxed);
+                Global.allocate(layout)
+            }
+
+            unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+                self.0.fetch_sub(1, Ordering::Relaxed);
+                Global.deallocate(ptr, layout)
+            }
+        }
+
+        let alloc = Counting::default();
+        let mut a = Arc::new_in(String::from("a"), &alloc);
+        let b = a.clone();
+        Arc::make_mut(&mut a).push('b');
+        assert_eq!(alloc.0.load(Ordering::Relaxed), 2);
+        assert_eq!(Arc::into_inner(b).as_deref(), Some("a"));
+        drop(a);
+        assert_eq!(alloc.0.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn shared_between_threads() {
+        let drops = AtomicUsize::new(0);
+        let arc = Arc::new(DetectDrop(&drops));
+        thread::scope(|s| {
+            for _ in 0..4 {
+                let arc = arc.clone();
+                s.spawn(move || {
+                    let clones: Vec<_> = (0..10).map(|_| arc.clone()).collect();
+                    drop(clones);
+                });
+            }
+        });
+        assert_eq!(Arc::strong_count(&arc), 1);
+        drop(arc);
+        assert_eq!(drops.load(Ordering::Relaxed), 1);
+    }
+}
@@ -0,0 +1,219 @@
+use std::alloc::{self, Layout};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::ptr::{self, NonNull};
+use std::sync::atomic::{fence, AtomicUsize, Ordering};
+
+use crate::simple_arc::{overflowed, MAX_REF_COUNT};
+
+// Sits at the start of every ThinArc allocation, followed by the elements.
+#[repr(C)]
+struct Header {
+    ref_count: AtomicUsize,
+    len: usize,
+}
+
+// An Arc<[T]> squeezed into a single pointer.
+//
+// A pointer to a slice is two words, the address and the length, so Arc<[T]>
+// is twice the size of Arc<T>. ThinArc keeps the length in the allocation next
+// to the count instead, so the handle is one word. This adds up when storing
+// millions of them, at the cost of reading the header to find the length.
+pub struct ThinArc<T> {
+    ptr: NonNull<Header>,
+    // The ThinArc owns the elements, for drop check and auto traits.
+    _marker: PhantomData<[T]>,
+}
+
+// The layout of an allocation holding len elements, and the offset of the
+// first element from the start.
+fn layout<T>(len: usize) -> (Layout, usize) {
+    let (layout, offset) = Layout::new::<Header>()
+        .extend(Layout::array::<T>(len).unwrap())
+        .unwrap();
+    (layout.pad_to_align(), offset)
+}
+
+impl<T> ThinArc<T> {
+    fn header(&self) -> &Header {
+        unsafe { self.ptr.as_ref() }
+    }
+
+    fn elements(&self) -> *mut T {
+        let (_, offset) = layout::<T>(self.header().len);
+        unsafe { self.ptr.as_ptr().byte_add(offset) as *mut T }
+    }
+
+    // As Arc::strong_count, only a snapshot.
+    pub fn strong_count(arc: &Self) -> usize {
+        arc.header().ref_count.load(Ordering::Relaxed)
+    }
+}
+
+impl<T> From<Vec<T>> for ThinArc<T> {
+    fn from(mut vec: Vec<T>) -> Self {
+        let len = vec.len();
+        let (layout, offset) = layout::<T>(len);
+        unsafe {
+            let mem = alloc::alloc(layout);
+            if mem.is_null() {
+                alloc::handle_alloc_error(layout);
+            }
+            (mem as *mut Header).write(Header {
+                ref_count: AtomicUsize::new(1),
+                len,
+            });
+            ptr::copy_nonoverlapping(vec.as_ptr(), mem.add(offset) as *mut T, len);
+            // The elements now belong to the ThinArc, so the Vec must only
+            // free its buffer.
+            vec.set_len(0);
+            Self {
+                ptr: NonNull::new_unchecked(mem as *mut Header),
+                _marker: PhantomData,
+            }
+        }
+    }
+}
+
+impl<T: Clone> From<&[T]> for ThinArc<T> {
+    fn from(slice: &[T]) -> Self {
+        Self::from(slice.to_vec())
+    }
+}
+
+impl<T> FromIterator<T> for ThinArc<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        Self::from(iter.into_iter().collect::<Vec<T>>())
+    }
+}
+
+impl<T> Deref for ThinArc<T> {
+    type Target = [T];
+    fn deref(&self) -> &[T] {
+        unsafe { &*ptr::slice_from_raw_parts(self.elements(), self.header().len) }
+    }
+}
+
+// As for Arc, cloning only increments the count.
+impl<T> Clone for ThinArc<T> {
+    fn clone(&self) -> Self {
+        let ref_count = &self.header().ref_count;
+        if ref_count.fetch_add(1, Ordering::Relaxed) > MAX_REF_COUNT {
+            overflowed(ref_count);
+        }
+        Self {
+            ptr: self.ptr,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T> Drop for ThinArc<T> {
+    fn drop(&mut self) {
+        if self.header().ref_count.fetch_sub(1, Ordering::Release) == 1 {
+            fence(Ordering::Acquire);
+            // We had the final reference, so nothing else can see the elements
+            // while they are dropped and the allocation freed.
+            unsafe {
+                let len = self.header().len;
+                ptr::drop_in_place(ptr::slice_from_raw_parts_mut(self.elements(), len));
+                alloc::dealloc(self.ptr.as_ptr() as *mut u8, layout::<T>(len).0);
+            }
+        }
+    }
+}
+
+unsafe impl<T: Send + Sync> Send for ThinArc<T> {}
+unsafe impl<T: Send + Sync> Sync for ThinArc<T> {}
+
+impl<T: fmt::Debug> fmt::Debug for ThinArc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: PartialEq> PartialEq for ThinArc<T> {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl<T: Eq> Eq for ThinArc<T> {}
+
+impl<T: Hash> Hash for ThinArc<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (**self).hash(state)
+    }
+}
+
+// A shared string in a single pointer, built on ThinArc<u8>.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct ThinStr(ThinArc<u8>);
+
+impl From<&str> for ThinStr {
+    fn from(s: &str) -> Self {
+        Self(ThinArc::from(s.as_bytes()))
+    }
+}
+
+impl From<String> for ThinStr {
+    fn from(s: String) -> Self {
+        Self(ThinArc::from(s.into_bytes()))
+    }
+}
+
+impl Deref for ThinStr {
+    type Target = str;
+    fn deref(&self) -> &str {
+        // The bytes were copied from a str or String, so they are valid UTF-8.
+        unsafe { std::str::from_utf8_unchecked(&self.0) }
+    }
+}
+
+impl fmt::Debug for ThinStr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl fmt::Display for ThinStr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
+
+// As for Arc, only the non-aborting ways of handling overflow can be tested.
+#[cfg(all(test, any(feature = "overflow_saturate", feature = "overflow_panic")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(feature = "overflow_saturate")]
+    fn overflow_saturates() {
+        use crate::simple_arc::SATURATED_REF_COUNT;
+
+        let arc = ThinArc::from(vec![1, 2]);
+        arc.header()
+            .ref_count
+            .store(MAX_REF_COUNT + 1, Ordering::Relaxed);
+        let clone = arc.clone();
+        drop(clone);
+        assert_eq!(ThinArc::strong_count(&arc), SATURATED_REF_COUNT - 1);
+        arc.header().ref_count.store(1, Ordering::Relaxed);
+    }
+
+    #[test]
+    #[cfg(all(feature = "overflow_panic", not(feature = "overflow_saturate")))]
+    fn overflow_panics() {
+        let arc = ThinArc::from(vec![1, 2]);
+        arc.header()
+            .ref_count
+            .store(MAX_REF_COUNT + 1, Ordering::Relaxed);
+        let result = std::panic::catch_unwind(|| arc.clone());
+        assert!(result.is_err());
+        assert_eq!(ThinArc::strong_count(&arc), MAX_REF_COUNT + 1);
+        arc.header().ref_count.store(1, Ordering::Relaxed);
+    }
+}
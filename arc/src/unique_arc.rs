@@ -0,0 +1,74 @@
+use std::mem::MaybeUninit;
+use std::ops::{Deref, DerefMut};
+
+use crate::simple_arc::Arc;
+
+// An Arc which is known to be the only reference to its data, so it can hand
+// out &mut T freely, until it is turned into a regular Arc to share.
+//
+// This makes building shared data in stages straightforward: mutate it
+// through the UniqueArc with no count checks or cloning, then freeze it with
+// into_arc once it is ready. It can also be allocated uninitialised and filled
+// in place, which avoids building a large value on the stack first.
+pub struct UniqueArc<T: ?Sized>(Arc<T>);
+
+impl<T> UniqueArc<T> {
+    pub fn new(data: T) -> Self {
+        Self(Arc::new(data))
+    }
+
+    // Allocates space for a T without initialising it, see write.
+    pub fn new_uninit() -> UniqueArc<MaybeUninit<T>> {
+        UniqueArc(Arc::new(MaybeUninit::uninit()))
+    }
+}
+
+impl<T> UniqueArc<MaybeUninit<T>> {
+    // Initialises the data in place.
+    pub fn write(mut this: Self, data: T) -> UniqueArc<T> {
+        (*this).write(data);
+        unsafe { Self::assume_init(this) }
+    }
+
+    /// Converts to a `UniqueArc<T>` once the data has been initialised, for
+    /// example field by field through raw pointers.
+    ///
+    /// # Safety
+    ///
+    /// The data must be fully initialised.
+    pub unsafe fn assume_init(this: Self) -> UniqueArc<T> {
+        // MaybeUninit<T> has the same layout as T, so this is the same
+        // allocation with the same reference, just retyped.
+        let ptr = Arc::into_raw(this.0) as *const T;
+        UniqueArc(Arc::from_raw(ptr))
+    }
+}
+
+impl<T: ?Sized> UniqueArc<T> {
+    // Gives up uniqueness, turning this into a regular Arc which can be
+    // cloned and shared.
+    pub fn into_arc(this: Self) -> Arc<T> {
+        this.0
+    }
+}
+
+impl<T: ?Sized> From<UniqueArc<T>> for Arc<T> {
+    fn from(unique: UniqueArc<T>) -> Self {
+        UniqueArc::into_arc(unique)
+    }
+}
+
+impl<T: ?Sized> Deref for UniqueArc<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+// Unlike Arc, a UniqueArc can implement DerefMut, as the inner Arc is never
+// cloned while it is wrapped.
+impl<T: ?Sized> DerefMut for UniqueArc<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { Arc::get_mut_unchecked(&mut self.0) }
+    }
+}
@@ -0,0 +1,160 @@
+// Checks the atomic orderings in Arc and AtomicArc by running each test under
+// every interleaving and reordering loom can find. Only built with loom's
+// atomics swapped in:
+//
+//     RUSTFLAGS="--cfg loom" cargo test --release --test loom
+#![cfg(loom)]
+
+use arc::atomic_arc::AtomicArc;
+use arc::simple_arc::Arc;
+use loom::cell::UnsafeCell;
+use loom::thread;
+
+// Data which is read through shared references and written when dropped or
+// through get_mut. loom reports a data race unless every read happens before
+// the write, which is exactly what the Release decrements and Acquire fences
+// in Arc are there to guarantee.
+struct Data(UnsafeCell<usize>);
+
+// The cell is only written with exclusive access, see above.
+unsafe impl Sync for Data {}
+
+impl Data {
+    fn new(value: usize) -> Self {
+        Self(UnsafeCell::new(value))
+    }
+
+    fn get(&self) -> usize {
+        self.0.with(|value| unsafe { *value })
+    }
+
+    fn set(&mut self, value: usize) {
+        self.0.with_mut(|ptr| unsafe { *ptr = value })
+    }
+}
+
+impl Clone for Data {
+    fn clone(&self) -> Self {
+        Self::new(self.get())
+    }
+}
+
+impl Drop for Data {
+    fn drop(&mut self) {
+        self.set(0);
+    }
+}
+
+#[test]
+fn clone_and_drop() {
+    loom::model(|| {
+        let arc = Arc::new(Data::new(1));
+        let threads: Vec<_> = (0..2)
+            .map(|_| {
+                let arc = arc.clone();
+                thread::spawn(move || assert_eq!(arc.get(), 1))
+            })
+            .collect();
+        drop(arc);
+        for thread in threads {
+            thread.join().unwrap();
+        }
+    });
+}
+
+#[test]
+fn get_mut_after_other_drop() {
+    loom::model(|| {
+        let mut arc = Arc::new(Data::new(1));
+        let other = arc.clone();
+        let thread = thread::spawn(move || assert_eq!(other.get(), 1));
+        // Succeeds only once the other thread has dropped its clone, and its
+        // read must then be visible as having happened first.
+        if let Some(data) = Arc::get_mut(&mut arc) {
+            data.set(2);
+        }
+        thread.join().unwrap();
+    });
+}
+
+#[test]
+fn make_mut_while_shared() {
+    loom::model(|| {
+        let mut arc = Arc::new(Data::new(1));
+        let other = arc.clone();
+        let thread = thread::spawn(move || assert_eq!(other.get(), 1));
+        // Either copies the data, or writes in place if the clone has gone.
+        Arc::make_mut(&mut arc).set(2);
+        assert_eq!(arc.get(), 2);
+        thread.join().unwrap();
+    });
+}
+
+#[test]
+fn into_inner_exactly_once() {
+    loom::model(|| {
+        let arc = Arc::new(Data::new(1));
+        let other = arc.clone();
+        let thread = thread::spawn(move || Arc::into_inner(other).map(|data| data.get()));
+        let ours = Arc::into_inner(arc).map(|data| data.get());
+        let theirs = thread.join().unwrap();
+        assert!(ours.is_some() != theirs.is_some());
+        assert_eq!(ours.or(theirs), Some(1));
+    });
+}
+
+#[test]
+fn try_unwrap_after_other_drop() {
+    loom::model(|| {
+        let arc = Arc::new(Data::new(1));
+        let other = arc.clone();
+        let thread = thread::spawn(move || assert_eq!(other.get(), 1));
+        if let Ok(mut data) = Arc::try_unwrap(arc) {
+            data.set(2);
+        }
+        thread.join().unwrap();
+    });
+}
+
+#[test]
+fn atomic_arc_load_during_store() {
+    loom::model(|| {
+        let cell = Arc::new(AtomicArc::new(Arc::new(Data::new(1))));
+        let reader = {
+            let cell = cell.clone();
+            // The store may drop the first Arc at any point, which loom reports
+            // as a race unless the load has its own reference by then.
+            thread::spawn(move || {
+                let value = cell.load().get();
+                assert!(value == 1 || value == 2);
+            })
+        };
+        let second = Arc::new(Data::new(2));
+        cell.store(second.clone());
+        reader.join().unwrap();
+        // Held by the cell and by us, the reader's clone having been dropped.
+        assert_eq!(Arc::strong_count(&second), 2);
+        assert_eq!(cell.load().get(), 2);
+    });
+}
+
+#[test]
+fn atomic_arc_swap_during_load() {
+    loom::model(|| {
+        let first = Arc::new(Data::new(1));
+        let cell = Arc::new(AtomicArc::new(first.clone()));
+        let reader = {
+            let cell = cell.clone();
+            thread::spawn(move || {
+                let loaded = cell.load();
+                assert!(loaded.get() == 1 || loaded.get() == 2);
+            })
+        };
+        let old = cell.swap(Arc::new(Data::new(2)));
+        assert!(Arc::ptr_eq(&old, &first));
+        reader.join().unwrap();
+        // Every reference the load took has been given back.
+        drop(old);
+        assert_eq!(Arc::strong_count(&first), 1);
+    });
+}
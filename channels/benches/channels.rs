@@ -0,0 +1,138 @@
+use std::{
+    hint::black_box,
+    thread,
+    time::{Duration, Instant},
+};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+// Capacity of every channel, small enough that producers regularly fill it
+// and have to block.
+const CAPACITY: usize = 64;
+// Number of messages shared between all producers in a contended run.
+const CONTENDED_MESSAGES: u64 = 100_000;
+
+/// Common interface over the channels being compared, so each benchmark is
+/// written once.
+trait Channel {
+    type Sender: Clone + Send;
+    type Receiver: Send;
+    fn bounded(capacity: usize) -> (Self::Sender, Self::Receiver);
+    fn send(sender: &Self::Sender, message: u64);
+    fn receive(receiver: &Self::Receiver) -> Option<u64>;
+}
+
+struct Simple;
+
+impl Channel for Simple {
+    type Sender = channels::simple::Sender<u64>;
+    type Receiver = channels::simple::Receiver<u64>;
+    fn bounded(capacity: usize) -> (Self::Sender, Self::Receiver) {
+        channels::simple::channel_with_capacity(capacity)
+    }
+    fn send(sender: &Self::Sender, message: u64) {
+        sender.send(message).unwrap();
+    }
+    fn receive(receiver: &Self::Receiver) -> Option<u64> {
+        receiver.receive().ok()
+    }
+}
+
+struct Futex;
+
+impl Channel for Futex {
+    type Sender = channels::futex::Sender<u64>;
+    type Receiver = channels::futex::Receiver<u64>;
+    fn bounded(capacity: usize) -> (Self::Sender, Self::Receiver) {
+        channels::futex::channel(capacity)
+    }
+    fn send(sender: &Self::Sender, message: u64) {
+        sender.send(message).unwrap();
+    }
+    fn receive(receiver: &Self::Receiver) -> Option<u64> {
+        receiver.receive().ok()
+    }
+}
+
+struct Std;
+
+impl Channel for Std {
+    type Sender = std::sync::mpsc::SyncSender<u64>;
+    type Receiver = std::sync::mpsc::Receiver<u64>;
+    fn bounded(capacity: usize) -> (Self::Sender, Self::Receiver) {
+        std::sync::mpsc::sync_channel(capacity)
+    }
+    fn send(sender: &Self::Sender, message: u64) {
+        sender.send(message).unwrap();
+    }
+    fn receive(receiver: &Self::Receiver) -> Option<u64> {
+        receiver.recv().ok()
+    }
+}
+
+// A send straight followed by a receive on one thread, so never blocking.
+fn uncontended<C: Channel>(c: &mut Criterion, name: &str) {
+    let (sender, receiver) = C::bounded(CAPACITY);
+    c.bench_function(&format!("uncontended/{name}"), |b| {
+        b.iter(|| {
+            C::send(&sender, black_box(1));
+            black_box(C::receive(&receiver))
+        })
+    });
+}
+
+// Several producers sending to one consumer, which drains until they are all
+// done.
+fn contended<C: Channel>(c: &mut Criterion, name: &str) {
+    let mut group = c.benchmark_group(format!("contended/{name}"));
+    group.throughput(Throughput::Elements(CONTENDED_MESSAGES));
+    for producers in [1, 2, 4, 8] {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(producers),
+            &producers,
+            |b, &producers| {
+                b.iter_custom(|iters| {
+                    let mut total = Duration::ZERO;
+                    for _ in 0..iters {
+                        let (sender, receiver) = C::bounded(CAPACITY);
+                        let start = Instant::now();
+                        thread::scope(|s| {
+                            for _ in 0..producers {
+                                let sender = sender.clone();
+                                s.spawn(move || {
+                                    for i in 0..CONTENDED_MESSAGES / producers {
+                                        C::send(&sender, i);
+                                    }
+                                });
+                            }
+                            // The receiver finishes once the last clone is
+                            // dropped by its producer.
+                            drop(sender);
+                            while let Some(message) = C::receive(&receiver) {
+                                black_box(message);
+                            }
+                        });
+                        total += start.elapsed();
+                    }
+                    total
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+fn bench_uncontended(c: &mut Criterion) {
+    uncontended::<Simple>(c, "simple");
+    uncontended::<Futex>(c, "futex");
+    uncontended::<Std>(c, "std");
+}
+
+fn bench_contended(c: &mut Criterion) {
+    contended::<Simple>(c, "simple");
+    contended::<Futex>(c, "futex");
+    contended::<Std>(c, "std");
+}
+
+criterion_group!(benches, bench_uncontended, bench_contended);
+criterion_main!(benches);
@@ -0,0 +1,173 @@
+use std::{
+    cell::UnsafeCell,
+    mem::MaybeUninit,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use spinlock::futex::{wait, wake_one};
+
+use crate::safe_oneshot::RecvError;
+
+// The same states as the `safe_oneshot` channel.
+const EMPTY: u32 = 0;
+const READY: u32 = 1;
+const DISCONNECTED: u32 = 2;
+
+/// A oneshot channel which lives in the caller's stack frame rather than in an
+/// `Arc`, so creating one doesn't allocate.
+///
+/// Instead of sharing ownership, the [`Sender`] and [`Receiver`] borrow the
+/// channel, and the borrow checker ensures it outlives them both. Once they
+/// are gone the channel can be split again, so a request/response loop can
+/// reuse a single channel, handing the sender to a scoped worker thread and
+/// waiting on the receiver each time around.
+pub struct Channel<T> {
+    message: UnsafeCell<MaybeUninit<T>>,
+    state: AtomicU32,
+}
+
+// As for the `safe_oneshot` channel, only one side touches the message at a
+// time.
+unsafe impl<T> Sync for Channel<T> where T: Send {}
+
+impl<T> Channel<T> {
+    pub const fn new() -> Self {
+        Self {
+            message: UnsafeCell::new(MaybeUninit::uninit()),
+            state: AtomicU32::new(EMPTY),
+        }
+    }
+
+    /// Split the channel into a [`Sender`] and [`Receiver`] borrowing it.
+    ///
+    /// Taking `&mut self` means this can't be called again while either half
+    /// is still alive. Each call starts a fresh channel, dropping any message
+    /// left over from the last one.
+    pub fn split(&mut self) -> (Sender<'_, T>, Receiver<'_, T>) {
+        *self = Self::new();
+        (Sender { channel: self }, Receiver { channel: self })
+    }
+}
+
+impl<T> Default for Channel<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Drop for Channel<T> {
+    fn drop(&mut self) {
+        if *self.state.get_mut() == READY {
+            unsafe { self.message.get_mut().assume_init_drop() }
+        }
+    }
+}
+
+pub struct Sender<'a, T> {
+    channel: &'a Channel<T>,
+}
+
+impl<T> Sender<'_, T> {
+    pub fn send(self, message: T) {
+        unsafe { (*self.channel.message.get()).write(message) };
+        self.channel.state.store(READY, Ordering::Release);
+        wake_one(&self.channel.state);
+    }
+}
+
+impl<T> Drop for Sender<'_, T> {
+    fn drop(&mut self) {
+        // Tell the receiver if nothing was sent, see `safe_oneshot::Sender`.
+        if self
+            .channel
+            .state
+            .compare_exchange(EMPTY, DISCONNECTED, Ordering::Relaxed, Ordering::Relaxed)
+            .is_ok()
+        {
+            wake_one(&self.channel.state);
+        }
+    }
+}
+
+pub struct Receiver<'a, T> {
+    channel: &'a Channel<T>,
+}
+
+impl<T> Receiver<'_, T> {
+    pub fn is_ready(&self) -> bool {
+        self.channel.state.load(Ordering::Relaxed) == READY
+    }
+
+    /// Block until the message has been sent, then receive it, or return
+    /// [`RecvError::Disconnected`] if the `Sender` was dropped without sending.
+    pub fn recv(self) -> Result<T, RecvError> {
+        loop {
+            match self.channel.state.compare_exchange(
+                READY,
+                DISCONNECTED,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return Ok(unsafe { (*self.channel.message.get()).assume_init_read() }),
+                Err(EMPTY) => wait(&self.channel.state, EMPTY),
+                Err(_) => return Err(RecvError::Disconnected),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::atomic::AtomicUsize, thread, time::Duration};
+
+    // Counts how many times it is dropped.
+    struct DetectDrop<'a>(&'a AtomicUsize);
+
+    impl Drop for DetectDrop<'_> {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn request_response_rounds_reuse_the_channel() {
+        let mut channel = Channel::new();
+        for request in 0..3 {
+            thread::scope(|s| {
+                let (sender, receiver) = channel.split();
+                s.spawn(move || sender.send(request * 2));
+                assert_eq!(receiver.recv(), Ok(request * 2));
+            });
+        }
+    }
+
+    #[test]
+    fn split_drops_unreceived_message() {
+        let drops = AtomicUsize::new(0);
+        let mut channel = Channel::new();
+        let (sender, receiver) = channel.split();
+        sender.send(DetectDrop(&drops));
+        assert!(receiver.is_ready());
+        assert_eq!(drops.load(Ordering::Relaxed), 0);
+
+        let (_, receiver) = channel.split();
+        assert_eq!(drops.load(Ordering::Relaxed), 1);
+        assert!(!receiver.is_ready());
+        drop(channel);
+        assert_eq!(drops.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn dropped_sender_disconnects() {
+        let mut channel = Channel::<i32>::new();
+        thread::scope(|s| {
+            let (sender, receiver) = channel.split();
+            s.spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                drop(sender);
+            });
+            assert_eq!(receiver.recv(), Err(RecvError::Disconnected));
+        });
+    }
+}
@@ -0,0 +1,470 @@
+use std::{
+    cell::UnsafeCell,
+    mem::MaybeUninit,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use spinlock::futex::{wait, wake_all, wake_one};
+
+use crate::simple::{RecvError, SendError, TrySendError};
+
+/// Create a bounded channel which holds at most `capacity` messages, as a
+/// [`Sender`] and [`Receiver`] pair.
+///
+/// This does the same job as [`crate::simple::channel_with_capacity`], but
+/// without a `Mutex<VecDeque>` and `Condvar`. Messages go through a fixed ring
+/// of slots, in the style of Dmitry Vyukov's bounded queue, claimed with a
+/// compare-and-swap on a position counter, so senders and receivers never
+/// take a lock. Blocking is done by waiting on an `AtomicU32` with a futex, as
+/// the locks in the spinlock crate do, and a thread only makes the wake system
+/// call when someone is actually asleep. When the channel is neither full nor
+/// empty, a message goes through without any system calls at all.
+///
+/// Both halves can be cloned. Receivers claim positions the same way senders
+/// do, so each message goes to exactly one of them, and every push wakes at
+/// most one sleeping receiver, as only one can take the message.
+///
+/// Panics:
+/// If `capacity` is zero, as no message could ever be sent
+pub fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(
+        capacity > 0,
+        "a futex channel needs a capacity of at least one"
+    );
+    let slots = (0..capacity)
+        .map(|i| Slot {
+            stamp: AtomicUsize::new(2 * i),
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+        })
+        .collect();
+    let shared = Arc::new(Shared {
+        slots,
+        tail: AtomicUsize::new(0),
+        head: AtomicUsize::new(0),
+        pushed: AtomicU32::new(0),
+        popped: AtomicU32::new(0),
+        sleeping_receivers: AtomicU32::new(0),
+        sleeping_senders: AtomicU32::new(0),
+        closed: AtomicBool::new(false),
+        senders: AtomicUsize::new(1),
+        receivers: AtomicUsize::new(1),
+    });
+    (
+        Sender {
+            shared: Arc::clone(&shared),
+        },
+        Receiver { shared },
+    )
+}
+
+struct Slot<T> {
+    // Twice the position of the next push which may write to this slot, plus
+    // one once it holds a message, so the pop at that position may read it.
+    // Doubling keeps the two apart even for a capacity of one, where the
+    // next push is at the position straight after.
+    stamp: AtomicUsize,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+struct Shared<T> {
+    slots: Box<[Slot<T>]>,
+    // The positions of the next push and the next pop. The slot for a position
+    // is at `position % capacity`.
+    tail: AtomicUsize,
+    head: AtomicUsize,
+    // Bumped after every push and pop, and on close, these are what blocked
+    // receivers and senders wait on. Only their changing matters, so they can
+    // wrap around.
+    pushed: AtomicU32,
+    popped: AtomicU32,
+    // How many threads are, or are about to be, asleep on each of the above,
+    // so that the other side can skip waking when nobody is.
+    sleeping_receivers: AtomicU32,
+    sleeping_senders: AtomicU32,
+    closed: AtomicBool,
+    senders: AtomicUsize,
+    receivers: AtomicUsize,
+}
+
+// Messages are moved between threads, but never shared, so `T: Send` is enough.
+unsafe impl<T: Send> Send for Shared<T> {}
+unsafe impl<T: Send> Sync for Shared<T> {}
+
+impl<T> Shared<T> {
+    fn try_push(&self, message: T) -> Result<(), T> {
+        let capacity = self.slots.len();
+        let mut tail = self.tail.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.slots[tail % capacity];
+            // Acquire pairs with the Release in `try_pop`, so the message the
+            // slot held before has been moved out before we overwrite it.
+            let stamp = slot.stamp.load(Ordering::Acquire);
+            if stamp == 2 * tail {
+                // The slot is free, claim this position.
+                match self.tail.compare_exchange_weak(
+                    tail,
+                    tail + 1,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        unsafe { (*slot.value.get()).write(message) };
+                        slot.stamp.store(2 * tail + 1, Ordering::Release);
+                        return Ok(());
+                    }
+                    Err(current) => tail = current,
+                }
+            } else if stamp < 2 * tail {
+                // The slot still holds the message from a lap ago, so the ring
+                // is full.
+                return Err(message);
+            } else {
+                // Another sender claimed this position, try the next one.
+                tail = self.tail.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn try_pop(&self) -> Option<T> {
+        let capacity = self.slots.len();
+        let mut head = self.head.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.slots[head % capacity];
+            // Acquire pairs with the Release in `try_push`, making the message
+            // visible.
+            let stamp = slot.stamp.load(Ordering::Acquire);
+            if stamp == 2 * head + 1 {
+                match self.head.compare_exchange_weak(
+                    head,
+                    head + 1,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        let message = unsafe { (*slot.value.get()).assume_init_read() };
+                        // Free the slot for the push one lap ahead.
+                        slot.stamp.store(2 * (head + capacity), Ordering::Release);
+                        return Some(message);
+                    }
+                    Err(current) => head = current,
+                }
+            } else if stamp <= 2 * head {
+                // Nothing has been pushed at this position yet, so the ring is
+                // empty.
+                return None;
+            } else {
+                head = self.head.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let slot = &self.slots[tail % self.slots.len()];
+        slot.stamp.load(Ordering::Acquire) < 2 * tail
+    }
+
+    fn is_empty(&self) -> bool {
+        let head = self.head.load(Ordering::Relaxed);
+        let slot = &self.slots[head % self.slots.len()];
+        slot.stamp.load(Ordering::Acquire) <= 2 * head
+    }
+
+    // A push claims its position before writing the message, so `tail` can
+    // briefly count one which isn't there yet.
+    fn len(&self) -> usize {
+        let head = self.head.load(Ordering::Relaxed);
+        let tail = self.tail.load(Ordering::Relaxed);
+        tail.saturating_sub(head).min(self.slots.len())
+    }
+
+    fn close(&self) {
+        // Release pairs with the Acquire load in `Receiver::receive`, so once
+        // it sees the channel closed, it also sees every message pushed before.
+        self.closed.store(true, Ordering::Release);
+        // Bumping the counters after setting the flag means any thread which
+        // read them before will not sleep, and any which reads them after will
+        // see the flag.
+        self.pushed.fetch_add(1, Ordering::SeqCst);
+        self.popped.fetch_add(1, Ordering::SeqCst);
+        wake_all(&self.pushed);
+        wake_all(&self.popped);
+    }
+}
+
+// Lets anyone asleep on `counter` know it has changed. The SeqCst
+// increment and load pair with those in `sleep`, so that either the
+// sleeper sees what we did before calling this, or we see the sleeper.
+fn notify(counter: &AtomicU32, sleeping: &AtomicU32) {
+    counter.fetch_add(1, Ordering::SeqCst);
+    if sleeping.load(Ordering::SeqCst) > 0 {
+        wake_one(counter);
+    }
+}
+
+// Blocks until `counter` changes, unless `ready` finds what we were
+// waiting for in the meantime.
+fn sleep(counter: &AtomicU32, sleeping: &AtomicU32, ready: impl FnOnce() -> bool) {
+    sleeping.fetch_add(1, Ordering::SeqCst);
+    let value = counter.load(Ordering::SeqCst);
+    if !ready() {
+        wait(counter, value);
+    }
+    sleeping.fetch_sub(1, Ordering::Relaxed);
+}
+
+impl<T> Drop for Shared<T> {
+    fn drop(&mut self) {
+        // Drop any messages which were sent but never received.
+        while self.try_pop().is_some() {}
+    }
+}
+
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Send a message, blocking while the channel is full. Returns the
+    /// message back as an error if the channel is closed, including while
+    /// waiting for room.
+    pub fn send(&self, mut message: T) -> Result<(), SendError<T>> {
+        let shared = &*self.shared;
+        loop {
+            if shared.closed.load(Ordering::Relaxed) {
+                return Err(SendError(message, false));
+            }
+            match shared.try_push(message) {
+                Ok(()) => {
+                    notify(&shared.pushed, &shared.sleeping_receivers);
+                    return Ok(());
+                }
+                Err(m) => message = m,
+            }
+            sleep(&shared.popped, &shared.sleeping_senders, || {
+                shared.closed.load(Ordering::Relaxed) || !shared.is_full()
+            });
+        }
+    }
+
+    /// Like `send`, but rather than blocking when the channel is full, hands
+    /// the message straight back.
+    pub fn try_send(&self, message: T) -> Result<(), TrySendError<T>> {
+        let shared = &*self.shared;
+        if shared.closed.load(Ordering::Relaxed) {
+            return Err(TrySendError::Disconnected(message));
+        }
+        shared.try_push(message).map_err(TrySendError::Full)?;
+        notify(&shared.pushed, &shared.sleeping_receivers);
+        Ok(())
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.shared.closed.load(Ordering::Relaxed)
+    }
+
+    /// The number of messages in the channel right now. As with
+    /// [`crate::simple::SimpleChannel::len`], this is only a snapshot.
+    pub fn len(&self) -> usize {
+        self.shared.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shared.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.shared.slots.len()
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::Relaxed);
+        Self {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        // AcqRel, so the last sender to go, which closes the channel, has
+        // seen the messages pushed by every other sender.
+        if self.shared.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.shared.close();
+        }
+    }
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Receiver<T> {
+    /// Block until a message arrives, or return [`RecvError::Disconnected`]
+    /// once every `Sender` has been dropped and the channel is drained.
+    pub fn receive(&self) -> Result<T, RecvError> {
+        let shared = &*self.shared;
+        loop {
+            if let Some(message) = self.try_recv() {
+                return Ok(message);
+            }
+            if shared.closed.load(Ordering::Acquire) {
+                // A last message may have been pushed before the close.
+                return self.try_recv().ok_or(RecvError::Disconnected);
+            }
+            sleep(&shared.pushed, &shared.sleeping_receivers, || {
+                shared.closed.load(Ordering::Relaxed) || !shared.is_empty()
+            });
+        }
+    }
+
+    /// Take a message if one is waiting, without blocking.
+    pub fn try_recv(&self) -> Option<T> {
+        let shared = &*self.shared;
+        let message = shared.try_pop()?;
+        notify(&shared.popped, &shared.sleeping_senders);
+        Some(message)
+    }
+
+    pub fn len(&self) -> usize {
+        self.shared.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shared.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.shared.slots.len()
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        self.shared.receivers.fetch_add(1, Ordering::Relaxed);
+        Self {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        if self.shared.receivers.fetch_sub(1, Ordering::Relaxed) == 1 {
+            self.shared.close();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{thread, time::Duration};
+
+    // Counts how many times it is dropped.
+    struct DetectDrop<'a>(&'a AtomicUsize);
+
+    impl Drop for DetectDrop<'_> {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn capacity_one_wraps_around() {
+        let (sender, receiver) = channel(1);
+        // Each message takes the only slot for a whole lap.
+        for i in 0..1000 {
+            sender.send(i).unwrap();
+            assert!(matches!(sender.try_send(-1), Err(TrySendError::Full(-1))));
+            assert_eq!(receiver.try_recv(), Some(i));
+            assert_eq!(receiver.try_recv(), None);
+        }
+    }
+
+    #[test]
+    fn wraps_around_under_contention() {
+        const MESSAGES: usize = 100_000;
+        let (sender, receiver) = channel(3);
+        thread::scope(|s| {
+            s.spawn(move || {
+                for i in 0..MESSAGES {
+                    sender.send(i).unwrap();
+                }
+            });
+            for i in 0..MESSAGES {
+                assert_eq!(receiver.receive(), Ok(i));
+            }
+            assert_eq!(receiver.receive(), Err(RecvError::Disconnected));
+        });
+    }
+
+    #[test]
+    fn blocked_sender_woken_by_close() {
+        let (sender, receiver) = channel(1);
+        sender.send(1).unwrap();
+        thread::scope(|s| {
+            let sending = s.spawn(|| sender.send(2));
+            thread::sleep(Duration::from_millis(20));
+            // Dropping the only receiver closes the channel.
+            drop(receiver);
+            assert_eq!(sending.join().unwrap(), Err(SendError(2, false)));
+        });
+    }
+
+    #[test]
+    fn unreceived_messages_dropped() {
+        let drops = AtomicUsize::new(0);
+        let (sender, receiver) = channel(4);
+        for _ in 0..3 {
+            sender.send(DetectDrop(&drops)).unwrap();
+        }
+        drop(receiver.try_recv());
+        assert_eq!(drops.load(Ordering::Relaxed), 1);
+        drop(sender);
+        drop(receiver);
+        assert_eq!(drops.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn each_message_received_once() {
+        const SENDERS: usize = 3;
+        const RECEIVERS: usize = 3;
+        const MESSAGES: usize = 10_000;
+        let (sender, receiver) = channel(8);
+        let mut received: Vec<usize> = thread::scope(|s| {
+            for t in 0..SENDERS {
+                let sender = sender.clone();
+                s.spawn(move || {
+                    for i in 0..MESSAGES {
+                        sender.send(t * MESSAGES + i).unwrap();
+                    }
+                });
+            }
+            drop(sender);
+            let receiving: Vec<_> = (0..RECEIVERS)
+                .map(|_| {
+                    let receiver = receiver.clone();
+                    s.spawn(move || {
+                        let mut received = Vec::new();
+                        while let Ok(message) = receiver.receive() {
+                            received.push(message);
+                        }
+                        received
+                    })
+                })
+                .collect();
+            receiving
+                .into_iter()
+                .flat_map(|r| r.join().unwrap())
+                .collect()
+        });
+        received.sort_unstable();
+        assert_eq!(received, (0..SENDERS * MESSAGES).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,216 @@
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures_core::Stream;
+use futures_sink::Sink;
+
+use crate::simple::{Receiver, SendError, Sender, TrySendError};
+
+/// Adapts a [`Receiver`] into a [`Stream`] of its messages, so it can be used
+/// with `StreamExt` combinators and anything else taking a stream. The stream
+/// ends once every `Sender` has been dropped and the channel is drained.
+///
+/// Polling never blocks the thread, a task waiting for a message is woken
+/// when one is sent, or the channel closes.
+pub struct ReceiverStream<T> {
+    receiver: Receiver<T>,
+}
+
+impl<T> ReceiverStream<T> {
+    pub fn new(receiver: Receiver<T>) -> Self {
+        Self { receiver }
+    }
+
+    pub fn into_inner(self) -> Receiver<T> {
+        self.receiver
+    }
+}
+
+impl<T> From<Receiver<T>> for ReceiverStream<T> {
+    fn from(receiver: Receiver<T>) -> Self {
+        Self::new(receiver)
+    }
+}
+
+impl<T> Stream for ReceiverStream<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        self.receiver.poll_receive(cx)
+    }
+}
+
+/// Adapts a [`Sender`] into a [`Sink`], so a stream can be forwarded into the
+/// channel with `StreamExt::forward`, or messages sent with `SinkExt::send`.
+///
+/// As a `Sink` has to accept a message before it knows whether there is room,
+/// the adapter holds on to one message until it fits in a bounded channel.
+/// `poll_ready` and `poll_flush` wait for that message to be sent, and fail
+/// with it in a [`SendError`] if the channel closes first.
+pub struct SenderSink<T> {
+    sender: Sender<T>,
+    pending: Option<T>,
+}
+
+impl<T> SenderSink<T> {
+    pub fn new(sender: Sender<T>) -> Self {
+        Self {
+            sender,
+            pending: None,
+        }
+    }
+
+    /// Returns the `Sender`, dropping any message which hasn't been flushed.
+    pub fn into_inner(self) -> Sender<T> {
+        self.sender
+    }
+}
+
+impl<T> From<Sender<T>> for SenderSink<T> {
+    fn from(sender: Sender<T>) -> Self {
+        Self::new(sender)
+    }
+}
+
+// The pending message is only ever moved, never pinned, so the sink can be
+// moved whether or not `T` can.
+impl<T> Unpin for SenderSink<T> {}
+
+impl<T> Sink<T> for SenderSink<T> {
+    type Error = SendError<T>;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), SendError<T>>> {
+        // Only one message is held, so there's room once it has gone.
+        self.poll_flush(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, message: T) -> Result<(), SendError<T>> {
+        let this = self.get_mut();
+        debug_assert!(this.pending.is_none(), "start_send without poll_ready");
+        this.pending = Some(message);
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), SendError<T>>> {
+        let this = self.get_mut();
+        let Some(message) = this.pending.take() else {
+            return Poll::Ready(Ok(()));
+        };
+        match this.sender.try_send_or_register(message, cx.waker()) {
+            Ok(()) => Poll::Ready(Ok(())),
+            Err(TrySendError::Full(message)) => {
+                this.pending = Some(message);
+                Poll::Pending
+            }
+            Err(TrySendError::Disconnected(message)) => Poll::Ready(Err(SendError(message, false))),
+            Err(TrySendError::Closed(message)) => Poll::Ready(Err(SendError(message, true))),
+        }
+    }
+
+    // Other clones of the `Sender` may still be sending, so closing the sink
+    // only flushes it. The channel closes once every `Sender` is dropped.
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), SendError<T>>> {
+        self.poll_flush(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple::{channel, channel_with_capacity};
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        task::{Wake, Waker},
+    };
+
+    // A waker which records whether it has been woken since last checked.
+    struct Flag(AtomicBool);
+
+    impl Wake for Flag {
+        fn wake(self: Arc<Self>) {
+            self.0.store(true, Ordering::Relaxed);
+        }
+    }
+
+    fn flag() -> (Arc<Flag>, Waker) {
+        let flag = Arc::new(Flag(AtomicBool::new(false)));
+        (Arc::clone(&flag), Waker::from(flag))
+    }
+
+    fn woken(flag: &Flag) -> bool {
+        flag.0.swap(false, Ordering::Relaxed)
+    }
+
+    #[test]
+    fn sink_flushes_the_pending_message_once_there_is_room() {
+        let (sender, receiver) = channel_with_capacity(1);
+        let mut sink = SenderSink::new(sender);
+        let (flag, waker) = flag();
+        let mut cx = Context::from_waker(&waker);
+
+        assert!(Pin::new(&mut sink).poll_ready(&mut cx).is_ready());
+        Pin::new(&mut sink).start_send(1).unwrap();
+        assert!(matches!(
+            Pin::new(&mut sink).poll_flush(&mut cx),
+            Poll::Ready(Ok(()))
+        ));
+
+        // The channel is full, so the sink holds on to the next message.
+        Pin::new(&mut sink).start_send(2).unwrap();
+        assert!(Pin::new(&mut sink).poll_flush(&mut cx).is_pending());
+        assert!(Pin::new(&mut sink).poll_ready(&mut cx).is_pending());
+        assert!(!woken(&flag));
+
+        assert_eq!(receiver.receive(), Ok(1));
+        assert!(woken(&flag));
+        assert!(matches!(
+            Pin::new(&mut sink).poll_flush(&mut cx),
+            Poll::Ready(Ok(()))
+        ));
+        assert_eq!(receiver.receive(), Ok(2));
+    }
+
+    #[test]
+    fn sink_hands_the_pending_message_back_on_close() {
+        let (sender, receiver) = channel_with_capacity(1);
+        let mut sink = SenderSink::new(sender);
+        let (flag, waker) = flag();
+        let mut cx = Context::from_waker(&waker);
+        Pin::new(&mut sink).start_send(1).unwrap();
+        assert!(Pin::new(&mut sink).poll_flush(&mut cx).is_ready());
+        Pin::new(&mut sink).start_send(2).unwrap();
+        assert!(Pin::new(&mut sink).poll_flush(&mut cx).is_pending());
+
+        drop(receiver);
+        assert!(woken(&flag));
+        match Pin::new(&mut sink).poll_flush(&mut cx) {
+            Poll::Ready(Err(error)) => assert_eq!(error, SendError(2, false)),
+            _ => panic!("flushed into a closed channel"),
+        }
+    }
+
+    #[test]
+    fn stream_ends_once_senders_are_gone() {
+        let (sender, receiver) = channel();
+        let mut stream = ReceiverStream::new(receiver);
+        let (flag, waker) = flag();
+        let mut cx = Context::from_waker(&waker);
+        assert!(Pin::new(&mut stream).poll_next(&mut cx).is_pending());
+
+        sender.send(1).unwrap();
+        assert!(woken(&flag));
+        assert_eq!(
+            Pin::new(&mut stream).poll_next(&mut cx),
+            Poll::Ready(Some(1))
+        );
+        assert!(Pin::new(&mut stream).poll_next(&mut cx).is_pending());
+        drop(sender);
+        assert!(woken(&flag));
+        assert_eq!(Pin::new(&mut stream).poll_next(&mut cx), Poll::Ready(None));
+    }
+}
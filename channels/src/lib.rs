@@ -1,3 +1,17 @@
-mod simple;
+pub mod borrowed_oneshot;
+pub mod futex;
+#[cfg(feature = "futures")]
+pub mod futures;
+pub mod mpsc;
+pub mod rendezvous;
+pub mod simple;
+pub mod slot;
+#[cfg(feature = "stats")]
+pub mod stats;
+pub mod std_mpsc;
+// The problematic example from the book, kept for its commentary rather than
+// as part of the API.
+#[allow(dead_code)]
 mod unsafe_oneshot;
-mod safe_oneshot;
+pub mod watch;
+pub mod safe_oneshot;
@@ -0,0 +1,302 @@
+use std::{
+    cell::{Cell, UnsafeCell},
+    marker::PhantomData,
+    mem::MaybeUninit,
+    ptr,
+    sync::{
+        atomic::{fence, AtomicBool, AtomicPtr, AtomicU32, AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use spinlock::futex::{wait, wake_one};
+
+use crate::simple::{RecvError, SendError};
+
+/// An unbounded multi-producer, single-consumer channel without any locks.
+///
+/// Messages go through a linked list of nodes in the style of Dmitry Vyukov's
+/// MPSC queue. A producer links in its node with a single atomic swap of the
+/// list's head, no matter how many other producers there are, and the consumer
+/// follows `next` pointers from the tail without synchronising with anyone.
+/// Only blocking needs more, a flag the consumer sleeps on with a futex when
+/// the queue is empty.
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    // The list always holds at least one node, the stub, whose value has
+    // already been taken, or was never set for the first one.
+    let stub = Box::into_raw(Node::new(MaybeUninit::uninit()));
+    let shared = Arc::new(Shared {
+        head: AtomicPtr::new(stub),
+        tail: UnsafeCell::new(stub),
+        sleeping: AtomicU32::new(0),
+        senders: AtomicUsize::new(1),
+        receiver: AtomicBool::new(true),
+    });
+    (
+        Sender {
+            shared: Arc::clone(&shared),
+        },
+        Receiver {
+            shared,
+            _not_sync: PhantomData,
+        },
+    )
+}
+
+struct Node<T> {
+    next: AtomicPtr<Node<T>>,
+    value: MaybeUninit<T>,
+}
+
+impl<T> Node<T> {
+    fn new(value: MaybeUninit<T>) -> Box<Self> {
+        Box::new(Self {
+            next: AtomicPtr::new(ptr::null_mut()),
+            value,
+        })
+    }
+}
+
+struct Shared<T> {
+    // The most recently pushed node, swapped by producers.
+    head: AtomicPtr<Node<T>>,
+    // The stub node, only touched by the consumer. The next message is in the
+    // node after it.
+    tail: UnsafeCell<*mut Node<T>>,
+    // 1 while the receiver is, or is about to be, asleep waiting for a message.
+    sleeping: AtomicU32,
+    senders: AtomicUsize,
+    receiver: AtomicBool,
+}
+
+// Messages are moved between threads, but never shared, so `T: Send` is enough.
+unsafe impl<T: Send> Send for Shared<T> {}
+unsafe impl<T: Send> Sync for Shared<T> {}
+
+impl<T> Shared<T> {
+    fn push(&self, value: T) {
+        let node = Box::into_raw(Node::new(MaybeUninit::new(value)));
+        // AcqRel: Release publishes our node to whoever swaps after us, and
+        // Acquire makes the previous node's initialisation visible to us.
+        let prev = self.head.swap(node, Ordering::AcqRel);
+        // Between the swap and this store the list is briefly broken, and the
+        // consumer sees it as ending at prev. It will pick up our node once
+        // this is done, and we wake it afterwards in case it went to sleep.
+        unsafe { (*prev).next.store(node, Ordering::Release) };
+    }
+
+    // Safety: only the single consumer may call this.
+    unsafe fn pop(&self) -> Option<T> {
+        let tail = *self.tail.get();
+        let next = (*tail).next.load(Ordering::Acquire);
+        if next.is_null() {
+            return None;
+        }
+        // next becomes the stub, with its value moved out, and the old stub
+        // can be freed as no producer holds a pointer to it any more.
+        *self.tail.get() = next;
+        let value = (*next).value.assume_init_read();
+        drop(Box::from_raw(tail));
+        Some(value)
+    }
+
+    // Wakes the receiver if it is asleep, or about to go to sleep.
+    fn wake_receiver(&self) {
+        // Pairs with the fence in `Receiver::receive`, so that either it sees
+        // what we did before calling this, or we see that it is sleeping.
+        fence(Ordering::SeqCst);
+        if self.sleeping.swap(0, Ordering::Relaxed) == 1 {
+            wake_one(&self.sleeping);
+        }
+    }
+}
+
+impl<T> Drop for Shared<T> {
+    fn drop(&mut self) {
+        // Free the stub, then every node after it along with its message.
+        let mut node = unsafe { Box::from_raw(*self.tail.get_mut()) };
+        loop {
+            let next = *node.next.get_mut();
+            if next.is_null() {
+                break;
+            }
+            node = unsafe { Box::from_raw(next) };
+            unsafe { node.value.assume_init_drop() };
+        }
+    }
+}
+
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Send a message, or hand it back if the `Receiver` has been dropped.
+    pub fn send(&self, message: T) -> Result<(), SendError<T>> {
+        // The receiver may be dropped straight after this check, in which case
+        // the message is dropped along with the queue instead.
+        if !self.shared.receiver.load(Ordering::Relaxed) {
+            return Err(SendError(message, false));
+        }
+        self.shared.push(message);
+        self.shared.wake_receiver();
+        Ok(())
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::Relaxed);
+        Self {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        // Release pairs with the receiver's Acquire load, so that once it sees
+        // no senders left, it also sees every message they pushed.
+        if self.shared.senders.fetch_sub(1, Ordering::Release) == 1 {
+            self.shared.wake_receiver();
+        }
+    }
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+    // There is only one consumer, so the receiving methods taking `&self` must
+    // not be called from several threads at once. Receiver can still be sent
+    // to another thread.
+    _not_sync: PhantomData<Cell<()>>,
+}
+
+impl<T> Receiver<T> {
+    /// Take a message if one is waiting, without blocking.
+    pub fn try_recv(&self) -> Option<T> {
+        // Receiver isn't Sync, so this is the only consumer.
+        unsafe { self.shared.pop() }
+    }
+
+    /// Block until a message arrives, or return [`RecvError::Disconnected`]
+    /// once every `Sender` has been dropped and the queue is drained.
+    pub fn receive(&self) -> Result<T, RecvError> {
+        loop {
+            if let Some(message) = self.try_recv() {
+                return Ok(message);
+            }
+            // Announce that we're going to sleep before checking again, so
+            // that a sender which pushes after our check will see the flag and
+            // wake us, see `Shared::wake_receiver`.
+            self.shared.sleeping.store(1, Ordering::Relaxed);
+            fence(Ordering::SeqCst);
+            if let Some(message) = self.try_recv() {
+                self.shared.sleeping.store(0, Ordering::Relaxed);
+                return Ok(message);
+            }
+            if self.shared.senders.load(Ordering::Acquire) == 0 {
+                self.shared.sleeping.store(0, Ordering::Relaxed);
+                // A last message may have been linked in after our check.
+                return self.try_recv().ok_or(RecvError::Disconnected);
+            }
+            wait(&self.shared.sleeping, 1);
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.receiver.store(false, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{thread, time::Duration};
+
+    // Counts how many times it is dropped.
+    struct DetectDrop<'a>(&'a AtomicUsize);
+
+    impl Drop for DetectDrop<'_> {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn many_producers_keep_their_order() {
+        const PRODUCERS: usize = 4;
+        const MESSAGES: usize = 10_000;
+        let (sender, receiver) = channel();
+        thread::scope(|s| {
+            for producer in 0..PRODUCERS {
+                let sender = sender.clone();
+                s.spawn(move || {
+                    for i in 0..MESSAGES {
+                        sender.send((producer, i)).unwrap();
+                    }
+                });
+            }
+            drop(sender);
+            // Messages from different producers interleave, but each
+            // producer's own arrive in the order it sent them.
+            let mut next = [0; PRODUCERS];
+            while let Ok((producer, i)) = receiver.receive() {
+                assert_eq!(i, next[producer]);
+                next[producer] += 1;
+            }
+            assert_eq!(next, [MESSAGES; PRODUCERS]);
+        });
+    }
+
+    #[test]
+    fn unreceived_messages_dropped() {
+        let drops = AtomicUsize::new(0);
+        let (sender, receiver) = channel();
+        for _ in 0..3 {
+            sender.send(DetectDrop(&drops)).unwrap();
+        }
+        drop(receiver.try_recv());
+        assert_eq!(drops.load(Ordering::Relaxed), 1);
+        drop(sender);
+        drop(receiver);
+        assert_eq!(drops.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn disconnected_after_last_sender_and_drain() {
+        let (sender, receiver) = channel();
+        let other = sender.clone();
+        sender.send(1).unwrap();
+        other.send(2).unwrap();
+        drop(sender);
+        // One sender is left, so the queue being empty isn't the end.
+        assert_eq!(receiver.receive(), Ok(1));
+        assert_eq!(receiver.receive(), Ok(2));
+        assert_eq!(receiver.try_recv(), None);
+        other.send(3).unwrap();
+        drop(other);
+        assert_eq!(receiver.receive(), Ok(3));
+        assert_eq!(receiver.receive(), Err(RecvError::Disconnected));
+    }
+
+    #[test]
+    fn blocked_receiver_woken_by_last_sender() {
+        let (sender, receiver) = channel::<i32>();
+        thread::scope(|s| {
+            let receiving = s.spawn(move || receiver.receive());
+            thread::sleep(Duration::from_millis(20));
+            drop(sender);
+            assert_eq!(receiving.join().unwrap(), Err(RecvError::Disconnected));
+        });
+    }
+
+    #[test]
+    fn send_fails_after_receiver_dropped() {
+        let (sender, receiver) = channel();
+        sender.send(1).unwrap();
+        drop(receiver);
+        assert_eq!(sender.send(2), Err(SendError(2, false)));
+    }
+}
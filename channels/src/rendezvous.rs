@@ -0,0 +1,270 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::simple::{RecvError, RecvTimeoutError, SendError, TrySendError};
+
+/// Create a rendezvous channel, one with no capacity at all, as a [`Sender`]
+/// and [`Receiver`] pair.
+///
+/// A message is never queued. `send` blocks until the `Receiver` has taken the
+/// message out of the sender's hands, so once it returns the message is known
+/// to have been received, and a producer can never get ahead of the consumer
+/// by even one message. This makes handoffs deterministic, which is useful for
+/// pacing and in tests.
+///
+/// As with [`crate::simple::channel`], the `Sender` can be cloned, and the
+/// channel closes once every `Sender` is dropped, or the `Receiver` is.
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            slot: None,
+            sent: 0,
+            taken: 0,
+            receiving: false,
+            closed: false,
+        }),
+        ready: Condvar::new(),
+        taken: Condvar::new(),
+        slot_free: Condvar::new(),
+        senders: AtomicUsize::new(1),
+    });
+    (
+        Sender {
+            shared: Arc::clone(&shared),
+        },
+        Receiver { shared },
+    )
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    // Signalled when a message is put in the slot, for the receiver.
+    ready: Condvar,
+    // Signalled when the receiver takes a message, for the sender that put it.
+    taken: Condvar,
+    // Signalled when the slot is emptied, for senders waiting their turn.
+    slot_free: Condvar,
+    senders: AtomicUsize,
+}
+
+struct State<T> {
+    // The message being handed over, by the one sender whose turn it is.
+    slot: Option<T>,
+    // How many messages have been put in the slot, and taken from it, so a
+    // sender can tell when its own message has gone.
+    sent: u64,
+    taken: u64,
+    // Whether the receiver is blocked in `receive`, for `try_send`.
+    receiving: bool,
+    closed: bool,
+}
+
+impl<T> Shared<T> {
+    fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.ready.notify_all();
+        self.taken.notify_all();
+        self.slot_free.notify_all();
+    }
+}
+
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Hand `message` to the receiver, blocking until it has been taken.
+    /// Returns the message back as an error if the channel closes before then.
+    pub fn send(&self, message: T) -> Result<(), SendError<T>> {
+        let mut state = self.shared.state.lock().unwrap();
+        // Other senders may be part way through a handoff, wait for our turn.
+        while !state.closed && state.slot.is_some() {
+            state = self.shared.slot_free.wait(state).unwrap();
+        }
+        if state.closed {
+            return Err(SendError(message, false));
+        }
+        state.slot = Some(message);
+        state.sent += 1;
+        let ticket = state.sent;
+        self.shared.ready.notify_one();
+        while !state.closed && state.taken < ticket {
+            state = self.shared.taken.wait(state).unwrap();
+        }
+        if state.taken < ticket {
+            // Closed with our message still in the slot, so take it back.
+            let message = state.slot.take().unwrap();
+            return Err(SendError(message, false));
+        }
+        Ok(())
+    }
+
+    /// Hand `message` over only if the receiver is already blocked waiting for
+    /// one, otherwise give it straight back as [`TrySendError::Full`].
+    pub fn try_send(&self, message: T) -> Result<(), TrySendError<T>> {
+        let mut state = self.shared.state.lock().unwrap();
+        if state.closed {
+            return Err(TrySendError::Disconnected(message));
+        }
+        if !state.receiving || state.slot.is_some() {
+            return Err(TrySendError::Full(message));
+        }
+        // The receiver is asleep in `receive` and can't leave without taking
+        // this, so there's no need to wait around for it.
+        state.slot = Some(message);
+        state.sent += 1;
+        self.shared.ready.notify_one();
+        Ok(())
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::Relaxed);
+        Self {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        if self.shared.senders.fetch_sub(1, Ordering::Relaxed) == 1 {
+            self.shared.close();
+        }
+    }
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Receiver<T> {
+    /// Block until a sender hands over a message, or return
+    /// [`RecvError::Disconnected`] once every `Sender` has been dropped.
+    pub fn receive(&self) -> Result<T, RecvError> {
+        let mut state = self.shared.state.lock().unwrap();
+        loop {
+            if let Some(message) = self.take(&mut state) {
+                state.receiving = false;
+                return Ok(message);
+            }
+            if state.closed {
+                state.receiving = false;
+                return Err(RecvError::Disconnected);
+            }
+            state.receiving = true;
+            state = self.shared.ready.wait(state).unwrap();
+        }
+    }
+
+    /// Like `receive`, but gives up with [`RecvTimeoutError::Timeout`] if no
+    /// sender hands over a message within `timeout`. While waiting, the
+    /// receiver counts as blocked for `try_send`, as it does in `receive`.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        // A timeout too long to represent may as well be forever.
+        let Some(deadline) = Instant::now().checked_add(timeout) else {
+            return self.receive().map_err(RecvTimeoutError::from);
+        };
+        let mut state = self.shared.state.lock().unwrap();
+        loop {
+            if let Some(message) = self.take(&mut state) {
+                state.receiving = false;
+                return Ok(message);
+            }
+            if state.closed {
+                state.receiving = false;
+                return Err(RecvTimeoutError::Disconnected);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                state.receiving = false;
+                return Err(RecvTimeoutError::Timeout);
+            }
+            state.receiving = true;
+            state = self
+                .shared
+                .ready
+                .wait_timeout(state, deadline - now)
+                .unwrap()
+                .0;
+        }
+    }
+
+    /// Take a message if a sender is blocked handing one over, otherwise
+    /// return `None` straight away.
+    pub fn try_recv(&self) -> Option<T> {
+        self.take(&mut self.shared.state.lock().unwrap())
+    }
+
+    fn take(&self, state: &mut State<T>) -> Option<T> {
+        let message = state.slot.take()?;
+        state.taken += 1;
+        // Only the sender whose message this was can be waiting on `taken`.
+        self.shared.taken.notify_one();
+        self.shared.slot_free.notify_one();
+        Some(message)
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    // Spins until `check` holds for the channel's state.
+    fn wait_until<T>(receiver: &Receiver<T>, check: impl Fn(&State<T>) -> bool) {
+        while !check(&receiver.shared.state.lock().unwrap()) {
+            thread::yield_now();
+        }
+    }
+
+    #[test]
+    fn send_returns_once_the_message_is_taken() {
+        let (sender, receiver) = channel();
+        thread::scope(|s| {
+            let sending = s.spawn(|| sender.send(1));
+            wait_until(&receiver, |state| state.slot.is_some());
+            thread::sleep(Duration::from_millis(20));
+            // Still in the sender's hands, as nothing has taken it.
+            assert!(!sending.is_finished());
+            assert_eq!(receiver.receive(), Ok(1));
+            assert_eq!(sending.join().unwrap(), Ok(()));
+        });
+    }
+
+    #[test]
+    fn try_send_needs_a_blocked_receiver() {
+        let (sender, receiver) = channel();
+        assert_eq!(sender.try_send(1), Err(TrySendError::Full(1)));
+        assert_eq!(receiver.try_recv(), None);
+        thread::scope(|s| {
+            let receiving = s.spawn(|| receiver.receive());
+            wait_until(&receiver, |state| state.receiving);
+            assert_eq!(sender.try_send(2), Ok(()));
+            assert_eq!(receiving.join().unwrap(), Ok(2));
+        });
+        assert_eq!(sender.try_send(3), Err(TrySendError::Full(3)));
+    }
+
+    #[test]
+    fn closing_hands_a_parked_message_back() {
+        let (sender, receiver) = channel();
+        thread::scope(|s| {
+            let sending = s.spawn(|| sender.send(String::from("unread")));
+            wait_until(&receiver, |state| state.slot.is_some());
+            drop(receiver);
+            assert_eq!(
+                sending.join().unwrap(),
+                Err(SendError(String::from("unread"), false))
+            );
+        });
+    }
+}
@@ -1,12 +1,25 @@
 use std::{
     cell::UnsafeCell,
+    error::Error,
+    fmt,
     mem::MaybeUninit,
     sync::{
-        atomic::{AtomicBool, Ordering},
+        atomic::{AtomicU32, Ordering},
         Arc,
     },
+    time::{Duration, Instant},
 };
 
+use spinlock::futex::{wait, wait_timeout, wake_one};
+
+// States of the channel. It starts out `EMPTY`, becomes `READY` when the
+// message is sent, and ends up `DISCONNECTED` once the message has been
+// received or either side is dropped early, as no message can be delivered
+// after any of those.
+const EMPTY: u32 = 0;
+const READY: u32 = 1;
+const DISCONNECTED: u32 = 2;
+
 /// Implementation of a channel through a safe mechanism.
 ///
 /// The channel itself is now considered an internal implementation detail.
@@ -17,22 +30,34 @@ use std::{
 /// library and other popular libraries.
 struct Channel<T> {
     message: UnsafeCell<MaybeUninit<T>>,
-    ready: AtomicBool,
+    // One of the states above. This is a u32 so that `recv` can wait on it
+    // with a futex.
+    state: AtomicU32,
 }
 
+// The sender and receiver are on different threads, sharing the channel. Only
+// one side touches the message at a time, so `T: Send` is enough.
+unsafe impl<T> Sync for Channel<T> where T: Send {}
+
 impl<T> Drop for Channel<T> {
     fn drop(&mut self) {
-        if *self.ready.get_mut() {
+        if *self.state.get_mut() == READY {
             unsafe { self.message.get_mut().assume_init_drop() }
         }
     }
 }
 
+impl<T> Channel<T> {
+    fn new() -> Self {
+        Self {
+            message: UnsafeCell::new(MaybeUninit::uninit()),
+            state: AtomicU32::new(EMPTY),
+        }
+    }
+}
+
 pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
-    let a = Arc::new(Channel {
-        message: UnsafeCell::new(MaybeUninit::uninit()),
-        ready: AtomicBool::new(false),
-    });
+    let a = Arc::new(Channel::new());
     (
         Sender {
             channel: Arc::clone(&a),
@@ -51,9 +76,40 @@ impl<T> Sender<T> {
     // Attempting to do so by a user will be caught by the compiler, removing
     // any possibility of user errors and panics, as is present in the unsafe
     // implementation.
-    pub fn send(self, message: T) {
+    //
+    // If the `Receiver` has been dropped nobody could ever read the message,
+    // so it is handed back as the error instead.
+    pub fn send(self, message: T) -> Result<(), T> {
+        // The receiver only reads the message once it sees `READY`, so writing
+        // it first is fine even if we end up taking it back.
         unsafe { (*self.channel.message.get()).write(message) };
-        self.channel.ready.store(true, Ordering::Release);
+        if self
+            .channel
+            .state
+            .compare_exchange(EMPTY, READY, Ordering::Release, Ordering::Relaxed)
+            .is_err()
+        {
+            return Err(unsafe { (*self.channel.message.get()).assume_init_read() });
+        }
+        // Wake the receiver if it is blocked in `recv`. Our reference keeps the
+        // channel alive even if it has already woken and returned.
+        wake_one(&self.channel.state);
+        Ok(())
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        // Only does anything if nothing was sent, in which case nothing ever
+        // will be, so wake the receiver to tell it so.
+        if self
+            .channel
+            .state
+            .compare_exchange(EMPTY, DISCONNECTED, Ordering::Relaxed, Ordering::Relaxed)
+            .is_ok()
+        {
+            wake_one(&self.channel.state);
+        }
     }
 }
 
@@ -63,12 +119,419 @@ pub struct Receiver<T> {
 
 impl<T> Receiver<T> {
     pub fn is_ready(&self) -> bool {
-        self.channel.ready.load(Ordering::Relaxed)
+        self.channel.state.load(Ordering::Relaxed) == READY
+    }
+    // As with `Sender::send`, taking `self` means the message can only be
+    // received once, which the compiler enforces rather than a panic at
+    // runtime. Rather than panicking if the message hasn't been sent yet, this
+    // waits for it, the same as `recv`.
+    pub fn receive(self) -> Result<T, RecvError> {
+        self.recv()
+    }
+
+    /// Receive the message if it has been sent, without blocking or panicking.
+    ///
+    /// Unlike pairing `is_ready` with `receive`, checking and taking the message
+    /// is a single atomic step, so nothing can take it in between.
+    pub fn try_receive(&self) -> Result<T, TryRecvError> {
+        match self.channel.state.compare_exchange(
+            READY,
+            DISCONNECTED,
+            Ordering::Acquire,
+            Ordering::Relaxed,
+        ) {
+            Ok(_) => Ok(unsafe { (*self.channel.message.get()).assume_init_read() }),
+            Err(EMPTY) => Err(TryRecvError::Empty),
+            Err(_) => Err(TryRecvError::Disconnected),
+        }
+    }
+
+    /// Block until the message has been sent, then receive it.
+    ///
+    /// Rather than spinning on `is_ready`, the thread sleeps in a futex wait on
+    /// the channel's state, which `send` wakes it from. If the `Sender` is
+    /// dropped without sending, or the message was already taken with
+    /// `try_receive`, this returns [`RecvError::Disconnected`] rather than
+    /// waiting forever.
+    pub fn recv(self) -> Result<T, RecvError> {
+        self.wait()
+    }
+
+    // `recv` without consuming the receiver, for `ReusableOneshot`.
+    fn wait(&self) -> Result<T, RecvError> {
+        loop {
+            match self.try_receive() {
+                Ok(message) => return Ok(message),
+                // Only sleeps if the channel is still empty, so a `send` which
+                // happens between the check and here isn't missed.
+                Err(TryRecvError::Empty) => wait(&self.channel.state, EMPTY),
+                Err(TryRecvError::Disconnected) => return Err(RecvError::Disconnected),
+            }
+        }
+    }
+
+    /// Like `recv`, but gives up with [`RecvTimeoutError::Timeout`] once
+    /// `timeout` has passed without the message arriving, for callers which
+    /// would rather report a missing response than wait on it forever.
+    ///
+    /// This borrows the `Receiver`, so a caller can wait again after a timeout,
+    /// or still take a message which arrives later with `try_receive`.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        let deadline = Instant::now().checked_add(timeout);
+        loop {
+            match self.try_receive() {
+                Ok(message) => return Ok(message),
+                Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+                Err(TryRecvError::Empty) => {}
+            }
+            match deadline {
+                // A timeout too long to represent may as well be forever.
+                None => wait(&self.channel.state, EMPTY),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(RecvTimeoutError::Timeout);
+                    }
+                    wait_timeout(&self.channel.state, EMPTY, deadline - now);
+                }
+            }
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        // If nothing has been sent yet, tell the sender not to bother. A message
+        // which was sent but not received is dropped along with the channel.
+        let _ = self.channel.state.compare_exchange(
+            EMPTY,
+            DISCONNECTED,
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+        );
+    }
+}
+
+/// A oneshot channel which can be used for one request/response round after
+/// another, without allocating a new channel each time.
+///
+/// Each round starts with `sender`, which hands out a [`Sender`] for the
+/// responder, and ends with receiving the message through `recv`,
+/// `try_receive` or `recv_timeout`, which behave as they do on [`Receiver`].
+/// Starting the next round resets the channel in place, as long as the last
+/// round's `Sender` is gone. If it is still alive, say after a timeout, a
+/// fresh channel is allocated instead, and the stale `Sender` finds it has
+/// been disconnected.
+pub struct ReusableOneshot<T> {
+    receiver: Receiver<T>,
+}
+
+impl<T> ReusableOneshot<T> {
+    pub fn new() -> Self {
+        Self {
+            receiver: Receiver {
+                channel: Arc::new(Channel::new()),
+            },
+        }
+    }
+
+    /// Start a new round, returning the `Sender` for it. Any message from the
+    /// last round which wasn't received is dropped.
+    pub fn sender(&mut self) -> Sender<T> {
+        self.reset();
+        Sender {
+            channel: Arc::clone(&self.receiver.channel),
+        }
+    }
+
+    /// Throw away the current round, so the channel is empty again. Called by
+    /// `sender`, so only needed to drop a message early.
+    pub fn reset(&mut self) {
+        match Arc::get_mut(&mut self.receiver.channel) {
+            // No `Sender` is left, so nothing else can be touching the channel.
+            Some(channel) => {
+                if *channel.state.get_mut() == READY {
+                    unsafe { channel.message.get_mut().assume_init_drop() }
+                }
+                *channel.state.get_mut() = EMPTY;
+            }
+            // Dropping the old receiver disconnects the old sender.
+            None => {
+                self.receiver = Receiver {
+                    channel: Arc::new(Channel::new()),
+                }
+            }
+        }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.receiver.is_ready()
+    }
+
+    pub fn try_receive(&self) -> Result<T, TryRecvError> {
+        self.receiver.try_receive()
+    }
+
+    /// Block until this round's message has been sent, then receive it, see
+    /// [`Receiver::recv`].
+    pub fn recv(&self) -> Result<T, RecvError> {
+        self.receiver.wait()
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        self.receiver.recv_timeout(timeout)
+    }
+}
+
+impl<T> Default for ReusableOneshot<T> {
+    fn default() -> Self {
+        Self::new()
     }
-    pub fn receive(&self) -> T {
-        if !self.channel.ready.swap(false, Ordering::Acquire) {
-            panic!("No messages");
+}
+
+/// Returned by [`Receiver::try_receive`] when there is no message to take.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+    /// The message hasn't been sent yet.
+    Empty,
+    /// The `Sender` was dropped without sending, or the message was already
+    /// received, so no message will ever arrive.
+    Disconnected,
+}
+
+impl fmt::Display for TryRecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryRecvError::Empty => f.write_str("no message is ready"),
+            TryRecvError::Disconnected => f.write_str("the channel is disconnected"),
+        }
+    }
+}
+
+impl Error for TryRecvError {}
+
+/// Returned by [`Receiver::recv`] when no message will ever arrive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecvError {
+    /// The `Sender` was dropped without sending, or the message was already
+    /// taken with `try_receive`.
+    Disconnected,
+}
+
+impl fmt::Display for RecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecvError::Disconnected => f.write_str("the channel is disconnected"),
+        }
+    }
+}
+
+impl Error for RecvError {}
+
+/// Returned by [`Receiver::recv_timeout`] when no message arrives in time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecvTimeoutError {
+    /// The message wasn't sent before the timeout, but may still be.
+    Timeout,
+    /// As for [`RecvError::Disconnected`].
+    Disconnected,
+}
+
+impl fmt::Display for RecvTimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecvTimeoutError::Timeout => f.write_str("timed out waiting for the message"),
+            RecvTimeoutError::Disconnected => f.write_str("the channel is disconnected"),
+        }
+    }
+}
+
+impl Error for RecvTimeoutError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::atomic::AtomicUsize, thread};
+
+    // Counts how many times it is dropped.
+    struct DetectDrop<'a>(&'a AtomicUsize);
+
+    impl Drop for DetectDrop<'_> {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::Relaxed);
         }
-        unsafe { (*self.channel.message.get()).assume_init_read() }
+    }
+
+    #[test]
+    fn recv_blocks_until_sent() {
+        let (sender, receiver) = channel();
+        thread::scope(|s| {
+            let waiter = s.spawn(move || receiver.recv());
+            thread::sleep(Duration::from_millis(20));
+            assert!(!waiter.is_finished());
+            sender.send(7).unwrap();
+            assert_eq!(waiter.join().unwrap(), Ok(7));
+        });
+    }
+
+    #[test]
+    fn recv_after_send_returns_straight_away() {
+        let (sender, receiver) = channel();
+        sender.send("hello").unwrap();
+        assert!(receiver.is_ready());
+        assert_eq!(receiver.recv(), Ok("hello"));
+    }
+
+    #[test]
+    fn try_receive_does_not_block() {
+        let (sender, receiver) = channel();
+        assert_eq!(receiver.try_receive(), Err(TryRecvError::Empty));
+        sender.send(1).unwrap();
+        assert_eq!(receiver.try_receive(), Ok(1));
+        // The message can only be taken once.
+        assert_eq!(receiver.try_receive(), Err(TryRecvError::Disconnected));
+        assert!(!receiver.is_ready());
+    }
+
+    #[test]
+    fn dropped_sender_disconnects() {
+        let (sender, receiver) = channel::<i32>();
+        drop(sender);
+        assert_eq!(receiver.try_receive(), Err(TryRecvError::Disconnected));
+        assert_eq!(receiver.recv(), Err(RecvError::Disconnected));
+    }
+
+    #[test]
+    fn blocked_recv_woken_by_dropped_sender() {
+        let (sender, receiver) = channel::<i32>();
+        thread::scope(|s| {
+            let waiter = s.spawn(move || receiver.recv());
+            thread::sleep(Duration::from_millis(20));
+            assert!(!waiter.is_finished());
+            drop(sender);
+            assert_eq!(waiter.join().unwrap(), Err(RecvError::Disconnected));
+        });
+    }
+
+    #[test]
+    fn send_hands_back_the_message_without_a_receiver() {
+        let drops = AtomicUsize::new(0);
+        let (sender, receiver) = channel();
+        drop(receiver);
+        let Err(message) = sender.send(DetectDrop(&drops)) else {
+            panic!("sent to a dropped receiver");
+        };
+        // Handed back rather than dropped by the channel as well.
+        assert_eq!(drops.load(Ordering::Relaxed), 0);
+        drop(message);
+        assert_eq!(drops.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn unreceived_message_dropped() {
+        let drops = AtomicUsize::new(0);
+        let (sender, receiver) = channel();
+        assert!(sender.send(DetectDrop(&drops)).is_ok());
+        assert_eq!(drops.load(Ordering::Relaxed), 0);
+        drop(receiver);
+        assert_eq!(drops.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn receive_waits_for_the_message() {
+        let (sender, receiver) = channel();
+        thread::scope(|s| {
+            let waiter = s.spawn(move || receiver.receive());
+            thread::sleep(Duration::from_millis(20));
+            assert!(!waiter.is_finished());
+            sender.send(String::from("late")).unwrap();
+            assert_eq!(waiter.join().unwrap().as_deref(), Ok("late"));
+        });
+    }
+
+    #[test]
+    fn recv_timeout_waits_out_the_deadline() {
+        let (sender, receiver) = channel();
+        let timeout = Duration::from_millis(20);
+        let start = Instant::now();
+        assert_eq!(
+            receiver.recv_timeout(timeout),
+            Err(RecvTimeoutError::Timeout)
+        );
+        assert!(start.elapsed() >= timeout);
+
+        // The receiver is only borrowed, so a message sent later still arrives.
+        sender.send(3).unwrap();
+        assert_eq!(receiver.recv_timeout(timeout), Ok(3));
+        assert_eq!(
+            receiver.recv_timeout(timeout),
+            Err(RecvTimeoutError::Disconnected)
+        );
+    }
+
+    #[test]
+    fn recv_timeout_woken_by_send() {
+        let (sender, receiver) = channel();
+        let start = Instant::now();
+        thread::scope(|s| {
+            s.spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                sender.send(4).unwrap();
+            });
+            assert_eq!(receiver.recv_timeout(Duration::from_secs(10)), Ok(4));
+        });
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn recv_timeout_disconnected_by_dropped_sender() {
+        let (sender, receiver) = channel::<i32>();
+        drop(sender);
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_secs(10)),
+            Err(RecvTimeoutError::Disconnected)
+        );
+    }
+
+    #[test]
+    fn reusable_resets_in_place() {
+        let drops = AtomicUsize::new(0);
+        let mut oneshot = ReusableOneshot::new();
+        let channel = Arc::as_ptr(&oneshot.receiver.channel);
+        for round in 0..3 {
+            assert!(oneshot.sender().send(DetectDrop(&drops)).is_ok());
+            drop(oneshot.recv().unwrap());
+            assert_eq!(drops.load(Ordering::Relaxed), round + 1);
+        }
+
+        // A message left unreceived is dropped when the next round starts.
+        assert!(oneshot.sender().send(DetectDrop(&drops)).is_ok());
+        assert!(oneshot.is_ready());
+        let sender = oneshot.sender();
+        assert_eq!(drops.load(Ordering::Relaxed), 4);
+        assert!(!oneshot.is_ready());
+        assert_eq!(oneshot.try_receive().err(), Some(TryRecvError::Empty));
+        drop(sender);
+        assert_eq!(Arc::as_ptr(&oneshot.receiver.channel), channel);
+    }
+
+    #[test]
+    fn reusable_replaces_channel_with_stale_sender() {
+        let mut oneshot = ReusableOneshot::new();
+        let stale = oneshot.sender();
+        assert_eq!(
+            oneshot.recv_timeout(Duration::from_millis(1)),
+            Err(RecvTimeoutError::Timeout)
+        );
+        let channel = Arc::as_ptr(&oneshot.receiver.channel);
+
+        // The stale sender keeps the old channel alive, so a new one is needed,
+        // and a late response must not turn up in the next round.
+        let sender = oneshot.sender();
+        assert_ne!(Arc::as_ptr(&oneshot.receiver.channel), channel);
+        assert_eq!(stale.send(1), Err(1));
+        assert_eq!(oneshot.try_receive(), Err(TryRecvError::Empty));
+        sender.send(2).unwrap();
+        assert_eq!(oneshot.recv(), Ok(2));
     }
 }
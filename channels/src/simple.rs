@@ -1,9 +1,78 @@
-use std::collections::VecDeque;
-use std::sync::{Mutex, Condvar};
+use std::collections::{vec_deque, VecDeque};
+use std::error::Error;
+use std::fmt;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, Weak};
+use std::task::Waker;
+#[cfg(feature = "futures")]
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "stats")]
+use crate::stats::{ChannelMetrics, Stats};
 
 pub struct SimpleChannel<T> {
-    queue: Mutex<VecDeque<T>>,
+    queue: Mutex<Queue<T>>,
     ready: Condvar,
+    // The most messages the queue can hold, usize::MAX when unbounded.
+    capacity: usize,
+    // What a sender does when the queue is full, always `Block` when
+    // unbounded as it never is.
+    backpressure: Backpressure,
+}
+
+// Everything protected by the mutex. The closed flag lives here rather than
+// in an atomic so that checking it and waiting on a condvar is one step, and
+// a close can't slip in between and go unnoticed.
+struct Queue<T> {
+    messages: VecDeque<T>,
+    closed: bool,
+    // Set by `shutdown` along with `closed`, so receivers report `Closed`
+    // rather than `Disconnected`.
+    shut_down: bool,
+    // Tasks waiting for a message, or for room to send one, which can't block
+    // on the condvars. Only ever filled with the `futures` feature.
+    receive_wakers: Vec<Waker>,
+    send_wakers: Vec<Waker>,
+    // How many threads are blocked on `ready`, counted from just before they
+    // wait until they have the lock back. A notify only goes out when this
+    // says someone could be waiting, and one per message, so no more threads
+    // are woken than can take one.
+    waiting_receivers: usize,
+    // Threads blocked waiting for room, in the order they arrived, each with
+    // a condvar of its own so that only the one at the front is woken, see
+    // `wait_for_room`.
+    parked_senders: VecDeque<Arc<Condvar>>,
+    #[cfg(feature = "stats")]
+    stats: Stats,
+}
+
+// A task is often polled again before it is woken, so it may already be
+// registered.
+fn register(wakers: &mut Vec<Waker>, waker: &Waker) {
+    if !wakers.iter().any(|w| w.will_wake(waker)) {
+        wakers.push(waker.clone());
+    }
+}
+
+impl<T> Queue<T> {
+    fn wake_receivers(&mut self) {
+        self.receive_wakers.drain(..).for_each(Waker::wake);
+    }
+
+    fn wake_senders(&mut self) {
+        self.send_wakers.drain(..).for_each(Waker::wake);
+    }
+
+    // What receiving fails with once the channel is closed and empty.
+    fn closed_error(&self) -> RecvError {
+        if self.shut_down {
+            RecvError::Closed
+        } else {
+            RecvError::Disconnected
+        }
+    }
 }
 
 /// A simple channel implementation through the use of a [`Mutex`] and [`Condvar`].
@@ -15,33 +84,1433 @@ pub struct SimpleChannel<T> {
 /// The conditional variable ([`Condvar`]) is used to cause the [`receive`] function
 /// to be blocking. The thread will block until a message can be received.
 ///
-/// This would class as an unbounded channel, there is nothing stopping those who
-/// send into the channel from outpacing the receive call.
+/// Created with `new`, this would class as an unbounded channel, there is
+/// nothing stopping those who send into the channel from outpacing the receive
+/// call. A channel created with `with_capacity` is bounded instead, `send`
+/// blocks while the queue is full, so a slow receiver holds up the senders
+/// rather than the queue growing without limit. One created with
+/// `with_backpressure` can shed messages instead, see [`Backpressure`].
+///
+/// Once the channel is closed with `close`, sending fails and receivers get a
+/// `Disconnected` error after taking any messages which were already queued,
+/// rather than blocking forever. Closing it with `shutdown` instead throws the
+/// queued messages away, and receivers get a `Closed` error straight away.
 impl<T> SimpleChannel<T> {
     pub fn new() -> Self {
+        Self::bounded(usize::MAX, Backpressure::Block)
+    }
+
+    /// Create a bounded channel which holds at most `capacity` messages.
+    ///
+    /// Panics:
+    /// If `capacity` is zero, as no message could ever be sent
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_backpressure(capacity, Backpressure::Block)
+    }
+
+    /// Create a bounded channel which holds at most `capacity` messages, and
+    /// applies `backpressure` when a sender finds it full.
+    ///
+    /// Panics:
+    /// If `capacity` is zero, as no message could ever be sent
+    pub fn with_backpressure(capacity: usize, backpressure: Backpressure) -> Self {
+        assert!(
+            capacity > 0,
+            "SimpleChannel needs a capacity of at least one"
+        );
+        Self::bounded(capacity, backpressure)
+    }
+
+    fn bounded(capacity: usize, backpressure: Backpressure) -> Self {
         Self {
-            queue: Mutex::new(VecDeque::new()),
-            ready: Condvar::new()
+            queue: Mutex::new(Queue {
+                messages: VecDeque::new(),
+                closed: false,
+                shut_down: false,
+                receive_wakers: Vec::new(),
+                send_wakers: Vec::new(),
+                waiting_receivers: 0,
+                parked_senders: VecDeque::new(),
+                #[cfg(feature = "stats")]
+                stats: Stats::new(),
+            }),
+            ready: Condvar::new(),
+            capacity,
+            backpressure,
+        }
+    }
+
+    /// Returns the message back as an error if the channel is closed, including
+    /// while waiting for room in a full queue.
+    ///
+    /// Senders blocked on a full queue are let in first come, first served.
+    /// Each waits in line until those which blocked before it have sent, and
+    /// a sender arriving while any are waiting joins the back of the line
+    /// even if there is room, so a busy producer can't keep taking the places
+    /// freed up and starve the rest.
+    ///
+    /// That is with the default [`Backpressure::Block`]. Under any other
+    /// policy `send` never blocks, and a message which doesn't fit is dropped,
+    /// or replaces the oldest, or is handed back as an error.
+    pub fn send(&self, message: T) -> Result<(), SendError<T>> {
+        let (mut q, _) = self.wait_for_room(self.queue.lock().unwrap(), None);
+        if q.closed {
+            #[cfg(feature = "stats")]
+            q.stats.dropped(1);
+            return Err(SendError(message, q.shut_down));
+        }
+        if q.messages.len() >= self.capacity {
+            return self
+                .shed(&mut q, message)
+                .map_err(|message| SendError(message, false));
+        }
+        self.push(&mut q, message);
+        Ok(())
+    }
+
+    /// Like `send`, but gives up once `timeout` has passed without room for
+    /// the message, handing it back so the caller can bound how long it waits
+    /// on a slow receiver. A policy other than `Block` applies as for `send`,
+    /// without waiting at all, with [`Backpressure::Error`] timing out.
+    pub fn send_timeout(&self, message: T, timeout: Duration) -> Result<(), SendTimeoutError<T>> {
+        // A timeout too long to represent may as well be forever.
+        let Some(deadline) = Instant::now().checked_add(timeout) else {
+            return self.send(message).map_err(|SendError(message, shut_down)| {
+                if shut_down {
+                    SendTimeoutError::Closed(message)
+                } else {
+                    SendTimeoutError::Disconnected(message)
+                }
+            });
+        };
+        let (mut q, in_time) = self.wait_for_room(self.queue.lock().unwrap(), Some(deadline));
+        if q.closed {
+            #[cfg(feature = "stats")]
+            q.stats.dropped(1);
+            return Err(if q.shut_down {
+                SendTimeoutError::Closed(message)
+            } else {
+                SendTimeoutError::Disconnected(message)
+            });
+        }
+        if !in_time {
+            return Err(SendTimeoutError::Timeout(message));
+        }
+        if q.messages.len() >= self.capacity {
+            return self
+                .shed(&mut q, message)
+                .map_err(SendTimeoutError::Timeout);
+        }
+        self.push(&mut q, message);
+        Ok(())
+    }
+
+    /// Like `send`, but rather than blocking when the queue is full, hands the
+    /// message straight back so the caller can drop it or try again later.
+    /// This doesn't jump the line either, so it fails while any sender is
+    /// blocked waiting for room. The [`Backpressure`] policy doesn't apply.
+    pub fn try_send(&self, message: T) -> Result<(), TrySendError<T>> {
+        self.try_send_or_register(message, None)
+    }
+
+    // Like `try_send`, but when the queue is full also registers `waker` to be
+    // woken once there is room, while still holding the lock, so a message
+    // taken in between can't be missed.
+    fn try_send_or_register(
+        &self,
+        message: T,
+        waker: Option<&Waker>,
+    ) -> Result<(), TrySendError<T>> {
+        let mut q = self.queue.lock().unwrap();
+        if q.closed {
+            #[cfg(feature = "stats")]
+            q.stats.dropped(1);
+            return Err(if q.shut_down {
+                TrySendError::Closed(message)
+            } else {
+                TrySendError::Disconnected(message)
+            });
+        }
+        if q.messages.len() >= self.capacity || !q.parked_senders.is_empty() {
+            if let Some(waker) = waker {
+                register(&mut q.send_wakers, waker);
+            }
+            return Err(TrySendError::Full(message));
+        }
+        self.push(&mut q, message);
+        Ok(())
+    }
+
+    /// Send every message from `messages` while taking the lock once, rather
+    /// than once per message, and waking receivers once. A bounded channel
+    /// which fills up part way through blocks as `send` does, releasing the
+    /// lock, until there's room for the rest. It waits at the back of the line
+    /// each time, as `send` would, so other senders get their turn in between.
+    /// Under a policy other than `Block`, the messages which don't fit are
+    /// each dealt with as `send` would, except that with
+    /// [`Backpressure::Error`] all of them are handed back together.
+    ///
+    /// The iterator is advanced with the lock held, so it should be cheap,
+    /// and must not use this channel. If the channel is closed, the messages
+    /// which weren't sent are returned, with any left in the iterator.
+    pub fn send_all<I>(&self, messages: I) -> Result<(), SendError<Vec<T>>>
+    where
+        I: IntoIterator<Item = T>,
+    {
+        let mut messages = messages.into_iter().peekable();
+        let mut q = self.queue.lock().unwrap();
+        loop {
+            q = self.wait_for_room(q, None).0;
+            if q.closed {
+                let unsent: Vec<T> = messages.collect();
+                #[cfg(feature = "stats")]
+                q.stats.dropped(unsent.len());
+                return Err(SendError(unsent, q.shut_down));
+            }
+            let before = q.messages.len();
+            while q.messages.len() < self.capacity {
+                match messages.next() {
+                    Some(message) => q.messages.push_back(message),
+                    None => break,
+                }
+            }
+            if q.messages.len() > before {
+                #[cfg(feature = "stats")]
+                {
+                    let depth = q.messages.len();
+                    q.stats.sent(depth - before, depth);
+                }
+                // Several receivers may be able to take one each.
+                self.notify_receivers(&q, q.messages.len() - before);
+                q.wake_receivers();
+            }
+            if messages.peek().is_none() {
+                self.notify_senders(&mut q);
+                return Ok(());
+            }
+            match self.backpressure {
+                Backpressure::Block => {}
+                Backpressure::Error => return Err(SendError(messages.collect(), false)),
+                Backpressure::DropNewest | Backpressure::DropOldest => {
+                    for message in messages {
+                        // Can't fail, only `Error` hands messages back.
+                        let _ = self.shed(&mut q, message);
+                    }
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    // Applies the backpressure policy to a message which doesn't fit in the
+    // full queue, handing it back if the policy is to refuse it. `Block` has
+    // already waited for room, so never gets here.
+    fn shed(&self, q: &mut Queue<T>, message: T) -> Result<(), T> {
+        match self.backpressure {
+            Backpressure::Block => unreachable!("a blocking sender waits for room"),
+            Backpressure::Error => return Err(message),
+            Backpressure::DropNewest => drop(message),
+            Backpressure::DropOldest => {
+                q.messages.pop_front();
+                self.push(q, message);
+            }
+        }
+        #[cfg(feature = "stats")]
+        q.stats.dropped(1);
+        Ok(())
+    }
+
+    // Queues a message, which the caller has checked there is room for, and
+    // wakes a receiver to take it.
+    fn push(&self, q: &mut Queue<T>, message: T) {
+        q.messages.push_back(message);
+        #[cfg(feature = "stats")]
+        q.stats.sent(1, q.messages.len());
+        self.notify_receivers(q, 1);
+        q.wake_receivers();
+        // A sender let in from the line may have left room for the next one.
+        self.notify_senders(q);
+    }
+
+    // Wakes up to `count` threads blocked waiting for a message, but no more
+    // than are waiting, so there's no system call at all when none are.
+    fn notify_receivers(&self, q: &Queue<T>, count: usize) {
+        for _ in 0..count.min(q.waiting_receivers) {
+            self.ready.notify_one();
+        }
+    }
+
+    // Wakes the sender at the front of the line if there is room for it. It
+    // passes the turn on once it has sent, so freeing several places at once
+    // still only needs this one wakeup. Tasks are only woken once the line is
+    // empty, as `try_send` won't get in before then.
+    fn notify_senders(&self, q: &mut Queue<T>) {
+        if q.messages.len() < self.capacity {
+            match q.parked_senders.front() {
+                Some(turn) => turn.notify_one(),
+                None => q.wake_senders(),
+            }
+        }
+    }
+
+    // Waits on `ready` for up to `timeout`, or for as long as it takes.
+    fn wait_for_message<'a>(
+        &self,
+        mut q: MutexGuard<'a, Queue<T>>,
+        timeout: Option<Duration>,
+    ) -> MutexGuard<'a, Queue<T>> {
+        q.waiting_receivers += 1;
+        let mut q = match timeout {
+            Some(timeout) => self.ready.wait_timeout(q, timeout).unwrap().0,
+            None => self.ready.wait(q).unwrap(),
+        };
+        q.waiting_receivers -= 1;
+        q
+    }
+
+    // Waits until there is room to send, or the channel is closed, or
+    // `deadline` passes, returning whether it was in time. A sender only gets
+    // in straight away if nobody is waiting already, otherwise it joins the
+    // back of the line and waits to reach the front with room free, counting
+    // the time as blocked with the `stats` feature.
+    fn wait_for_room<'a>(
+        &self,
+        mut q: MutexGuard<'a, Queue<T>>,
+        deadline: Option<Instant>,
+    ) -> (MutexGuard<'a, Queue<T>>, bool) {
+        // The length check is never true for an unbounded channel, which can't
+        // hold usize::MAX messages in memory anyway. Only `Block` ever waits,
+        // so under other policies nobody is in line either.
+        if q.closed
+            || self.backpressure != Backpressure::Block
+            || (q.parked_senders.is_empty() && q.messages.len() < self.capacity)
+        {
+            return (q, true);
+        }
+        #[cfg(feature = "stats")]
+        let started = Instant::now();
+        let turn = Arc::new(Condvar::new());
+        q.parked_senders.push_back(Arc::clone(&turn));
+        let in_time = loop {
+            let first = Arc::ptr_eq(&q.parked_senders[0], &turn);
+            if q.closed || (first && q.messages.len() < self.capacity) {
+                break true;
+            }
+            q = match deadline {
+                None => turn.wait(q).unwrap(),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        break false;
+                    }
+                    turn.wait_timeout(q, deadline - now).unwrap().0
+                }
+            };
+        };
+        // Closing wakes everyone in line, so we may not be at the front.
+        let position = q
+            .parked_senders
+            .iter()
+            .position(|t| Arc::ptr_eq(t, &turn))
+            .unwrap();
+        q.parked_senders.remove(position);
+        if !in_time {
+            // We may have been woken for room we're not going to use.
+            self.notify_senders(&mut q);
         }
+        #[cfg(feature = "stats")]
+        q.stats.blocked(started.elapsed());
+        (q, in_time)
     }
 
-    pub fn send(&self, message: T) {
-        self.queue.lock().unwrap().push_back(message);
-        // Wake up the blocked thread which is doing the receive.
-        self.ready.notify_one();
+    // Takes the next message, if any, letting a sender blocked on a full queue
+    // know there is room now.
+    fn pop(&self, q: &mut Queue<T>) -> Option<T> {
+        let message = q.messages.pop_front()?;
+        #[cfg(feature = "stats")]
+        q.stats.received(1);
+        self.notify_senders(q);
+        Some(message)
     }
 
-    pub fn receive(&self) -> T {
+    /// Blocks until a message arrives, or returns [`RecvError::Disconnected`]
+    /// once the channel is closed and every queued message has been taken, or
+    /// [`RecvError::Closed`] once it has been shut down.
+    pub fn receive(&self) -> Result<T, RecvError> {
         let mut q = self.queue.lock().unwrap();
         loop {
-            if let Some(message) = q.pop_front() {
-                return message;
+            if let Some(message) = self.pop(&mut q) {
+                return Ok(message);
+            }
+            if q.closed {
+                return Err(q.closed_error());
             }
             // Atomically unlock the mutex and wait for notification through
             // the [`Condvar`].
             // This means our mutex isn't locked for the entire duration of a
             // blocking receive if there are no messages in the channel.
-            q = self.ready.wait(q).unwrap();
+            q = self.wait_for_message(q, None);
+        }
+    }
+
+    /// Block until at least one message arrives, then move up to `max` of those
+    /// queued onto the end of `messages` while holding the lock once. Returns
+    /// how many were moved, or an error as `receive` does.
+    /// With a `max` of zero, nothing is moved and this returns straight away.
+    pub fn recv_many(&self, messages: &mut Vec<T>, max: usize) -> Result<usize, RecvError> {
+        if max == 0 {
+            return Ok(0);
+        }
+        let mut q = self.queue.lock().unwrap();
+        while q.messages.is_empty() {
+            if q.closed {
+                return Err(q.closed_error());
+            }
+            q = self.wait_for_message(q, None);
+        }
+        let count = max.min(q.messages.len());
+        messages.extend(q.messages.drain(..count));
+        #[cfg(feature = "stats")]
+        q.stats.received(count);
+        self.notify_senders(&mut q);
+        Ok(count)
+    }
+
+    /// Take every queued message at once, without blocking. The iterator owns
+    /// the messages, so the lock is released before it is used.
+    pub fn drain(&self) -> Drain<T> {
+        let mut q = self.queue.lock().unwrap();
+        let messages = std::mem::take(&mut q.messages);
+        #[cfg(feature = "stats")]
+        q.stats.received(messages.len());
+        if !messages.is_empty() {
+            self.notify_senders(&mut q);
+        }
+        Drain {
+            messages: messages.into_iter(),
+        }
+    }
+
+    // The async equivalent of `receive`, returning `None` once the channel is
+    // closed and drained.
+    #[cfg(feature = "futures")]
+    fn poll_receive(&self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let mut q = self.queue.lock().unwrap();
+        if let Some(message) = self.pop(&mut q) {
+            return Poll::Ready(Some(message));
+        }
+        if q.closed {
+            return Poll::Ready(None);
+        }
+        register(&mut q.receive_wakers, cx.waker());
+        Poll::Pending
+    }
+
+    /// Take a message if one is waiting, otherwise return `None` straight away
+    /// rather than blocking. Use `is_closed` to tell whether any more can come.
+    pub fn try_recv(&self) -> Option<T> {
+        self.pop(&mut self.queue.lock().unwrap())
+    }
+
+    /// Like `receive`, but gives up once `timeout` has passed without a message
+    /// arriving, so the caller can get on with something else.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        // A timeout too long to represent may as well be forever.
+        let Some(deadline) = Instant::now().checked_add(timeout) else {
+            return self.receive().map_err(RecvTimeoutError::from);
+        };
+        let mut q = self.queue.lock().unwrap();
+        loop {
+            if let Some(message) = self.pop(&mut q) {
+                return Ok(message);
+            }
+            if q.closed {
+                return Err(q.closed_error().into());
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(RecvTimeoutError::Timeout);
+            }
+            // Wakeups can be spurious, or another receiver may take the message
+            // first, so this loops with whatever time is left.
+            q = self.wait_for_message(q, Some(deadline - now));
+        }
+    }
+
+    /// Look at the next message without taking it, if one is waiting. The
+    /// returned [`Peek`] derefs to the message, and can `take` it if the
+    /// caller decides it wants it after all.
+    ///
+    /// The channel stays locked while the `Peek` is held, so every other
+    /// sender and receiver waits on it, and it should be dropped quickly.
+    pub fn peek(&self) -> Option<Peek<'_, T>> {
+        let q = self.queue.lock().unwrap();
+        if q.messages.is_empty() {
+            return None;
+        }
+        Some(Peek {
+            channel: self,
+            queue: q,
+            taken: false,
+        })
+    }
+
+    /// Like `peek`, but waits for up to `timeout` for a message to arrive, as
+    /// `recv_timeout` does.
+    pub fn peek_timeout(&self, timeout: Duration) -> Result<Peek<'_, T>, RecvTimeoutError> {
+        // A timeout too long to represent may as well be forever.
+        let deadline = Instant::now().checked_add(timeout);
+        let mut q = self.queue.lock().unwrap();
+        loop {
+            if !q.messages.is_empty() {
+                return Ok(Peek {
+                    channel: self,
+                    queue: q,
+                    taken: false,
+                });
+            }
+            if q.closed {
+                return Err(q.closed_error().into());
+            }
+            q = match deadline {
+                None => self.wait_for_message(q, None),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(RecvTimeoutError::Timeout);
+                    }
+                    self.wait_for_message(q, Some(deadline - now))
+                }
+            };
+        }
+    }
+
+    /// Close the channel, so that no more messages can be sent. Messages which
+    /// are already queued can still be received, after which receiving returns
+    /// a `Disconnected` error. Closing more than once does nothing.
+    pub fn close(&self) {
+        self.close_locked(&mut self.queue.lock().unwrap());
+    }
+
+    /// Close the channel and throw away every queued message in one step, so
+    /// nothing is received after this returns. Blocked senders and receivers
+    /// are all woken, which lets a pipeline be torn down from any stage
+    /// without a separate stop flag or a sentinel message for each consumer.
+    ///
+    /// From then on every receive fails with [`RecvError::Closed`], rather
+    /// than the `Disconnected` of a channel which was closed and drained, so
+    /// a consumer can tell that it was stopped with work still outstanding.
+    /// Sends fail just as they do after `close`, handing the message back, but
+    /// with an error which says the channel was shut down.
+    pub fn shutdown(&self) {
+        let messages = {
+            let mut q = self.queue.lock().unwrap();
+            let messages = std::mem::take(&mut q.messages);
+            #[cfg(feature = "stats")]
+            q.stats.dropped(messages.len());
+            q.shut_down = true;
+            self.close_locked(&mut q);
+            messages
+        };
+        // Dropped once the lock is released, in case a message's drop uses
+        // the channel.
+        drop(messages);
+    }
+
+    // `close`, for callers already holding the lock.
+    fn close_locked(&self, q: &mut Queue<T>) {
+        q.closed = true;
+        // Every waiting thread has to find out, not just one.
+        self.ready.notify_all();
+        for turn in &q.parked_senders {
+            turn.notify_one();
+        }
+        q.wake_receivers();
+        q.wake_senders();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.queue.lock().unwrap().closed
+    }
+
+    // Closes the channel once nothing will receive from it any more, counting
+    // the messages left in the queue as dropped.
+    fn abandon(&self) {
+        self.close();
+        // Nothing can be sent after the close, so the count can't go stale.
+        #[cfg(feature = "stats")]
+        {
+            let mut q = self.queue.lock().unwrap();
+            let queued = q.messages.len();
+            q.stats.dropped(queued);
+        }
+    }
+
+    /// Snapshot of the channel's counters, see [`ChannelMetrics`].
+    #[cfg(feature = "stats")]
+    pub fn metrics(&self) -> ChannelMetrics {
+        self.queue.lock().unwrap().stats.snapshot()
+    }
+
+    /// The number of messages queued right now. Other threads may send or
+    /// receive straight after, so this is only a snapshot, good for metrics
+    /// and heuristics such as batch sizes but not for deciding whether a
+    /// `receive` would block.
+    pub fn len(&self) -> usize {
+        self.queue.lock().unwrap().messages.len()
+    }
+
+    /// Whether no messages are queued right now, with the same caveat as
+    /// `len`.
+    pub fn is_empty(&self) -> bool {
+        self.queue.lock().unwrap().messages.is_empty()
+    }
+
+    /// The most messages the channel can hold, or `None` if it is unbounded.
+    pub fn capacity(&self) -> Option<usize> {
+        (self.capacity != usize::MAX).then_some(self.capacity)
+    }
+
+    /// An iterator which blocks for each message in turn, and ends once the
+    /// channel is closed and drained, as `receive` would error.
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter { channel: self }
+    }
+}
+
+impl<T> Default for SimpleChannel<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Lets a consumer loop be written as `for message in &channel { ... }`, see
+/// [`SimpleChannel::iter`].
+impl<'a, T> IntoIterator for &'a SimpleChannel<T> {
+    type Item = T;
+    type IntoIter = Iter<'a, T>;
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+pub struct Iter<'a, T> {
+    channel: &'a SimpleChannel<T>,
+}
+
+impl<T> Iterator for Iter<'_, T> {
+    type Item = T;
+    fn next(&mut self) -> Option<T> {
+        self.channel.receive().ok()
+    }
+}
+
+/// The next message in a channel, left in place, from [`SimpleChannel::peek`].
+pub struct Peek<'a, T> {
+    channel: &'a SimpleChannel<T>,
+    // Holding the lock keeps the message at the front of the queue.
+    queue: MutexGuard<'a, Queue<T>>,
+    taken: bool,
+}
+
+impl<T> Peek<'_, T> {
+    /// Take the message after all, as `receive` would have.
+    pub fn take(mut self) -> T {
+        self.taken = true;
+        let channel = self.channel;
+        channel.pop(&mut self.queue).unwrap()
+    }
+}
+
+impl<T> Deref for Peek<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // A Peek is only made while there is a message.
+        self.queue.messages.front().unwrap()
+    }
+}
+
+impl<T> Drop for Peek<'_, T> {
+    fn drop(&mut self) {
+        // `peek_timeout` may have used up the wakeup meant for a receiver,
+        // so pass it on as the message is still there.
+        if !self.taken {
+            self.channel.notify_receivers(&self.queue, 1);
+        }
+    }
+}
+
+/// The messages taken by [`SimpleChannel::drain`], in the order they were sent.
+pub struct Drain<T> {
+    messages: vec_deque::IntoIter<T>,
+}
+
+impl<T> Iterator for Drain<T> {
+    type Item = T;
+    fn next(&mut self) -> Option<T> {
+        self.messages.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.messages.size_hint()
+    }
+}
+
+impl<T> ExactSizeIterator for Drain<T> {}
+
+/// Create an unbounded channel as a [`Sender`] and [`Receiver`] pair.
+///
+/// Sharing a [`SimpleChannel`] directly lets every thread both send and
+/// receive, and nothing closes it when the producers are done. Here the
+/// `Sender` can be cloned for each producer and the `Receiver` for each
+/// consumer, and the channel closes itself once every `Sender` is dropped, or
+/// every `Receiver` is.
+///
+/// Cloned receivers share the one queue, each message going to whichever
+/// receiver takes it first, so a pool of workers can pull jobs from it and
+/// an idle worker picks up the next job as soon as it is free.
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    split(SimpleChannel::new())
+}
+
+/// Like [`channel`], but bounded as with [`SimpleChannel::with_capacity`].
+pub fn channel_with_capacity<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    split(SimpleChannel::with_capacity(capacity))
+}
+
+/// Like [`channel`], but bounded as with [`SimpleChannel::with_backpressure`].
+pub fn channel_with_backpressure<T>(
+    capacity: usize,
+    backpressure: Backpressure,
+) -> (Sender<T>, Receiver<T>) {
+    split(SimpleChannel::with_backpressure(capacity, backpressure))
+}
+
+fn split<T>(channel: SimpleChannel<T>) -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Shared {
+        channel,
+        senders: AtomicUsize::new(1),
+        receivers: AtomicUsize::new(1),
+    });
+    (
+        Sender {
+            shared: Arc::clone(&shared),
+        },
+        Receiver { shared },
+    )
+}
+
+struct Shared<T> {
+    channel: SimpleChannel<T>,
+    // The number of Senders and Receivers, so the last of either to be
+    // dropped can close the channel.
+    senders: AtomicUsize,
+    receivers: AtomicUsize,
+}
+
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    /// See [`SimpleChannel::send`], this fails once every `Receiver` is
+    /// dropped.
+    pub fn send(&self, message: T) -> Result<(), SendError<T>> {
+        self.shared.channel.send(message)
+    }
+
+    pub fn try_send(&self, message: T) -> Result<(), TrySendError<T>> {
+        self.shared.channel.try_send(message)
+    }
+
+    pub fn send_timeout(&self, message: T, timeout: Duration) -> Result<(), SendTimeoutError<T>> {
+        self.shared.channel.send_timeout(message, timeout)
+    }
+
+    /// See [`SimpleChannel::send_all`].
+    pub fn send_all<I>(&self, messages: I) -> Result<(), SendError<Vec<T>>>
+    where
+        I: IntoIterator<Item = T>,
+    {
+        self.shared.channel.send_all(messages)
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.shared.channel.is_closed()
+    }
+
+    /// See [`SimpleChannel::shutdown`]. This closes the channel for every
+    /// handle, not only this one, even while other senders are still around.
+    pub fn shutdown(&self) {
+        self.shared.channel.shutdown();
+    }
+
+    #[cfg(feature = "stats")]
+    pub fn metrics(&self) -> ChannelMetrics {
+        self.shared.channel.metrics()
+    }
+
+    /// Create a [`WeakSender`] for this channel, which doesn't count towards
+    /// keeping it open.
+    pub fn downgrade(&self) -> WeakSender<T> {
+        WeakSender {
+            shared: Arc::downgrade(&self.shared),
+        }
+    }
+
+    #[cfg(feature = "futures")]
+    pub(crate) fn try_send_or_register(
+        &self,
+        message: T,
+        waker: &Waker,
+    ) -> Result<(), TrySendError<T>> {
+        self.shared
+            .channel
+            .try_send_or_register(message, Some(waker))
+    }
+
+    pub fn len(&self) -> usize {
+        self.shared.channel.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shared.channel.is_empty()
+    }
+
+    pub fn capacity(&self) -> Option<usize> {
+        self.shared.channel.capacity()
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::Relaxed);
+        Self {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        // Closing takes the mutex, which orders it with everything else, so
+        // the count itself only needs to be exact.
+        if self.shared.senders.fetch_sub(1, Ordering::Relaxed) == 1 {
+            self.shared.channel.close();
+        }
+    }
+}
+
+/// A handle to a channel which can't send, but can be upgraded to a [`Sender`]
+/// while at least one other `Sender` is still around.
+///
+/// Unlike a `Sender`, holding one doesn't stop the channel closing once every
+/// `Sender` has been dropped, so a long-lived registry of handles won't keep
+/// a channel open after all of its real producers are gone.
+pub struct WeakSender<T> {
+    shared: Weak<Shared<T>>,
+}
+
+impl<T> WeakSender<T> {
+    /// Returns a new `Sender`, or `None` if every `Sender` has been dropped,
+    /// as the channel is closed by then.
+    pub fn upgrade(&self) -> Option<Sender<T>> {
+        let shared = self.shared.upgrade()?;
+        // Only count another sender while there is one already, so a closed
+        // channel is never handed out again.
+        let mut senders = shared.senders.load(Ordering::Relaxed);
+        loop {
+            if senders == 0 {
+                return None;
+            }
+            match shared.senders.compare_exchange_weak(
+                senders,
+                senders + 1,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return Some(Sender { shared }),
+                Err(current) => senders = current,
+            }
+        }
+    }
+}
+
+impl<T> Clone for WeakSender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: Weak::clone(&self.shared),
+        }
+    }
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Receiver<T> {
+    /// See [`SimpleChannel::receive`], this returns
+    /// [`RecvError::Disconnected`] once every `Sender` has been dropped and
+    /// the queue is drained, or [`RecvError::Closed`] after a `shutdown`.
+    pub fn receive(&self) -> Result<T, RecvError> {
+        self.shared.channel.receive()
+    }
+
+    pub fn try_recv(&self) -> Option<T> {
+        self.shared.channel.try_recv()
+    }
+
+    #[cfg(feature = "futures")]
+    pub(crate) fn poll_receive(&self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        self.shared.channel.poll_receive(cx)
+    }
+
+    /// See [`SimpleChannel::recv_many`].
+    pub fn recv_many(&self, messages: &mut Vec<T>, max: usize) -> Result<usize, RecvError> {
+        self.shared.channel.recv_many(messages, max)
+    }
+
+    pub fn drain(&self) -> Drain<T> {
+        self.shared.channel.drain()
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        self.shared.channel.recv_timeout(timeout)
+    }
+
+    /// See [`SimpleChannel::shutdown`]. As with [`Sender::shutdown`], this
+    /// closes the channel for every handle.
+    pub fn shutdown(&self) {
+        self.shared.channel.shutdown();
+    }
+
+    #[cfg(feature = "stats")]
+    pub fn metrics(&self) -> ChannelMetrics {
+        self.shared.channel.metrics()
+    }
+
+    /// See [`SimpleChannel::peek`].
+    pub fn peek(&self) -> Option<Peek<'_, T>> {
+        self.shared.channel.peek()
+    }
+
+    pub fn peek_timeout(&self, timeout: Duration) -> Result<Peek<'_, T>, RecvTimeoutError> {
+        self.shared.channel.peek_timeout(timeout)
+    }
+
+    pub fn iter(&self) -> Iter<'_, T> {
+        self.shared.channel.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.shared.channel.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shared.channel.is_empty()
+    }
+
+    pub fn capacity(&self) -> Option<usize> {
+        self.shared.channel.capacity()
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        self.shared.receivers.fetch_add(1, Ordering::Relaxed);
+        Self {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        // Once the last receiver is gone nothing can receive any more, so make
+        // further sends fail rather than queue messages nobody will read.
+        if self.shared.receivers.fetch_sub(1, Ordering::Relaxed) == 1 {
+            self.shared.channel.abandon();
+        }
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Receiver<T> {
+    type Item = T;
+    type IntoIter = Iter<'a, T>;
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+impl<T> IntoIterator for Receiver<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+    fn into_iter(self) -> IntoIter<T> {
+        IntoIter { receiver: self }
+    }
+}
+
+/// Like [`Iter`], but owning the `Receiver`.
+pub struct IntoIter<T> {
+    receiver: Receiver<T>,
+}
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = T;
+    fn next(&mut self) -> Option<T> {
+        self.receiver.receive().ok()
+    }
+}
+
+/// What a bounded channel does when a sender finds it full, set with
+/// [`SimpleChannel::with_backpressure`].
+///
+/// Blocking slows producers down to the pace of the consumer, which is right
+/// when every message matters. For telemetry and the like, where a stale
+/// message is worth less than a stalled producer, the channel can shed load
+/// instead. Messages shed are counted as dropped with the `stats` feature.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Backpressure {
+    /// Wait for room, in line behind any other blocked senders.
+    #[default]
+    Block,
+    /// Drop the message being sent, keeping what is already queued.
+    DropNewest,
+    /// Drop the oldest queued message to make room for the new one, so the
+    /// receiver always gets the most recent.
+    DropOldest,
+    /// Hand the message back to the sender as an error.
+    Error,
+}
+
+/// Returned by [`SimpleChannel::send`] with the message, as the channel is
+/// closed, or is full with [`Backpressure::Error`].
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct SendError<T>(pub T, pub(crate) bool);
+
+impl<T> SendError<T> {
+    /// Whether the channel was shut down with [`SimpleChannel::shutdown`],
+    /// rather than closed, as [`RecvError::Closed`] tells a receiver.
+    pub fn is_shut_down(&self) -> bool {
+        self.1
+    }
+
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+// Written out rather than derived so that `T` doesn't need to be `Debug`, which
+// `Error` requires, as the message is left out anyway.
+impl<T> fmt::Debug for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SendError(..)")
+    }
+}
+
+impl<T> fmt::Display for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_shut_down() {
+            f.write_str("sending on a shut down channel")
+        } else {
+            f.write_str("sending on a closed or full channel")
+        }
+    }
+}
+
+impl<T> Error for SendError<T> {}
+
+/// Returned by [`SimpleChannel::try_send`] with the message which couldn't be
+/// sent.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum TrySendError<T> {
+    /// The queue is at capacity.
+    Full(T),
+    /// The channel is closed.
+    Disconnected(T),
+    /// The channel was shut down with [`SimpleChannel::shutdown`].
+    Closed(T),
+}
+
+impl<T> TrySendError<T> {
+    pub fn into_inner(self) -> T {
+        match self {
+            TrySendError::Full(message)
+            | TrySendError::Disconnected(message)
+            | TrySendError::Closed(message) => message,
+        }
+    }
+}
+
+// As for SendError, the message is left out.
+impl<T> fmt::Debug for TrySendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrySendError::Full(_) => f.write_str("Full(..)"),
+            TrySendError::Disconnected(_) => f.write_str("Disconnected(..)"),
+            TrySendError::Closed(_) => f.write_str("Closed(..)"),
+        }
+    }
+}
+
+impl<T> fmt::Display for TrySendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrySendError::Full(_) => f.write_str("the channel is full"),
+            TrySendError::Disconnected(_) => f.write_str("sending on a closed channel"),
+            TrySendError::Closed(_) => f.write_str("sending on a shut down channel"),
+        }
+    }
+}
+
+impl<T> Error for TrySendError<T> {}
+
+/// Returned by [`SimpleChannel::send_timeout`] with the message which couldn't
+/// be sent.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SendTimeoutError<T> {
+    /// The queue stayed full for the whole timeout.
+    Timeout(T),
+    /// The channel is closed.
+    Disconnected(T),
+    /// The channel was shut down with [`SimpleChannel::shutdown`].
+    Closed(T),
+}
+
+impl<T> SendTimeoutError<T> {
+    pub fn into_inner(self) -> T {
+        match self {
+            SendTimeoutError::Timeout(message)
+            | SendTimeoutError::Disconnected(message)
+            | SendTimeoutError::Closed(message) => message,
+        }
+    }
+}
+
+// As for SendError, the message is left out.
+impl<T> fmt::Debug for SendTimeoutError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendTimeoutError::Timeout(_) => f.write_str("Timeout(..)"),
+            SendTimeoutError::Disconnected(_) => f.write_str("Disconnected(..)"),
+            SendTimeoutError::Closed(_) => f.write_str("Closed(..)"),
+        }
+    }
+}
+
+impl<T> fmt::Display for SendTimeoutError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendTimeoutError::Timeout(_) => f.write_str("timed out waiting for room to send"),
+            SendTimeoutError::Disconnected(_) => f.write_str("sending on a closed channel"),
+            SendTimeoutError::Closed(_) => f.write_str("sending on a shut down channel"),
+        }
+    }
+}
+
+impl<T> Error for SendTimeoutError<T> {}
+
+/// Returned by [`SimpleChannel::receive`] once no message will ever arrive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecvError {
+    /// The channel is closed and every queued message has been taken.
+    Disconnected,
+    /// The channel was shut down with [`SimpleChannel::shutdown`], and any
+    /// messages still queued were thrown away.
+    Closed,
+}
+
+impl fmt::Display for RecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecvError::Disconnected => f.write_str("the channel is closed and empty"),
+            RecvError::Closed => f.write_str("the channel was shut down"),
+        }
+    }
+}
+
+impl Error for RecvError {}
+
+/// Returned by [`SimpleChannel::recv_timeout`] when no message arrives in time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecvTimeoutError {
+    Timeout,
+    /// As for [`RecvError::Disconnected`].
+    Disconnected,
+    /// As for [`RecvError::Closed`].
+    Closed,
+}
+
+impl From<RecvError> for RecvTimeoutError {
+    fn from(error: RecvError) -> Self {
+        match error {
+            RecvError::Disconnected => RecvTimeoutError::Disconnected,
+            RecvError::Closed => RecvTimeoutError::Closed,
+        }
+    }
+}
+
+impl fmt::Display for RecvTimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecvTimeoutError::Timeout => f.write_str("timed out waiting for a message"),
+            RecvTimeoutError::Disconnected => f.write_str("the channel is closed and empty"),
+            RecvTimeoutError::Closed => f.write_str("the channel was shut down"),
+        }
+    }
+}
+
+impl Error for RecvTimeoutError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    // Spins until `count` senders are parked, so each test can line them up
+    // in a known order.
+    fn wait_for_parked<T>(channel: &SimpleChannel<T>, count: usize) {
+        while channel.queue.lock().unwrap().parked_senders.len() != count {
+            thread::yield_now();
+        }
+    }
+
+    // The same, for receivers blocked waiting for a message.
+    fn wait_for_receivers<T>(channel: &SimpleChannel<T>, count: usize) {
+        while channel.queue.lock().unwrap().waiting_receivers != count {
+            thread::yield_now();
         }
     }
+
+    #[test]
+    fn blocked_senders_are_admitted_in_arrival_order() {
+        let channel = SimpleChannel::with_capacity(1);
+        channel.send(0).unwrap();
+        thread::scope(|s| {
+            for i in 1..=8 {
+                let channel = &channel;
+                s.spawn(move || channel.send(i).unwrap());
+                wait_for_parked(channel, i);
+            }
+            let received: Vec<_> = (0..=8).map(|_| channel.receive().unwrap()).collect();
+            assert_eq!(received, (0..=8).collect::<Vec<_>>());
+        });
+    }
+
+    #[test]
+    fn greedy_sender_cannot_overtake_the_line() {
+        let channel = SimpleChannel::with_capacity(2);
+        channel.send_all([0, 0]).unwrap();
+        thread::scope(|s| {
+            s.spawn(|| channel.send(1).unwrap());
+            wait_for_parked(&channel, 1);
+            // Keeps sending for as long as it can, which before would often
+            // take every place freed and leave the other sender waiting.
+            s.spawn(|| {
+                for _ in 0..100 {
+                    channel.send(2).unwrap();
+                }
+            });
+            wait_for_parked(&channel, 2);
+            let received: Vec<_> = (0..4).map(|_| channel.receive().unwrap()).collect();
+            assert_eq!(received, [0, 0, 1, 2]);
+            for _ in 0..99 {
+                assert_eq!(channel.receive(), Ok(2));
+            }
+        });
+    }
+
+    #[test]
+    fn try_send_waits_its_turn() {
+        let channel = SimpleChannel::with_capacity(1);
+        channel.send(0).unwrap();
+        thread::scope(|s| {
+            s.spawn(|| channel.send(1).unwrap());
+            wait_for_parked(&channel, 1);
+            assert_eq!(channel.receive(), Ok(0));
+            // Whether or not the parked sender has taken the room yet, it is
+            // its turn, not ours.
+            assert!(matches!(channel.try_send(2), Err(TrySendError::Full(2))));
+            assert_eq!(channel.receive(), Ok(1));
+        });
+    }
+
+    #[test]
+    fn timed_out_sender_passes_its_turn_on() {
+        let channel = SimpleChannel::with_capacity(1);
+        channel.send(0).unwrap();
+        thread::scope(|s| {
+            let first = s.spawn(|| channel.send_timeout(1, Duration::from_millis(50)));
+            wait_for_parked(&channel, 1);
+            s.spawn(|| channel.send(2).unwrap());
+            wait_for_parked(&channel, 2);
+            assert!(matches!(
+                first.join().unwrap(),
+                Err(SendTimeoutError::Timeout(1))
+            ));
+            assert_eq!(channel.receive(), Ok(0));
+            assert_eq!(channel.receive(), Ok(2));
+        });
+    }
+
+    #[test]
+    fn backpressure_sheds_instead_of_blocking() {
+        let newest = SimpleChannel::with_backpressure(2, Backpressure::DropNewest);
+        let oldest = SimpleChannel::with_backpressure(2, Backpressure::DropOldest);
+        let error = SimpleChannel::with_backpressure(2, Backpressure::Error);
+        for i in 0..4 {
+            newest.send(i).unwrap();
+            oldest.send(i).unwrap();
+        }
+        error.send_all([0, 1]).unwrap();
+        assert_eq!(error.send(2), Err(SendError(2, false)));
+        assert_eq!(error.send_all([3, 4]), Err(SendError(vec![3, 4], false)));
+        assert_eq!(newest.drain().collect::<Vec<_>>(), [0, 1]);
+        assert_eq!(oldest.drain().collect::<Vec<_>>(), [2, 3]);
+        assert_eq!(error.drain().collect::<Vec<_>>(), [0, 1]);
+    }
+
+    #[test]
+    fn shutdown_discards_queued_messages_and_wakes_everyone() {
+        let (sender, receiver) = channel_with_capacity(1);
+        sender.send(0).unwrap();
+        thread::scope(|s| {
+            let blocked = s.spawn(|| sender.send(1));
+            wait_for_parked(&receiver.shared.channel, 1);
+            receiver.shutdown();
+            assert_eq!(blocked.join().unwrap(), Err(SendError(1, true)));
+        });
+        assert_eq!(receiver.receive(), Err(RecvError::Closed));
+        assert_eq!(sender.send(2), Err(SendError(2, true)));
+
+        let (sender, receiver) = channel::<i32>();
+        let other = receiver.clone();
+        thread::scope(|s| {
+            let waiting = [s.spawn(|| receiver.receive()), s.spawn(|| other.receive())];
+            while sender
+                .shared
+                .channel
+                .queue
+                .lock()
+                .unwrap()
+                .waiting_receivers
+                != 2
+            {
+                thread::yield_now();
+            }
+            sender.shutdown();
+            for receiver in waiting {
+                assert_eq!(receiver.join().unwrap(), Err(RecvError::Closed));
+            }
+        });
+    }
+
+    #[test]
+    fn shutdown_is_told_apart_from_close() {
+        let channel = SimpleChannel::new();
+        channel.send(0).unwrap();
+        channel.close();
+        assert_eq!(channel.receive(), Ok(0));
+        assert_eq!(channel.receive(), Err(RecvError::Disconnected));
+        assert_eq!(
+            channel.recv_timeout(Duration::ZERO),
+            Err(RecvTimeoutError::Disconnected)
+        );
+        let error = channel.send(1).unwrap_err();
+        assert!(!error.is_shut_down());
+        assert_eq!(channel.try_send(2), Err(TrySendError::Disconnected(2)));
+        assert_eq!(
+            channel.send_timeout(3, Duration::ZERO),
+            Err(SendTimeoutError::Disconnected(3))
+        );
+
+        let channel = SimpleChannel::new();
+        channel.send(0).unwrap();
+        channel.shutdown();
+        assert_eq!(channel.receive(), Err(RecvError::Closed));
+        assert_eq!(
+            channel.recv_timeout(Duration::ZERO),
+            Err(RecvTimeoutError::Closed)
+        );
+        let mut messages = Vec::new();
+        assert_eq!(channel.recv_many(&mut messages, 1), Err(RecvError::Closed));
+        assert!(messages.is_empty());
+        let error = channel.send(1).unwrap_err();
+        assert!(error.is_shut_down());
+        assert_eq!(error.into_inner(), 1);
+        assert_eq!(channel.try_send(2), Err(TrySendError::Closed(2)));
+        assert_eq!(
+            channel.send_timeout(3, Duration::ZERO),
+            Err(SendTimeoutError::Closed(3))
+        );
+        assert_eq!(channel.send_all([4, 5]), Err(SendError(vec![4, 5], true)));
+    }
+
+    #[test]
+    fn close_wakes_every_blocked_sender() {
+        let channel = SimpleChannel::with_capacity(1);
+        channel.send(0).unwrap();
+        thread::scope(|s| {
+            let senders: Vec<_> = (1..=4)
+                .map(|i| {
+                    let channel = &channel;
+                    let sender = s.spawn(move || channel.send(i));
+                    wait_for_parked(channel, i);
+                    sender
+                })
+                .collect();
+            channel.close();
+            for (i, sender) in (1..=4).zip(senders) {
+                assert_eq!(sender.join().unwrap(), Err(SendError(i, false)));
+            }
+        });
+        assert!(channel.queue.lock().unwrap().parked_senders.is_empty());
+    }
+
+    #[test]
+    fn dropped_peek_passes_the_wakeup_on() {
+        // Which of the two is woken by the send is up to the condvar, so try
+        // it enough times for the peek to take the wakeup in some of them.
+        for _ in 0..50 {
+            let channel = SimpleChannel::new();
+            thread::scope(|s| {
+                let peeker = s.spawn(|| channel.peek_timeout(Duration::from_secs(60)).map(drop));
+                let receiver = s.spawn(|| channel.receive());
+                wait_for_receivers(&channel, 2);
+                channel.send(1).unwrap();
+                // The peek leaves the message in place, so the receive has to
+                // be woken again once it is dropped.
+                assert_eq!(receiver.join().unwrap(), Ok(1));
+                channel.close();
+                assert!(matches!(
+                    peeker.join().unwrap(),
+                    Ok(()) | Err(RecvTimeoutError::Disconnected)
+                ));
+            });
+        }
+    }
+
+    #[test]
+    fn every_blocked_receiver_gets_a_message() {
+        const RECEIVERS: usize = 8;
+        let channel = SimpleChannel::new();
+        thread::scope(|s| {
+            let receivers: Vec<_> = (0..RECEIVERS)
+                .map(|_| s.spawn(|| channel.receive()))
+                .collect();
+            wait_for_receivers(&channel, RECEIVERS);
+            // One wakeup goes out per message, and none may be lost to a
+            // receiver which was woken for a message another already took.
+            for i in 0..RECEIVERS {
+                channel.send(i).unwrap();
+            }
+            let mut received: Vec<_> = receivers
+                .into_iter()
+                .map(|receiver| receiver.join().unwrap().unwrap())
+                .collect();
+            received.sort();
+            assert_eq!(received, (0..RECEIVERS).collect::<Vec<_>>());
+        });
+        assert_eq!(channel.queue.lock().unwrap().waiting_receivers, 0);
+    }
 }
@@ -0,0 +1,304 @@
+use std::{
+    error::Error,
+    fmt,
+    ops::{Deref, DerefMut},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use crate::simple::{self, RecvError, SimpleChannel};
+
+/// Create a channel for large byte payloads, backed by a pool of `slots`
+/// buffers of `slot_size` bytes each, as a [`Sender`] and [`Receiver`] pair.
+///
+/// Sending a large value through a queue copies it at least once on the way in
+/// and again on the way out. Here every buffer is allocated up front and only
+/// ever moved by pointer. A sender `reserve`s a free buffer, writes the
+/// payload straight into it through the [`SlotGuard`], and sends it. The
+/// receiver reads it in place through the [`Frame`], and dropping the frame
+/// puts the buffer back in the pool for the next `reserve`.
+///
+/// Once every buffer is in flight, `reserve` blocks until a frame is dropped,
+/// so the pool also bounds how far senders can get ahead of the receiver.
+///
+/// Panics:
+/// If `slots` is zero, as nothing could ever be reserved
+pub fn channel(slots: usize, slot_size: usize) -> (Sender, Receiver) {
+    assert!(slots > 0, "a slot channel needs at least one slot");
+    let pool = SimpleChannel::with_capacity(slots);
+    for _ in 0..slots {
+        // Can't fail, the pool has room for every buffer and isn't closed.
+        let _ = pool.send(vec![0; slot_size].into_boxed_slice());
+    }
+    let shared = Arc::new(Shared {
+        queue: SimpleChannel::new(),
+        pool,
+        slot_size,
+        senders: AtomicUsize::new(1),
+    });
+    (
+        Sender {
+            shared: Arc::clone(&shared),
+        },
+        Receiver { shared },
+    )
+}
+
+struct Shared {
+    // Filled buffers on their way to the receiver, with the length written.
+    queue: SimpleChannel<(Box<[u8]>, usize)>,
+    // Free buffers. Never more than the number of slots, so never full.
+    pool: SimpleChannel<Box<[u8]>>,
+    slot_size: usize,
+    senders: AtomicUsize,
+}
+
+impl Shared {
+    // Puts a buffer back for the next `reserve`. Once the receiver is gone the
+    // pool is closed, and the buffer is freed instead.
+    fn release(&self, buffer: Box<[u8]>) {
+        let _ = self.pool.send(buffer);
+    }
+}
+
+pub struct Sender {
+    shared: Arc<Shared>,
+}
+
+impl Sender {
+    /// Take a free buffer from the pool to write a payload into, blocking
+    /// while every buffer is in use. Returns [`SendError::Disconnected`] once
+    /// the `Receiver` has been dropped.
+    pub fn reserve(&self) -> Result<SlotGuard, SendError> {
+        // The pool is closed along with the receiver, but may still hold
+        // buffers, so the queue says whether there's any point.
+        if self.shared.queue.is_closed() {
+            return Err(SendError::Disconnected);
+        }
+        let buffer = self
+            .shared
+            .pool
+            .receive()
+            .map_err(|_| SendError::Disconnected)?;
+        Ok(SlotGuard {
+            shared: Arc::clone(&self.shared),
+            buffer: Some(buffer),
+        })
+    }
+
+    /// The size of every buffer in the pool.
+    pub fn slot_size(&self) -> usize {
+        self.shared.slot_size
+    }
+}
+
+impl Clone for Sender {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::Relaxed);
+        Self {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+impl Drop for Sender {
+    fn drop(&mut self) {
+        if self.shared.senders.fetch_sub(1, Ordering::Relaxed) == 1 {
+            self.shared.queue.close();
+        }
+    }
+}
+
+/// A buffer reserved from the pool by [`Sender::reserve`], derefing to the
+/// whole slot to write a payload into. A buffer is reused without being
+/// cleared, so it holds whatever the last payload left behind.
+///
+/// Dropping the guard without sending gives the buffer back to the pool.
+pub struct SlotGuard {
+    shared: Arc<Shared>,
+    // Only `None` once sent.
+    buffer: Option<Box<[u8]>>,
+}
+
+impl SlotGuard {
+    /// Send the first `len` bytes of the slot to the receiver, without
+    /// copying them. Returns [`SendError::Disconnected`] if the `Receiver` has
+    /// been dropped since the slot was reserved.
+    ///
+    /// Panics:
+    /// If `len` is larger than the slot
+    pub fn send(mut self, len: usize) -> Result<(), SendError> {
+        let buffer = self.buffer.take().unwrap();
+        assert!(len <= buffer.len(), "frame is larger than the slot");
+        self.shared
+            .queue
+            .send((buffer, len))
+            .map_err(|simple::SendError((buffer, _), _)| {
+                self.shared.release(buffer);
+                SendError::Disconnected
+            })
+    }
+}
+
+impl Deref for SlotGuard {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        self.buffer.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for SlotGuard {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        self.buffer.as_mut().unwrap()
+    }
+}
+
+impl Drop for SlotGuard {
+    fn drop(&mut self) {
+        if let Some(buffer) = self.buffer.take() {
+            self.shared.release(buffer);
+        }
+    }
+}
+
+pub struct Receiver {
+    shared: Arc<Shared>,
+}
+
+impl Receiver {
+    /// Block until a frame arrives, or return [`RecvError::Disconnected`] once
+    /// every `Sender` has been dropped and every frame has been taken.
+    pub fn receive(&self) -> Result<Frame, RecvError> {
+        let (buffer, len) = self.shared.queue.receive()?;
+        Ok(self.frame(buffer, len))
+    }
+
+    /// Take a frame if one is waiting, without blocking.
+    pub fn try_recv(&self) -> Option<Frame> {
+        let (buffer, len) = self.shared.queue.try_recv()?;
+        Some(self.frame(buffer, len))
+    }
+
+    fn frame(&self, buffer: Box<[u8]>, len: usize) -> Frame {
+        Frame {
+            shared: Arc::clone(&self.shared),
+            buffer: Some(buffer),
+            len,
+        }
+    }
+}
+
+impl Drop for Receiver {
+    fn drop(&mut self) {
+        // Closing the pool wakes any sender blocked in `reserve`, and makes
+        // buffers handed back from now on be freed.
+        self.shared.queue.close();
+        self.shared.pool.close();
+    }
+}
+
+/// A payload sent through the channel, derefing to the bytes which were
+/// written. Dropping it gives the buffer back to the pool.
+pub struct Frame {
+    shared: Arc<Shared>,
+    // Only `None` while being dropped.
+    buffer: Option<Box<[u8]>>,
+    len: usize,
+}
+
+impl Deref for Frame {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        &self.buffer.as_ref().unwrap()[..self.len]
+    }
+}
+
+impl DerefMut for Frame {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.buffer.as_mut().unwrap()[..self.len]
+    }
+}
+
+impl Drop for Frame {
+    fn drop(&mut self) {
+        if let Some(buffer) = self.buffer.take() {
+            self.shared.release(buffer);
+        }
+    }
+}
+
+/// Returned by [`Sender::reserve`] and [`SlotGuard::send`] once nothing will
+/// ever receive the payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendError {
+    /// The `Receiver` has been dropped.
+    Disconnected,
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendError::Disconnected => f.write_str("the receiver has been dropped"),
+        }
+    }
+}
+
+impl Error for SendError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{thread, time::Duration};
+
+    #[test]
+    fn buffers_go_back_to_the_pool() {
+        let (sender, receiver) = channel(2, 16);
+        let mut slot = sender.reserve().unwrap();
+        let buffer = slot.as_ptr();
+        slot[..5].copy_from_slice(b"hello");
+        slot.send(5).unwrap();
+        assert_eq!(receiver.shared.pool.len(), 1);
+
+        let frame = receiver.receive().unwrap();
+        // The same buffer, read in place rather than copied.
+        assert_eq!(frame.as_ptr(), buffer);
+        assert_eq!(&*frame, b"hello");
+        drop(frame);
+        assert_eq!(receiver.shared.pool.len(), 2);
+
+        // As is one reserved but never sent.
+        drop(sender.reserve().unwrap());
+        assert_eq!(receiver.shared.pool.len(), 2);
+        assert!(receiver.try_recv().is_none());
+    }
+
+    #[test]
+    fn reserve_waits_for_a_frame_to_be_dropped() {
+        let (sender, receiver) = channel(1, 8);
+        sender.reserve().unwrap().send(8).unwrap();
+        let frame = receiver.receive().unwrap();
+        thread::scope(|s| {
+            let reserving = s.spawn(|| sender.reserve().is_ok());
+            thread::sleep(Duration::from_millis(20));
+            assert!(!reserving.is_finished());
+            drop(frame);
+            assert!(reserving.join().unwrap());
+        });
+    }
+
+    #[test]
+    fn reserve_unblocked_by_dropped_receiver() {
+        let (sender, receiver) = channel(1, 8);
+        let slot = sender.reserve().unwrap();
+        thread::scope(|s| {
+            let reserving = s.spawn(|| sender.reserve().err());
+            thread::sleep(Duration::from_millis(20));
+            assert!(!reserving.is_finished());
+            drop(receiver);
+            assert_eq!(reserving.join().unwrap(), Some(SendError::Disconnected));
+        });
+        assert_eq!(slot.send(8), Err(SendError::Disconnected));
+    }
+}
@@ -0,0 +1,86 @@
+//! Per-channel counters for [`crate::simple::SimpleChannel`].
+//!
+//! These are meant for wiring queue health into dashboards. A channel which is
+//! usually near its capacity, or whose senders spend a long time blocked,
+//! needs a faster consumer or more room, while a steady count of dropped
+//! messages means producers are outliving their consumer.
+
+use std::{fmt, time::Duration};
+
+/// Counters stored alongside each channel's queue, and so only ever updated
+/// with its lock held.
+pub(crate) struct Stats {
+    sent: u64,
+    received: u64,
+    dropped: u64,
+    max_depth: usize,
+    send_blocked: Duration,
+}
+
+impl Stats {
+    pub(crate) const fn new() -> Self {
+        Self {
+            sent: 0,
+            received: 0,
+            dropped: 0,
+            max_depth: 0,
+            send_blocked: Duration::ZERO,
+        }
+    }
+
+    /// Called once messages have been queued, with the queue's new length.
+    pub(crate) fn sent(&mut self, count: usize, depth: usize) {
+        self.sent += count as u64;
+        self.max_depth = self.max_depth.max(depth);
+    }
+
+    pub(crate) fn received(&mut self, count: usize) {
+        self.received += count as u64;
+    }
+
+    pub(crate) fn dropped(&mut self, count: usize) {
+        self.dropped += count as u64;
+    }
+
+    /// Called after a sender has waited for room in a full queue.
+    pub(crate) fn blocked(&mut self, waited: Duration) {
+        self.send_blocked += waited;
+    }
+
+    pub(crate) fn snapshot(&self) -> ChannelMetrics {
+        ChannelMetrics {
+            sent: self.sent,
+            received: self.received,
+            dropped: self.dropped,
+            max_depth: self.max_depth,
+            send_blocked: self.send_blocked,
+        }
+    }
+}
+
+/// Snapshot of the counters for one channel since it was created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelMetrics {
+    /// Messages queued, by any of the sending methods.
+    pub sent: u64,
+    /// Messages taken, by any of the receiving methods.
+    pub received: u64,
+    /// Messages refused because the channel was closed, and those still
+    /// queued when the last `Receiver` was dropped. Messages shed by a
+    /// [`crate::simple::Backpressure`] policy are counted here too.
+    pub dropped: u64,
+    /// The most messages queued at once.
+    pub max_depth: usize,
+    /// Total time senders spent waiting for room in a full queue.
+    pub send_blocked: Duration,
+}
+
+impl fmt::Display for ChannelMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} sent, {} received, {} dropped, max depth {}, senders blocked for {:?}",
+            self.sent, self.received, self.dropped, self.max_depth, self.send_blocked
+        )
+    }
+}
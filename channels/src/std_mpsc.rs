@@ -0,0 +1,284 @@
+use std::{fmt, time::Duration};
+
+use crate::{rendezvous, simple};
+
+// The error types are std's own, so code which names or matches on them keeps
+// working unchanged.
+pub use std::sync::mpsc::{RecvError, RecvTimeoutError, SendError, TryRecvError, TrySendError};
+
+/// Create an unbounded channel, as [`std::sync::mpsc::channel`] does, backed by
+/// [`crate::simple::channel`].
+///
+/// Everything in this module has the same name and signature as its
+/// counterpart in `std::sync::mpsc`, and the same behaviour, so existing code
+/// can switch over by changing its imports alone.
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let (sender, receiver) = simple::channel();
+    (
+        Sender { inner: sender },
+        Receiver {
+            flavor: Flavor::Simple(receiver),
+        },
+    )
+}
+
+/// Create a bounded channel, as [`std::sync::mpsc::sync_channel`] does, backed
+/// by [`crate::simple::channel_with_capacity`].
+///
+/// As with std, a `bound` of zero makes a rendezvous channel, where `send`
+/// blocks until the receiver takes the message, which is backed by
+/// [`crate::rendezvous::channel`].
+pub fn sync_channel<T>(bound: usize) -> (SyncSender<T>, Receiver<T>) {
+    if bound == 0 {
+        let (sender, receiver) = rendezvous::channel();
+        (
+            SyncSender {
+                flavor: Flavor::Rendezvous(sender),
+            },
+            Receiver {
+                flavor: Flavor::Rendezvous(receiver),
+            },
+        )
+    } else {
+        let (sender, receiver) = simple::channel_with_capacity(bound);
+        (
+            SyncSender {
+                flavor: Flavor::Simple(sender),
+            },
+            Receiver {
+                flavor: Flavor::Simple(receiver),
+            },
+        )
+    }
+}
+
+// A bounded channel is a rendezvous one when its bound is zero, which
+// `simple` can't do.
+enum Flavor<S, R> {
+    Simple(S),
+    Rendezvous(R),
+}
+
+pub struct Sender<T> {
+    inner: simple::Sender<T>,
+}
+
+impl<T> Sender<T> {
+    pub fn send(&self, t: T) -> Result<(), SendError<T>> {
+        self.inner
+            .send(t)
+            .map_err(|simple::SendError(t, _)| SendError(t))
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sender").finish_non_exhaustive()
+    }
+}
+
+pub struct SyncSender<T> {
+    flavor: Flavor<simple::Sender<T>, rendezvous::Sender<T>>,
+}
+
+impl<T> SyncSender<T> {
+    pub fn send(&self, t: T) -> Result<(), SendError<T>> {
+        match &self.flavor {
+            Flavor::Simple(sender) => sender.send(t),
+            Flavor::Rendezvous(sender) => sender.send(t),
+        }
+        .map_err(|simple::SendError(t, _)| SendError(t))
+    }
+
+    pub fn try_send(&self, t: T) -> Result<(), TrySendError<T>> {
+        match &self.flavor {
+            Flavor::Simple(sender) => sender.try_send(t),
+            Flavor::Rendezvous(sender) => sender.try_send(t),
+        }
+        .map_err(|e| match e {
+            simple::TrySendError::Full(t) => TrySendError::Full(t),
+            simple::TrySendError::Disconnected(t) | simple::TrySendError::Closed(t) => {
+                TrySendError::Disconnected(t)
+            }
+        })
+    }
+}
+
+impl<T> Clone for SyncSender<T> {
+    fn clone(&self) -> Self {
+        let flavor = match &self.flavor {
+            Flavor::Simple(sender) => Flavor::Simple(sender.clone()),
+            Flavor::Rendezvous(sender) => Flavor::Rendezvous(sender.clone()),
+        };
+        Self { flavor }
+    }
+}
+
+impl<T> fmt::Debug for SyncSender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SyncSender").finish_non_exhaustive()
+    }
+}
+
+pub struct Receiver<T> {
+    flavor: Flavor<simple::Receiver<T>, rendezvous::Receiver<T>>,
+}
+
+impl<T> Receiver<T> {
+    pub fn recv(&self) -> Result<T, RecvError> {
+        match &self.flavor {
+            Flavor::Simple(receiver) => receiver.receive(),
+            Flavor::Rendezvous(receiver) => receiver.receive(),
+        }
+        // std's channels can't be shut down, so there is only one way to fail.
+        .map_err(|_| RecvError)
+    }
+
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        // A zero timeout checks for a message and for the channel being closed
+        // under the one lock, so a message sent just before the last sender
+        // drops is never mistaken for a disconnect.
+        self.recv_timeout(Duration::ZERO).map_err(|e| match e {
+            RecvTimeoutError::Timeout => TryRecvError::Empty,
+            RecvTimeoutError::Disconnected => TryRecvError::Disconnected,
+        })
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        match &self.flavor {
+            Flavor::Simple(receiver) => receiver.recv_timeout(timeout),
+            Flavor::Rendezvous(receiver) => receiver.recv_timeout(timeout),
+        }
+        .map_err(|e| match e {
+            simple::RecvTimeoutError::Timeout => RecvTimeoutError::Timeout,
+            simple::RecvTimeoutError::Disconnected | simple::RecvTimeoutError::Closed => {
+                RecvTimeoutError::Disconnected
+            }
+        })
+    }
+
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter { rx: self }
+    }
+
+    pub fn try_iter(&self) -> TryIter<'_, T> {
+        TryIter { rx: self }
+    }
+}
+
+impl<T> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Receiver").finish_non_exhaustive()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Receiver<T> {
+    type Item = T;
+    type IntoIter = Iter<'a, T>;
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+impl<T> IntoIterator for Receiver<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+    fn into_iter(self) -> IntoIter<T> {
+        IntoIter { rx: self }
+    }
+}
+
+/// Blocks for each message in turn, ending once every sender has been dropped
+/// and the channel is drained, see [`Receiver::iter`].
+#[derive(Debug)]
+pub struct Iter<'a, T: 'a> {
+    rx: &'a Receiver<T>,
+}
+
+impl<T> Iterator for Iter<'_, T> {
+    type Item = T;
+    fn next(&mut self) -> Option<T> {
+        self.rx.recv().ok()
+    }
+}
+
+/// Takes the messages already waiting, without blocking, see
+/// [`Receiver::try_iter`].
+#[derive(Debug)]
+pub struct TryIter<'a, T: 'a> {
+    rx: &'a Receiver<T>,
+}
+
+impl<T> Iterator for TryIter<'_, T> {
+    type Item = T;
+    fn next(&mut self) -> Option<T> {
+        self.rx.try_recv().ok()
+    }
+}
+
+/// Like [`Iter`], but owning the `Receiver`.
+#[derive(Debug)]
+pub struct IntoIter<T> {
+    rx: Receiver<T>,
+}
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = T;
+    fn next(&mut self) -> Option<T> {
+        self.rx.recv().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn sync_channel_zero_hands_off_directly() {
+        let (sender, receiver) = sync_channel(0);
+        // Nobody is waiting to receive, and there is no room to queue it.
+        assert_eq!(sender.try_send(1), Err(TrySendError::Full(1)));
+        assert_eq!(receiver.try_recv(), Err(TryRecvError::Empty));
+        thread::scope(|s| {
+            let sending = s.spawn(|| sender.send(2));
+            thread::sleep(Duration::from_millis(20));
+            assert!(!sending.is_finished());
+            assert_eq!(receiver.recv(), Ok(2));
+            assert_eq!(sending.join().unwrap(), Ok(()));
+        });
+        drop(sender);
+        assert_eq!(receiver.try_recv(), Err(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn sync_channel_fills_up_to_its_bound() {
+        let (sender, receiver) = sync_channel(1);
+        assert_eq!(sender.try_send(1), Ok(()));
+        assert_eq!(sender.try_send(2), Err(TrySendError::Full(2)));
+        assert_eq!(receiver.recv(), Ok(1));
+        drop(receiver);
+        assert_eq!(sender.try_send(3), Err(TrySendError::Disconnected(3)));
+        assert_eq!(sender.send(4), Err(SendError(4)));
+    }
+
+    #[test]
+    fn try_recv_tells_empty_from_disconnected() {
+        let (sender, receiver) = channel();
+        assert_eq!(receiver.try_recv(), Err(TryRecvError::Empty));
+        sender.send(1).unwrap();
+        drop(sender);
+        // Queued messages still arrive after the last sender has gone.
+        assert_eq!(receiver.try_recv(), Ok(1));
+        assert_eq!(receiver.try_recv(), Err(TryRecvError::Disconnected));
+        assert_eq!(receiver.recv(), Err(RecvError));
+    }
+}
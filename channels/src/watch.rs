@@ -0,0 +1,223 @@
+use std::{
+    error::Error,
+    fmt,
+    sync::{
+        atomic::{AtomicU32, AtomicUsize, Ordering},
+        Arc, RwLock, RwLockReadGuard,
+    },
+};
+
+use spinlock::futex::{wait, wake_all};
+
+// The low bit of the version is set once every `Sender` has been dropped, the
+// rest counts the values sent, so a receiver can wait on both at once.
+const CLOSED: u32 = 1;
+const SENT: u32 = 2;
+
+/// A channel holding only the latest value, for state such as configuration or
+/// status where a receiver only cares about the current value, not every one
+/// in between.
+///
+/// Senders overwrite the value in place, rather than queueing it, so sending
+/// never blocks on a slow receiver and there is no backlog to work through.
+/// Receivers can read the latest value at any time with `borrow`, or block in
+/// `changed` until a new one is sent. Both halves can be cloned.
+pub fn channel<T>(initial: T) -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Shared {
+        value: RwLock::new(initial),
+        version: AtomicU32::new(0),
+        senders: AtomicUsize::new(1),
+    });
+    (
+        Sender {
+            shared: Arc::clone(&shared),
+        },
+        Receiver { shared, seen: 0 },
+    )
+}
+
+struct Shared<T> {
+    value: RwLock<T>,
+    version: AtomicU32,
+    senders: AtomicUsize,
+}
+
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Replace the value, waking every receiver waiting in `changed`.
+    pub fn send(&self, value: T) {
+        let mut guard = self.shared.value.write().unwrap();
+        *guard = value;
+        // Bumped under the lock, so a reader holding it always sees the version
+        // matching the value, see `Receiver::borrow_and_update`.
+        self.shared.version.fetch_add(SENT, Ordering::Release);
+        drop(guard);
+        wake_all(&self.shared.version);
+    }
+
+    pub fn borrow(&self) -> RwLockReadGuard<'_, T> {
+        self.shared.value.read().unwrap()
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::Relaxed);
+        Self {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        if self.shared.senders.fetch_sub(1, Ordering::Relaxed) == 1 {
+            // No more values can be sent, so waiting receivers need to stop.
+            self.shared.version.fetch_or(CLOSED, Ordering::Release);
+            wake_all(&self.shared.version);
+        }
+    }
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+    // The version of the last value this receiver was told about.
+    seen: u32,
+}
+
+impl<T> Receiver<T> {
+    /// Read the latest value. The value can't be replaced while the guard is
+    /// held, so it should be dropped quickly.
+    pub fn borrow(&self) -> RwLockReadGuard<'_, T> {
+        self.shared.value.read().unwrap()
+    }
+
+    /// Like `borrow`, but also marks the value as seen, so `changed` only
+    /// returns once a newer one is sent.
+    pub fn borrow_and_update(&mut self) -> RwLockReadGuard<'_, T> {
+        // Taking the lock first means the version can't move on before we
+        // record it.
+        let guard = self.shared.value.read().unwrap();
+        self.seen = self.shared.version.load(Ordering::Acquire) & !CLOSED;
+        guard
+    }
+
+    /// Whether a value has been sent since this receiver last saw one.
+    pub fn has_changed(&self) -> bool {
+        self.shared.version.load(Ordering::Relaxed) & !CLOSED != self.seen
+    }
+
+    /// Block until a value newer than the last one seen is sent, marking it as
+    /// seen, then read it with `borrow`. Returns [`RecvError::Disconnected`]
+    /// once every `Sender` is dropped, as the value can't change any more.
+    pub fn changed(&mut self) -> Result<(), RecvError> {
+        loop {
+            let version = self.shared.version.load(Ordering::Acquire);
+            if version & !CLOSED != self.seen {
+                self.seen = version & !CLOSED;
+                return Ok(());
+            }
+            if version & CLOSED != 0 {
+                return Err(RecvError::Disconnected);
+            }
+            // Only sleeps if nothing has been sent, and no sender dropped,
+            // since the load above.
+            wait(&self.shared.version, version);
+        }
+    }
+}
+
+// A clone starts out having seen the same values as the original.
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: Arc::clone(&self.shared),
+            seen: self.seen,
+        }
+    }
+}
+
+/// Returned by [`Receiver::changed`] once the value can no longer change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecvError {
+    /// Every `Sender` has been dropped.
+    Disconnected,
+}
+
+impl fmt::Display for RecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecvError::Disconnected => f.write_str("every sender has been dropped"),
+        }
+    }
+}
+
+impl Error for RecvError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{thread, time::Duration};
+
+    #[test]
+    fn changed_waits_for_a_new_value() {
+        let (sender, mut receiver) = channel(0);
+        thread::scope(|s| {
+            let mut waiting = receiver.clone();
+            let waiter = s.spawn(move || {
+                waiting.changed().unwrap();
+                *waiting.borrow()
+            });
+            thread::sleep(Duration::from_millis(20));
+            assert!(!waiter.is_finished());
+            sender.send(1);
+            assert_eq!(waiter.join().unwrap(), 1);
+        });
+
+        // Values sent in between are skipped, only the latest is seen.
+        sender.send(2);
+        sender.send(3);
+        assert!(receiver.has_changed());
+        assert_eq!(receiver.changed(), Ok(()));
+        assert_eq!(*receiver.borrow(), 3);
+        assert!(!receiver.has_changed());
+    }
+
+    #[test]
+    fn borrow_and_update_marks_the_value_seen() {
+        let (sender, mut receiver) = channel("first");
+        sender.send("second");
+        assert_eq!(*receiver.borrow_and_update(), "second");
+        assert!(!receiver.has_changed());
+        drop(sender);
+        assert_eq!(receiver.changed(), Err(RecvError::Disconnected));
+    }
+
+    #[test]
+    fn changed_woken_by_the_last_sender_dropped() {
+        let (sender, mut receiver) = channel(0);
+        let other = sender.clone();
+        thread::scope(|s| {
+            let waiter = s.spawn(move || receiver.changed());
+            drop(other);
+            thread::sleep(Duration::from_millis(20));
+            // Another sender is left, which could still send.
+            assert!(!waiter.is_finished());
+            drop(sender);
+            assert_eq!(waiter.join().unwrap(), Err(RecvError::Disconnected));
+        });
+    }
+
+    #[test]
+    fn value_sent_before_closing_is_still_seen() {
+        let (sender, mut receiver) = channel(0);
+        sender.send(1);
+        drop(sender);
+        assert_eq!(receiver.changed(), Ok(()));
+        assert_eq!(*receiver.borrow(), 1);
+        assert_eq!(receiver.changed(), Err(RecvError::Disconnected));
+    }
+}
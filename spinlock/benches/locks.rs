@@ -0,0 +1,110 @@
+use std::{
+    hint::black_box,
+    thread,
+    time::{Duration, Instant},
+};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use spinlock::{word_lock::WordLock, SpinLock};
+
+// Number of lock/unlock pairs shared between all threads in a contended run.
+const CONTENDED_OPS: u64 = 10_000;
+
+/// Common interface over the locks being compared, so each benchmark is written
+/// once.
+trait Lock: Sync {
+    fn new(value: u64) -> Self;
+    fn increment(&self);
+}
+
+impl Lock for SpinLock<u64> {
+    fn new(value: u64) -> Self {
+        SpinLock::new(value)
+    }
+    fn increment(&self) {
+        *self.lock() += 1;
+    }
+}
+
+impl Lock for WordLock<u64> {
+    fn new(value: u64) -> Self {
+        WordLock::new(value)
+    }
+    fn increment(&self) {
+        *self.lock() += 1;
+    }
+}
+
+impl Lock for std::sync::Mutex<u64> {
+    fn new(value: u64) -> Self {
+        std::sync::Mutex::new(value)
+    }
+    fn increment(&self) {
+        *self.lock().unwrap() += 1;
+    }
+}
+
+impl Lock for parking_lot::Mutex<u64> {
+    fn new(value: u64) -> Self {
+        parking_lot::Mutex::new(value)
+    }
+    fn increment(&self) {
+        *self.lock() += 1;
+    }
+}
+
+fn uncontended<L: Lock>(c: &mut Criterion, name: &str) {
+    let lock = L::new(0);
+    c.bench_function(&format!("uncontended/{name}"), |b| {
+        b.iter(|| black_box(&lock).increment())
+    });
+}
+
+fn contended<L: Lock>(c: &mut Criterion, name: &str) {
+    let mut group = c.benchmark_group(format!("contended/{name}"));
+    group.throughput(Throughput::Elements(CONTENDED_OPS));
+    for threads in [2, 4, 8, 16, 32, 64] {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(threads),
+            &threads,
+            |b, &threads| {
+                b.iter_custom(|iters| {
+                    let mut total = Duration::ZERO;
+                    for _ in 0..iters {
+                        let lock = L::new(0);
+                        let start = Instant::now();
+                        thread::scope(|s| {
+                            for _ in 0..threads {
+                                s.spawn(|| {
+                                    for _ in 0..CONTENDED_OPS / threads {
+                                        lock.increment();
+                                    }
+                                });
+                            }
+                        });
+                        total += start.elapsed();
+                    }
+                    total
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+fn bench_uncontended(c: &mut Criterion) {
+    uncontended::<SpinLock<u64>>(c, "spinlock");
+    uncontended::<WordLock<u64>>(c, "word_lock");
+    uncontended::<std::sync::Mutex<u64>>(c, "std");
+    uncontended::<parking_lot::Mutex<u64>>(c, "parking_lot");
+}
+
+fn bench_contended(c: &mut Criterion) {
+    contended::<SpinLock<u64>>(c, "spinlock");
+    contended::<WordLock<u64>>(c, "word_lock");
+    contended::<std::sync::Mutex<u64>>(c, "std");
+    contended::<parking_lot::Mutex<u64>>(c, "parking_lot");
+}
+
+criterion_group!(benches, bench_uncontended, bench_contended);
+criterion_main!(benches);
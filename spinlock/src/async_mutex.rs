@@ -0,0 +1,504 @@
+use std::{
+    cell::UnsafeCell,
+    fmt,
+    future::Future,
+    marker::PhantomPinned,
+    ops::{Deref, DerefMut},
+    pin::Pin,
+    ptr::NonNull,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    task::{Context, Poll, Waker},
+};
+
+use crate::{LockedPlaceholder, SpinLock};
+
+/// A waiting task's entry in [`Waiters`], owned by the future that is waiting.
+///
+/// The entry lives inside the future itself, which is pinned while it is
+/// polled, so the list can link entries together by pointer without
+/// allocating. A future must remove its entry before it is dropped.
+pub(crate) struct Waiter {
+    // Only ever accessed while holding the lock around the `Waiters` list the
+    // entry is registered in.
+    node: UnsafeCell<Node>,
+    _pinned: PhantomPinned,
+}
+
+struct Node {
+    waker: Option<Waker>,
+    prev: Option<NonNull<Waiter>>,
+    next: Option<NonNull<Waiter>>,
+    queued: bool,
+}
+
+// The node is only touched with the list's lock held, whichever thread that is.
+unsafe impl Send for Waiter {}
+unsafe impl Sync for Waiter {}
+
+impl Waiter {
+    pub(crate) const fn new() -> Self {
+        Self {
+            node: UnsafeCell::new(Node {
+                waker: None,
+                prev: None,
+                next: None,
+                queued: false,
+            }),
+            _pinned: PhantomPinned,
+        }
+    }
+}
+
+/// Intrusive queue of tasks which are waiting to acquire the [`Mutex`].
+///
+/// Each waiting [`Lock`] future links its own [`Waiter`] into the list, so
+/// refreshing its [`Waker`] when polled and removing itself when it acquires
+/// the lock or is dropped are both constant time, however long the queue is.
+pub(crate) struct Waiters {
+    head: Option<NonNull<Waiter>>,
+    tail: Option<NonNull<Waiter>>,
+}
+
+// The pointers are only followed with the lock around the list held, and every
+// entry removes itself before its future is dropped.
+unsafe impl Send for Waiters {}
+
+impl Waiters {
+    pub(crate) const fn new() -> Self {
+        Self {
+            head: None,
+            tail: None,
+        }
+    }
+
+    /// Add `waiter` to the back of the queue, or refresh its waker if it is
+    /// already queued.
+    ///
+    /// # Safety
+    ///
+    /// `waiter` must be removed with [`Waiters::remove`] before it is dropped.
+    pub(crate) unsafe fn register(&mut self, waiter: Pin<&Waiter>, waker: &Waker) {
+        let ptr = NonNull::from(&*waiter);
+        let node = &mut *waiter.node.get();
+        match &mut node.waker {
+            Some(existing) if existing.will_wake(waker) => {}
+            slot => *slot = Some(waker.clone()),
+        }
+        if node.queued {
+            return;
+        }
+        node.queued = true;
+        node.prev = self.tail;
+        node.next = None;
+        match self.tail {
+            Some(tail) => (*tail.as_ref().node.get()).next = Some(ptr),
+            None => self.head = Some(ptr),
+        }
+        self.tail = Some(ptr);
+    }
+
+    /// Unlink `waiter` if it is queued.
+    pub(crate) fn remove(&mut self, waiter: Pin<&Waiter>) {
+        // Safety: we hold the list's lock, and a queued entry's neighbours are
+        // still alive as they remove themselves before being dropped.
+        unsafe {
+            let node = &mut *waiter.node.get();
+            if !node.queued {
+                return;
+            }
+            match node.prev {
+                Some(prev) => (*prev.as_ref().node.get()).next = node.next,
+                None => self.head = node.next,
+            }
+            match node.next {
+                Some(next) => (*next.as_ref().node.get()).prev = node.prev,
+                None => self.tail = node.prev,
+            }
+            node.queued = false;
+            node.prev = None;
+            node.next = None;
+            node.waker = None;
+        }
+    }
+
+    pub(crate) fn wake_front(&self) {
+        if let Some(head) = self.head {
+            // Safety: as in `remove`.
+            if let Some(waker) = unsafe { &(*head.as_ref().node.get()).waker } {
+                waker.wake_by_ref();
+            }
+        }
+    }
+
+    pub(crate) fn wake_all(&self) {
+        let mut cursor = self.head;
+        while let Some(waiter) = cursor {
+            // Safety: as in `remove`.
+            let node = unsafe { &*waiter.as_ref().node.get() };
+            if let Some(waker) = &node.waker {
+                waker.wake_by_ref();
+            }
+            cursor = node.next;
+        }
+    }
+}
+
+/// Implementation of an asynchronous mutex.
+///
+/// Rather than blocking the calling thread, [`Mutex::lock`] returns a [`Future`]
+/// which resolves to a [`MutexGuard`]. A task which cannot acquire the lock
+/// registers its [`Waker`] in a queue and returns [`Poll::Pending`], giving the
+/// executor thread back so that it can make progress on other tasks.
+///
+/// The queue itself is protected by a [`SpinLock`], which is only ever held for
+/// the very short time it takes to push, remove or wake an entry.
+///
+/// Unlocking wakes the task at the front of the queue, it is not removed from
+/// the queue until it has successfully acquired the lock. This means that if
+/// another task manages to take the lock first, the woken task keeps its place.
+pub struct Mutex<T> {
+    locked: AtomicBool,
+    waiters: SpinLock<Waiters>,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T> Sync for Mutex<T> where T: Send {}
+
+impl<T> Mutex<T> {
+    pub fn new(inner: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            waiters: SpinLock::new(Waiters::new()),
+            data: UnsafeCell::new(inner),
+        }
+    }
+
+    /// Acquire the lock asynchronously.
+    ///
+    /// The returned [`Lock`] future resolves to a [`MutexGuard`] which unlocks
+    /// the [`Mutex`] when dropped.
+    pub fn lock(&self) -> Lock<'_, T> {
+        Lock {
+            mutex: self,
+            waiter: Waiter::new(),
+            queued: false,
+        }
+    }
+
+    /// Acquire the lock asynchronously as an [`OwnedMutexGuard`], which keeps
+    /// the [`Mutex`] alive until it is dropped.
+    ///
+    /// As the guard has no lifetime tied to the lock, it can be held inside
+    /// spawned tasks and across `.await` points in `'static` futures.
+    pub async fn lock_owned(self: Arc<Self>) -> OwnedMutexGuard<T> {
+        // Ownership of the lock moves to the returned guard, so the borrowed
+        // guard must not unlock when it goes out of scope.
+        std::mem::forget(self.lock().await);
+        OwnedMutexGuard { mutex: self }
+    }
+
+    /// Attempt to acquire the lock without waiting.
+    ///
+    /// Returns `None` if the lock is currently held.
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        if self.try_acquire() {
+            Some(MutexGuard { mutex: self })
+        } else {
+            None
+        }
+    }
+
+    /// Whether the lock is currently held, see [`crate::SpinLock::is_locked`].
+    pub fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Relaxed)
+    }
+
+    /// Raw pointer to the underlying data, without taking the lock.
+    ///
+    /// Reading or writing through the pointer is only safe while the caller
+    /// otherwise guarantees exclusive access, such as by holding a [`MutexGuard`].
+    pub fn data_ptr(&self) -> *mut T {
+        self.data.get()
+    }
+
+    fn try_acquire(&self) -> bool {
+        !self.locked.swap(true, Ordering::Acquire)
+    }
+
+    fn unlock(&self) {
+        self.locked.store(false, Ordering::Release);
+        self.waiters.lock().wake_front();
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Mutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("Mutex");
+        match self.try_lock() {
+            Some(guard) => d.field("data", &&*guard),
+            None => d.field("data", &LockedPlaceholder),
+        };
+        d.finish_non_exhaustive()
+    }
+}
+
+/// Future returned by [`Mutex::lock`].
+pub struct Lock<'a, T> {
+    mutex: &'a Mutex<T>,
+    // This future's entry in the mutex's queue of waiters, see `Waiters`.
+    waiter: Waiter,
+    // Whether `waiter` is linked into the queue.
+    queued: bool,
+}
+
+impl<'a, T> Future for Lock<'a, T> {
+    type Output = MutexGuard<'a, T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // Safety: `waiter` is never moved out of, it stays pinned with us.
+        let this = unsafe { self.get_unchecked_mut() };
+        let waiter = unsafe { Pin::new_unchecked(&this.waiter) };
+        let mutex = this.mutex;
+        if mutex.try_acquire() {
+            if std::mem::take(&mut this.queued) {
+                mutex.waiters.lock().remove(waiter);
+            }
+            return Poll::Ready(MutexGuard { mutex });
+        }
+
+        let mut waiters = mutex.waiters.lock();
+        // The lock may have been released between the failed attempt above and
+        // taking the queue lock. The unlocking side only wakes a task after it
+        // has released the lock, and it must also take the queue lock to do so,
+        // so checking again here means a wakeup can never be missed.
+        if mutex.try_acquire() {
+            waiters.remove(waiter);
+            this.queued = false;
+            return Poll::Ready(MutexGuard { mutex });
+        }
+
+        // Safety: Drop removes the entry if it is still queued.
+        unsafe { waiters.register(waiter, cx.waker()) };
+        this.queued = true;
+        Poll::Pending
+    }
+}
+
+impl<T> Drop for Lock<'_, T> {
+    fn drop(&mut self) {
+        // A future which is dropped while waiting may have been the one that
+        // was just woken. Pass the wakeup along so it isn't lost.
+        if self.queued {
+            let mut waiters = self.mutex.waiters.lock();
+            // Safety: we are being dropped in place, having been pinned.
+            waiters.remove(unsafe { Pin::new_unchecked(&self.waiter) });
+            if !self.mutex.locked.load(Ordering::Relaxed) {
+                waiters.wake_front();
+            }
+        }
+    }
+}
+
+pub struct MutexGuard<'a, T> {
+    mutex: &'a Mutex<T>,
+}
+
+// Sharing a guard between threads shares `&T`, so this must only be allowed
+// when `T` itself can be shared.
+unsafe impl<T> Sync for MutexGuard<'_, T> where T: Sync {}
+
+impl<'a, T> MutexGuard<'a, T> {
+    /// Consume the guard without unlocking, see [`crate::Guard::leak`].
+    ///
+    /// Any tasks already waiting on the [`Mutex`] will wait forever.
+    pub fn leak(guard: Self) -> &'a mut T {
+        let mutex = guard.mutex;
+        std::mem::forget(guard);
+        unsafe { &mut *mutex.data.get() }
+    }
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // Existence of the guard implies we have an exclusive lock, so this is
+        // safe to do.
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // Existence of the guard implies we have an exclusive lock, so this is
+        // safe to do.
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.unlock();
+    }
+}
+
+/// A [`MutexGuard`] which owns a reference counted [`Mutex`] instead of
+/// borrowing it.
+pub struct OwnedMutexGuard<T> {
+    mutex: Arc<Mutex<T>>,
+}
+
+// Sharing a guard between threads shares `&T`, so this must only be allowed
+// when `T` itself can be shared.
+unsafe impl<T> Sync for OwnedMutexGuard<T> where T: Sync {}
+
+impl<T> Deref for OwnedMutexGuard<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // Existence of the guard implies we have an exclusive lock, so this is
+        // safe to do.
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T> DerefMut for OwnedMutexGuard<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // Existence of the guard implies we have an exclusive lock, so this is
+        // safe to do.
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T> Drop for OwnedMutexGuard<T> {
+    fn drop(&mut self) {
+        self.mutex.unlock();
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::task::Wake;
+
+    use super::*;
+
+    /// A waker which records whether it has been woken since last checked.
+    pub(crate) struct Flag(AtomicBool);
+
+    impl Wake for Flag {
+        fn wake(self: Arc<Self>) {
+            self.wake_by_ref();
+        }
+
+        fn wake_by_ref(self: &Arc<Self>) {
+            self.0.store(true, Ordering::Relaxed);
+        }
+    }
+
+    impl Flag {
+        pub(crate) fn new() -> (Arc<Self>, Waker) {
+            let flag = Arc::new(Flag(AtomicBool::new(false)));
+            (Arc::clone(&flag), Waker::from(flag))
+        }
+
+        pub(crate) fn take(&self) -> bool {
+            self.0.swap(false, Ordering::Relaxed)
+        }
+    }
+
+    pub(crate) fn poll<F: Future + ?Sized>(future: Pin<&mut F>, waker: &Waker) -> Poll<F::Output> {
+        future.poll(&mut Context::from_waker(waker))
+    }
+
+    fn queue_len<T>(mutex: &Mutex<T>) -> usize {
+        let waiters = mutex.waiters.lock();
+        let mut len = 0;
+        let mut cursor = waiters.head;
+        while let Some(waiter) = cursor {
+            len += 1;
+            cursor = unsafe { (*waiter.as_ref().node.get()).next };
+        }
+        len
+    }
+
+    #[test]
+    fn dropping_a_queued_lock_unlinks_it() {
+        let mutex = Mutex::new(0);
+        let guard = mutex.try_lock().unwrap();
+        let (_, waker) = Flag::new();
+        let mut first = Box::pin(mutex.lock());
+        let mut second = Box::pin(mutex.lock());
+        assert!(poll(first.as_mut(), &waker).is_pending());
+        assert!(poll(second.as_mut(), &waker).is_pending());
+        assert_eq!(queue_len(&mutex), 2);
+
+        // Drop the one at the front, then the unlock must only reach the one
+        // which is left, rather than follow a pointer into the dropped future.
+        drop(first);
+        assert_eq!(queue_len(&mutex), 1);
+        drop(guard);
+        let Poll::Ready(mut guard) = poll(second.as_mut(), &waker) else {
+            panic!("the remaining waiter could not take the lock");
+        };
+        *guard += 1;
+        drop(guard);
+        drop(second);
+        assert_eq!(queue_len(&mutex), 0);
+        assert_eq!(*mutex.try_lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn cancelled_waiter_passes_its_wakeup_on() {
+        let mutex = Mutex::new(());
+        let guard = mutex.try_lock().unwrap();
+        let (first_woken, first_waker) = Flag::new();
+        let (second_woken, second_waker) = Flag::new();
+        let mut first = Box::pin(mutex.lock());
+        let mut second = Box::pin(mutex.lock());
+        assert!(poll(first.as_mut(), &first_waker).is_pending());
+        assert!(poll(second.as_mut(), &second_waker).is_pending());
+
+        drop(guard);
+        assert!(first_woken.take());
+        assert!(!second_woken.take());
+
+        // The woken future goes away without taking the lock, which would
+        // leave the second waiting forever unless the wakeup moves on to it.
+        drop(first);
+        assert!(second_woken.take());
+        assert!(poll(second.as_mut(), &second_waker).is_ready());
+    }
+
+    #[test]
+    fn waiters_are_woken_in_fifo_order() {
+        let mutex = Mutex::new(Vec::new());
+        let mut guard = mutex.try_lock().unwrap();
+        let mut waiting: Vec<_> = (0..4)
+            .map(|i| {
+                let (woken, waker) = Flag::new();
+                let mut lock = Box::pin(mutex.lock());
+                assert!(poll(lock.as_mut(), &waker).is_pending());
+                (i, lock, woken, waker)
+            })
+            .collect();
+
+        for _ in 0..4 {
+            drop(guard);
+            // Only the front of the queue is woken by each unlock.
+            let woken: Vec<_> = waiting
+                .iter()
+                .map(|(_, _, woken, _)| woken.take())
+                .collect();
+            assert_eq!(woken.iter().filter(|&&woken| woken).count(), 1);
+            assert!(woken[0]);
+            let (i, mut lock, _, waker) = waiting.remove(0);
+            let Poll::Ready(next) = poll(lock.as_mut(), &waker) else {
+                panic!("woken waiter {i} could not take the lock");
+            };
+            guard = next;
+            guard.push(i);
+        }
+        assert_eq!(*guard, [0, 1, 2, 3]);
+    }
+}
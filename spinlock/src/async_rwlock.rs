@@ -0,0 +1,435 @@
+use std::{
+    cell::UnsafeCell,
+    future::Future,
+    ops::{Deref, DerefMut},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU32, AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+};
+
+use crate::{
+    async_mutex::{Waiter, Waiters},
+    SpinLock,
+};
+
+// The whole state while write-locked, otherwise it is the number of readers.
+const WRITE_LOCKED: u32 = u32::MAX;
+
+/// Implementation of an asynchronous reader-writer lock.
+///
+/// This is the reader-writer counterpart of [`crate::async_mutex::Mutex`].
+/// [`RwLock::read`] and [`RwLock::write`] return futures, and a task which
+/// cannot acquire the lock registers its waker in the same kind of queue and
+/// returns [`Poll::Pending`] rather than blocking the executor thread.
+///
+/// Like [`crate::rwlock::RwLock`], new readers wait while a writer is queued so
+/// that writers can't be starved. As the queue holds both readers and writers,
+/// whenever the lock becomes free every queued task is woken and they race for
+/// it, the writers winning over readers.
+pub struct RwLock<T> {
+    state: AtomicU32,
+    // Number of writers in the queue. Only changed while holding `waiters`.
+    writers_waiting: AtomicUsize,
+    waiters: SpinLock<Waiters>,
+    data: UnsafeCell<T>,
+}
+
+// Readers share `&T` between threads, so unlike a mutex `T: Sync` is needed
+// as well as `T: Send`.
+unsafe impl<T> Sync for RwLock<T> where T: Send + Sync {}
+
+impl<T> RwLock<T> {
+    pub fn new(inner: T) -> Self {
+        Self {
+            state: AtomicU32::new(0),
+            writers_waiting: AtomicUsize::new(0),
+            waiters: SpinLock::new(Waiters::new()),
+            data: UnsafeCell::new(inner),
+        }
+    }
+
+    /// Acquire a shared lock asynchronously.
+    ///
+    /// The returned [`Read`] future resolves to a [`ReadGuard`].
+    pub fn read(&self) -> Read<'_, T> {
+        Read {
+            rwlock: self,
+            waiter: Waiter::new(),
+            queued: false,
+        }
+    }
+
+    /// Acquire an exclusive lock asynchronously.
+    ///
+    /// The returned [`Write`] future resolves to a [`WriteGuard`].
+    pub fn write(&self) -> Write<'_, T> {
+        Write {
+            rwlock: self,
+            waiter: Waiter::new(),
+            queued: false,
+        }
+    }
+
+    /// Acquire a shared lock asynchronously as an [`OwnedReadGuard`], which
+    /// keeps the [`RwLock`] alive until it is dropped.
+    pub async fn read_owned(self: Arc<Self>) -> OwnedReadGuard<T> {
+        // Ownership of the lock moves to the returned guard, so the borrowed
+        // guard must not unlock when it goes out of scope.
+        std::mem::forget(self.read().await);
+        OwnedReadGuard { rwlock: self }
+    }
+
+    /// Acquire an exclusive lock asynchronously as an [`OwnedWriteGuard`],
+    /// which keeps the [`RwLock`] alive until it is dropped.
+    pub async fn write_owned(self: Arc<Self>) -> OwnedWriteGuard<T> {
+        std::mem::forget(self.write().await);
+        OwnedWriteGuard { rwlock: self }
+    }
+
+    /// Attempt to acquire a shared lock without waiting.
+    ///
+    /// Returns `None` if the lock is write-locked or a writer is waiting.
+    pub fn try_read(&self) -> Option<ReadGuard<'_, T>> {
+        if self.try_acquire_read() {
+            Some(ReadGuard { rwlock: self })
+        } else {
+            None
+        }
+    }
+
+    /// Attempt to acquire an exclusive lock without waiting.
+    ///
+    /// Returns `None` if there are any readers or another writer.
+    pub fn try_write(&self) -> Option<WriteGuard<'_, T>> {
+        if self.try_acquire_write() {
+            Some(WriteGuard { rwlock: self })
+        } else {
+            None
+        }
+    }
+
+    /// Raw pointer to the underlying data, without taking the lock, see
+    /// [`crate::rwlock::RwLock::data_ptr`].
+    pub fn data_ptr(&self) -> *mut T {
+        self.data.get()
+    }
+
+    fn try_acquire_read(&self) -> bool {
+        let mut s = self.state.load(Ordering::Relaxed);
+        loop {
+            if s == WRITE_LOCKED || self.writers_waiting.load(Ordering::Relaxed) > 0 {
+                return false;
+            }
+            // One more reader would look write-locked, see the blocking
+            // `RwLock` for why this panics rather than aborts.
+            assert!(s < WRITE_LOCKED - 1, "too many readers holding the RwLock");
+            match self
+                .state
+                .compare_exchange_weak(s, s + 1, Ordering::Acquire, Ordering::Relaxed)
+            {
+                Ok(_) => return true,
+                Err(e) => s = e,
+            }
+        }
+    }
+
+    fn try_acquire_write(&self) -> bool {
+        self.state
+            .compare_exchange(0, WRITE_LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    fn unlock_read(&self) {
+        if self.state.fetch_sub(1, Ordering::Release) == 1 {
+            self.waiters.lock().wake_all();
+        }
+    }
+
+    fn unlock_write(&self) {
+        self.state.store(0, Ordering::Release);
+        self.waiters.lock().wake_all();
+    }
+
+    // Shared by the `Read` and `Write` futures, which differ only in how they
+    // try to acquire the lock and whether they count as a waiting writer.
+    fn poll_acquire(
+        &self,
+        waiter: Pin<&Waiter>,
+        queued: &mut bool,
+        writer: bool,
+        cx: &mut Context<'_>,
+    ) -> Poll<()> {
+        let try_acquire = || {
+            if writer {
+                self.try_acquire_write()
+            } else {
+                self.try_acquire_read()
+            }
+        };
+        if try_acquire() {
+            if std::mem::take(queued) {
+                self.waiters.lock().remove(waiter);
+                if writer {
+                    self.writers_waiting.fetch_sub(1, Ordering::Relaxed);
+                }
+            }
+            return Poll::Ready(());
+        }
+
+        let mut waiters = self.waiters.lock();
+        // As with the async mutex, checking again while holding the queue lock
+        // means a wakeup can never be missed.
+        if try_acquire() {
+            if std::mem::take(queued) {
+                waiters.remove(waiter);
+                if writer {
+                    self.writers_waiting.fetch_sub(1, Ordering::Relaxed);
+                }
+            }
+            return Poll::Ready(());
+        }
+
+        if writer && !*queued {
+            self.writers_waiting.fetch_add(1, Ordering::Relaxed);
+        }
+        // Safety: the futures' Drop calls `cancel`, which removes the entry.
+        unsafe { waiters.register(waiter, cx.waker()) };
+        *queued = true;
+        Poll::Pending
+    }
+
+    // Called when a `Read` or `Write` future is dropped before completing.
+    fn cancel(&self, waiter: Pin<&Waiter>, writer: bool) {
+        let mut waiters = self.waiters.lock();
+        waiters.remove(waiter);
+        if writer {
+            self.writers_waiting.fetch_sub(1, Ordering::Relaxed);
+        }
+        // The future may have been woken and given up its turn, and a departing
+        // writer may have been all that was holding back the readers.
+        if self.state.load(Ordering::Relaxed) != WRITE_LOCKED {
+            waiters.wake_all();
+        }
+    }
+}
+
+/// Future returned by [`RwLock::read`].
+pub struct Read<'a, T> {
+    rwlock: &'a RwLock<T>,
+    // As in the async mutex's `Lock` future.
+    waiter: Waiter,
+    queued: bool,
+}
+
+impl<'a, T> Future for Read<'a, T> {
+    type Output = ReadGuard<'a, T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // Safety: `waiter` is never moved out of, it stays pinned with us.
+        let this = unsafe { self.get_unchecked_mut() };
+        let waiter = unsafe { Pin::new_unchecked(&this.waiter) };
+        let rwlock = this.rwlock;
+        rwlock
+            .poll_acquire(waiter, &mut this.queued, false, cx)
+            .map(|()| ReadGuard { rwlock })
+    }
+}
+
+impl<T> Drop for Read<'_, T> {
+    fn drop(&mut self) {
+        if self.queued {
+            // Safety: we are being dropped in place, having been pinned.
+            let waiter = unsafe { Pin::new_unchecked(&self.waiter) };
+            self.rwlock.cancel(waiter, false);
+        }
+    }
+}
+
+/// Future returned by [`RwLock::write`].
+pub struct Write<'a, T> {
+    rwlock: &'a RwLock<T>,
+    // As in the async mutex's `Lock` future.
+    waiter: Waiter,
+    queued: bool,
+}
+
+impl<'a, T> Future for Write<'a, T> {
+    type Output = WriteGuard<'a, T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // Safety: `waiter` is never moved out of, it stays pinned with us.
+        let this = unsafe { self.get_unchecked_mut() };
+        let waiter = unsafe { Pin::new_unchecked(&this.waiter) };
+        let rwlock = this.rwlock;
+        rwlock
+            .poll_acquire(waiter, &mut this.queued, true, cx)
+            .map(|()| WriteGuard { rwlock })
+    }
+}
+
+impl<T> Drop for Write<'_, T> {
+    fn drop(&mut self) {
+        if self.queued {
+            // Safety: we are being dropped in place, having been pinned.
+            let waiter = unsafe { Pin::new_unchecked(&self.waiter) };
+            self.rwlock.cancel(waiter, true);
+        }
+    }
+}
+
+pub struct ReadGuard<'a, T> {
+    rwlock: &'a RwLock<T>,
+}
+
+impl<T> Deref for ReadGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // Existence of the guard implies nobody holds a write lock, so this is
+        // safe to do.
+        unsafe { &*self.rwlock.data.get() }
+    }
+}
+
+impl<T> Drop for ReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.rwlock.unlock_read();
+    }
+}
+
+pub struct WriteGuard<'a, T> {
+    rwlock: &'a RwLock<T>,
+}
+
+impl<T> Deref for WriteGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // Existence of the guard implies we have an exclusive lock, so this is
+        // safe to do.
+        unsafe { &*self.rwlock.data.get() }
+    }
+}
+
+impl<T> DerefMut for WriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // Existence of the guard implies we have an exclusive lock, so this is
+        // safe to do.
+        unsafe { &mut *self.rwlock.data.get() }
+    }
+}
+
+impl<T> Drop for WriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.rwlock.unlock_write();
+    }
+}
+
+/// A [`ReadGuard`] which owns a reference counted [`RwLock`] instead of
+/// borrowing it.
+pub struct OwnedReadGuard<T> {
+    rwlock: Arc<RwLock<T>>,
+}
+
+impl<T> Deref for OwnedReadGuard<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // Existence of the guard implies nobody holds a write lock, so this is
+        // safe to do.
+        unsafe { &*self.rwlock.data.get() }
+    }
+}
+
+impl<T> Drop for OwnedReadGuard<T> {
+    fn drop(&mut self) {
+        self.rwlock.unlock_read();
+    }
+}
+
+/// A [`WriteGuard`] which owns a reference counted [`RwLock`] instead of
+/// borrowing it.
+pub struct OwnedWriteGuard<T> {
+    rwlock: Arc<RwLock<T>>,
+}
+
+impl<T> Deref for OwnedWriteGuard<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // Existence of the guard implies we have an exclusive lock, so this is
+        // safe to do.
+        unsafe { &*self.rwlock.data.get() }
+    }
+}
+
+impl<T> DerefMut for OwnedWriteGuard<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // Existence of the guard implies we have an exclusive lock, so this is
+        // safe to do.
+        unsafe { &mut *self.rwlock.data.get() }
+    }
+}
+
+impl<T> Drop for OwnedWriteGuard<T> {
+    fn drop(&mut self) {
+        self.rwlock.unlock_write();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::async_mutex::tests::{poll, Flag};
+
+    #[test]
+    fn queued_writer_blocks_new_readers() {
+        let rwlock = RwLock::new(0);
+        let reader = rwlock.try_read().unwrap();
+        let (writer_woken, writer_waker) = Flag::new();
+        let (reader_woken, reader_waker) = Flag::new();
+        let mut write = Box::pin(rwlock.write());
+        assert!(poll(write.as_mut(), &writer_waker).is_pending());
+
+        // The lock is only read-locked, but the waiting writer keeps new
+        // readers out so that it isn't starved.
+        assert!(rwlock.try_read().is_none());
+        let mut read = Box::pin(rwlock.read());
+        assert!(poll(read.as_mut(), &reader_waker).is_pending());
+
+        drop(reader);
+        assert!(writer_woken.take());
+        assert!(reader_woken.take());
+        assert!(poll(read.as_mut(), &reader_waker).is_pending());
+        let Poll::Ready(mut guard) = poll(write.as_mut(), &writer_waker) else {
+            panic!("the writer could not take the lock once the reader left");
+        };
+        *guard += 1;
+        drop(guard);
+
+        assert!(reader_woken.take());
+        let Poll::Ready(guard) = poll(read.as_mut(), &reader_waker) else {
+            panic!("the reader could not take the lock once the writer left");
+        };
+        assert_eq!(*guard, 1);
+    }
+
+    #[test]
+    fn cancelled_writer_releases_held_back_readers() {
+        let rwlock = RwLock::new(());
+        let reader = rwlock.try_read().unwrap();
+        let (_, writer_waker) = Flag::new();
+        let (reader_woken, reader_waker) = Flag::new();
+        let mut write = Box::pin(rwlock.write());
+        assert!(poll(write.as_mut(), &writer_waker).is_pending());
+        let mut read = Box::pin(rwlock.read());
+        assert!(poll(read.as_mut(), &reader_waker).is_pending());
+
+        // Nothing else will wake the reader while the first one holds on, so
+        // the writer giving up has to.
+        drop(write);
+        assert!(reader_woken.take());
+        assert!(poll(read.as_mut(), &reader_waker).is_ready());
+        assert!(rwlock.try_read().is_some());
+        drop(reader);
+    }
+}
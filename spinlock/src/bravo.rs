@@ -0,0 +1,274 @@
+use std::{
+    cell::UnsafeCell,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    time::Instant,
+};
+
+use crate::rwlock::RawRwLock;
+
+// Number of slots in the visible readers table, shared by every lock.
+const SLOTS: usize = 4096;
+// After revoking the bias, it stays off for this many times as long as the
+// revocation took, bounding the cost writers pay to at most 1/(N+1) of the time.
+const INHIBIT_MULTIPLIER: u32 = 9;
+
+// Each slot holds the address of a lock which a reader is holding through it,
+// or zero if it is free. Sharing one table between all locks keeps the memory
+// cost fixed however many locks there are.
+static VISIBLE_READERS: [AtomicUsize; SLOTS] = [const { AtomicUsize::new(0) }; SLOTS];
+
+thread_local! {
+    // Only its address is used, to tell threads apart when picking a slot.
+    static THREAD: u8 = const { 0 };
+}
+
+// Pick a slot from the lock and the current thread, so that readers of the
+// same lock on different threads spread out over the table.
+fn slot(lock: usize) -> usize {
+    let thread = THREAD.with(|t| t as *const u8 as usize);
+    let hash = ((lock ^ thread) as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    (hash >> (64 - SLOTS.trailing_zeros())) as usize
+}
+
+/// A reader-writer lock biased towards readers, using the BRAVO technique.
+///
+/// With a plain [`crate::rwlock::RwLock`] every reader updates the same atomic,
+/// so with many reader threads that cache line bounces between cores and reads
+/// end up serialised anyway. Here, while the lock is reader biased, a reader
+/// instead claims a slot in a global table of visible readers, picked by hashing
+/// the lock and thread, and never touches the lock's own state.
+///
+/// A writer first acquires the underlying [`RawRwLock`], then revokes the bias
+/// and waits for every reader still in the table to leave. Revocation is slow,
+/// so the bias is only turned back on by a reader once a multiple of the time it
+/// took has passed. Readers which find their slot taken, or the bias off, fall
+/// back to the underlying lock.
+///
+/// Paper: "BRAVO: Biased Locking for Reader-Writer Locks", Dice and Kogan, 2019.
+pub struct BravoRwLock<T> {
+    raw: RawRwLock,
+    reader_bias: AtomicBool,
+    // Nanoseconds since `created` before readers may turn the bias back on.
+    inhibit_until: AtomicU64,
+    created: Instant,
+    value: UnsafeCell<T>,
+}
+
+// Readers share `&T` between threads, so unlike a mutex `T: Sync` is needed
+// as well as `T: Send`.
+unsafe impl<T> Sync for BravoRwLock<T> where T: Send + Sync {}
+
+impl<T> BravoRwLock<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            raw: RawRwLock::new(),
+            reader_bias: AtomicBool::new(true),
+            inhibit_until: AtomicU64::new(0),
+            created: Instant::now(),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Acquire a shared lock as a [`BravoReadGuard`].
+    pub fn read(&self) -> BravoReadGuard<'_, T> {
+        if self.reader_bias.load(Ordering::Relaxed) {
+            let slot = &VISIBLE_READERS[slot(self.addr())];
+            if slot
+                .compare_exchange(0, self.addr(), Ordering::SeqCst, Ordering::Relaxed)
+                .is_ok()
+            {
+                // A writer clears the bias before scanning the table, so if it
+                // is still set the writer will see our slot and wait for it.
+                if self.reader_bias.load(Ordering::SeqCst) {
+                    return BravoReadGuard {
+                        lock: self,
+                        slot: Some(slot),
+                    };
+                }
+                slot.store(0, Ordering::Relaxed);
+            }
+        }
+
+        self.raw.lock_shared();
+        if !self.reader_bias.load(Ordering::Relaxed) && self.elapsed() >= self.inhibit_until() {
+            // Holding a shared lock keeps writers out while turning the bias
+            // back on. Release passes on what we acquired from the last writer
+            // to readers which then take the fast path.
+            self.reader_bias.store(true, Ordering::Release);
+        }
+        BravoReadGuard {
+            lock: self,
+            slot: None,
+        }
+    }
+
+    /// Acquire an exclusive lock as a [`BravoWriteGuard`].
+    pub fn write(&self) -> BravoWriteGuard<'_, T> {
+        self.raw.lock_exclusive();
+        if self.reader_bias.load(Ordering::Relaxed) {
+            let start = self.elapsed();
+            self.reader_bias.store(false, Ordering::SeqCst);
+            for slot in &VISIBLE_READERS {
+                while slot.load(Ordering::SeqCst) == self.addr() {
+                    std::hint::spin_loop();
+                }
+            }
+            let now = self.elapsed();
+            let inhibit = now + (now - start) * u64::from(INHIBIT_MULTIPLIER);
+            self.inhibit_until.store(inhibit, Ordering::Relaxed);
+        }
+        BravoWriteGuard { lock: self }
+    }
+
+    /// Whether readers are currently using the visible readers table.
+    pub fn is_reader_biased(&self) -> bool {
+        self.reader_bias.load(Ordering::Relaxed)
+    }
+
+    fn addr(&self) -> usize {
+        self as *const Self as usize
+    }
+
+    fn elapsed(&self) -> u64 {
+        self.created
+            .elapsed()
+            .as_nanos()
+            .try_into()
+            .unwrap_or(u64::MAX)
+    }
+
+    fn inhibit_until(&self) -> u64 {
+        self.inhibit_until.load(Ordering::Relaxed)
+    }
+}
+
+pub struct BravoReadGuard<'a, T> {
+    lock: &'a BravoRwLock<T>,
+    // The visible readers slot this reader holds, or `None` if it holds the
+    // underlying lock instead.
+    slot: Option<&'static AtomicUsize>,
+}
+
+impl<T> Deref for BravoReadGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // Existence of the guard implies nobody holds a write lock, so this is
+        // safe to do.
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T> Drop for BravoReadGuard<'_, T> {
+    fn drop(&mut self) {
+        match self.slot {
+            Some(slot) => slot.store(0, Ordering::Release),
+            // Existence of the guard implies we hold a shared lock.
+            None => unsafe { self.lock.raw.unlock_shared() },
+        }
+    }
+}
+
+pub struct BravoWriteGuard<'a, T> {
+    lock: &'a BravoRwLock<T>,
+}
+
+impl<T> Deref for BravoWriteGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // Existence of the guard implies we have an exclusive lock, so this is
+        // safe to do.
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T> DerefMut for BravoWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // Existence of the guard implies we have an exclusive lock, so this is
+        // safe to do.
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T> Drop for BravoWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        // Existence of the guard implies we have an exclusive lock.
+        unsafe { self.lock.raw.unlock_exclusive() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{thread, time::Duration};
+
+    #[test]
+    fn writer_waits_for_biased_readers() {
+        let lock = BravoRwLock::new(0);
+        let reader = lock.read();
+        // Took the fast path, so the underlying lock is free for the writer.
+        assert!(reader.slot.is_some());
+        thread::scope(|s| {
+            let writer = s.spawn(|| *lock.write() += 1);
+            while lock.is_reader_biased() {
+                thread::yield_now();
+            }
+            // The bias is revoked, but the reader in the table still holds the
+            // writer back.
+            thread::sleep(Duration::from_millis(20));
+            assert!(!writer.is_finished());
+            assert_eq!(*reader, 0);
+            drop(reader);
+            writer.join().unwrap();
+        });
+        assert_eq!(*lock.read(), 1);
+    }
+
+    #[test]
+    fn bias_revoked_by_writer_and_restored_by_reader() {
+        let lock = BravoRwLock::new(());
+        drop(lock.write());
+        assert!(!lock.is_reader_biased());
+
+        // While inhibited, readers use the underlying lock and leave the bias
+        // off.
+        lock.inhibit_until.store(u64::MAX, Ordering::Relaxed);
+        let reader = lock.read();
+        assert!(reader.slot.is_none());
+        drop(reader);
+        assert!(!lock.is_reader_biased());
+
+        // Once the time is up, the next reader turns it back on, and the one
+        // after takes the fast path.
+        lock.inhibit_until.store(0, Ordering::Relaxed);
+        let reader = lock.read();
+        assert!(reader.slot.is_none());
+        drop(reader);
+        assert!(lock.is_reader_biased());
+        assert!(lock.read().slot.is_some());
+    }
+
+    #[test]
+    fn writers_exclude_each_other_and_readers() {
+        let lock = BravoRwLock::new((0, 0));
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..1000 {
+                        let mut guard = lock.write();
+                        guard.0 += 1;
+                        guard.1 += 1;
+                    }
+                });
+                s.spawn(|| {
+                    for _ in 0..1000 {
+                        // Never sees a write half done.
+                        let guard = lock.read();
+                        assert_eq!(guard.0, guard.1);
+                    }
+                });
+            }
+        });
+        assert_eq!(*lock.read(), (4000, 4000));
+    }
+}
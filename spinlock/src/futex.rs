@@ -0,0 +1,338 @@
+//! Waiting on, and waking, an `AtomicU32`, as every blocking lock here does.
+//!
+//! Each platform has its own way of putting a thread to sleep until a word in
+//! memory changes: `futex` on Linux, `WaitOnAddress` on Windows and `__ulock`
+//! on macOS. They all sit behind the [`Backend`] trait, and [`Native`] is
+//! whichever one the target has, falling back to [`Spin`] where there is none.
+//! The free functions use [`Native`], and are what the rest of the crate calls,
+//! so a wake always reaches a thread waiting on the same word.
+//!
+//! All of them can wake up spuriously, so callers must check the value again,
+//! and their own deadline, before deciding to carry on.
+
+use std::{sync::atomic::AtomicU32, time::Duration};
+
+/// A way of blocking a thread until an `AtomicU32` changes.
+pub trait Backend {
+    /// Block while `atomic` holds `expected`, for at most `timeout` if one is
+    /// given. Returns straight away if the value is already different.
+    fn wait(atomic: &AtomicU32, expected: u32, timeout: Option<Duration>);
+
+    /// Wake one thread blocked in `wait` on `atomic`, if there is one.
+    fn wake_one(atomic: &AtomicU32);
+
+    /// Wake every thread blocked in `wait` on `atomic`.
+    fn wake_all(atomic: &AtomicU32);
+}
+
+/// Block while `atomic` holds `expected`, using the [`Native`] backend.
+pub fn wait(atomic: &AtomicU32, expected: u32) {
+    Native::wait(atomic, expected, None);
+}
+
+/// Block while `atomic` holds `expected`, for at most `timeout`, using the
+/// [`Native`] backend.
+pub fn wait_timeout(atomic: &AtomicU32, expected: u32, timeout: Duration) {
+    Native::wait(atomic, expected, Some(timeout));
+}
+
+pub fn wake_one(atomic: &AtomicU32) {
+    Native::wake_one(atomic);
+}
+
+pub fn wake_all(atomic: &AtomicU32) {
+    Native::wake_all(atomic);
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub type Native = Linux;
+#[cfg(windows)]
+pub type Native = WaitOnAddress;
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+pub type Native = Ulock;
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    windows,
+    target_os = "macos",
+    target_os = "ios"
+)))]
+pub type Native = Spin;
+
+/// The `futex` system call.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub struct Linux;
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+impl Linux {
+    // The private flag is fine, as the word is never shared between processes,
+    // and lets the kernel skip looking up the mapping.
+    fn futex(atomic: &AtomicU32, op: libc::c_int, value: u32, timeout: *const libc::timespec) {
+        unsafe {
+            libc::syscall(
+                libc::SYS_futex,
+                atomic as *const AtomicU32,
+                op | libc::FUTEX_PRIVATE_FLAG,
+                value,
+                timeout,
+            );
+        }
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+impl Backend for Linux {
+    fn wait(atomic: &AtomicU32, expected: u32, timeout: Option<Duration>) {
+        // FUTEX_WAIT takes a relative timeout, or null to wait forever.
+        let timeout = timeout.map(|timeout| libc::timespec {
+            tv_sec: timeout.as_secs().try_into().unwrap_or(libc::time_t::MAX),
+            tv_nsec: timeout.subsec_nanos() as _,
+        });
+        let timeout = timeout
+            .as_ref()
+            .map_or(std::ptr::null(), |t| t as *const libc::timespec);
+        Self::futex(atomic, libc::FUTEX_WAIT, expected, timeout);
+    }
+
+    fn wake_one(atomic: &AtomicU32) {
+        Self::futex(atomic, libc::FUTEX_WAKE, 1, std::ptr::null());
+    }
+
+    fn wake_all(atomic: &AtomicU32) {
+        Self::futex(atomic, libc::FUTEX_WAKE, i32::MAX as u32, std::ptr::null());
+    }
+}
+
+#[cfg(windows)]
+mod synchronization {
+    use std::ffi::c_void;
+
+    pub const INFINITE: u32 = u32::MAX;
+
+    #[link(name = "synchronization")]
+    extern "system" {
+        pub fn WaitOnAddress(
+            address: *const c_void,
+            compare_address: *const c_void,
+            address_size: usize,
+            milliseconds: u32,
+        ) -> i32;
+        pub fn WakeByAddressSingle(address: *const c_void);
+        pub fn WakeByAddressAll(address: *const c_void);
+    }
+}
+
+/// `WaitOnAddress` and `WakeByAddress`, from Windows 8 on.
+#[cfg(windows)]
+pub struct WaitOnAddress;
+
+#[cfg(windows)]
+impl Backend for WaitOnAddress {
+    fn wait(atomic: &AtomicU32, expected: u32, timeout: Option<Duration>) {
+        // Rounded up to whole milliseconds, so a short timeout doesn't become
+        // a busy loop, and kept short of INFINITE.
+        let milliseconds = match timeout {
+            None => synchronization::INFINITE,
+            Some(timeout) => timeout
+                .as_nanos()
+                .div_ceil(1_000_000)
+                .min(u128::from(synchronization::INFINITE - 1)) as u32,
+        };
+        unsafe {
+            synchronization::WaitOnAddress(
+                atomic.as_ptr().cast(),
+                (&expected as *const u32).cast(),
+                std::mem::size_of::<u32>(),
+                milliseconds,
+            );
+        }
+    }
+
+    fn wake_one(atomic: &AtomicU32) {
+        unsafe { synchronization::WakeByAddressSingle(atomic.as_ptr().cast()) };
+    }
+
+    fn wake_all(atomic: &AtomicU32) {
+        unsafe { synchronization::WakeByAddressAll(atomic.as_ptr().cast()) };
+    }
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+mod ulock {
+    use std::ffi::{c_int, c_void};
+
+    pub const UL_COMPARE_AND_WAIT: u32 = 1;
+    pub const ULF_WAKE_ALL: u32 = 0x100;
+    pub const ULF_NO_ERRNO: u32 = 0x0100_0000;
+
+    extern "C" {
+        pub fn __ulock_wait(
+            operation: u32,
+            addr: *mut c_void,
+            value: u64,
+            timeout_us: u32,
+        ) -> c_int;
+        pub fn __ulock_wake(operation: u32, addr: *mut c_void, wake_value: u64) -> c_int;
+    }
+}
+
+/// The `__ulock_wait` and `__ulock_wake` calls which the system's own locks
+/// are built on.
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+pub struct Ulock;
+
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+impl Backend for Ulock {
+    fn wait(atomic: &AtomicU32, expected: u32, timeout: Option<Duration>) {
+        // In microseconds, where zero means forever, so a timeout is at least
+        // one. One too long to fit just returns early, which callers allow for.
+        let timeout_us = match timeout {
+            None => 0,
+            Some(timeout) => timeout.as_micros().clamp(1, u128::from(u32::MAX)) as u32,
+        };
+        unsafe {
+            ulock::__ulock_wait(
+                ulock::UL_COMPARE_AND_WAIT | ulock::ULF_NO_ERRNO,
+                atomic.as_ptr().cast(),
+                u64::from(expected),
+                timeout_us,
+            );
+        }
+    }
+
+    fn wake_one(atomic: &AtomicU32) {
+        unsafe {
+            ulock::__ulock_wake(
+                ulock::UL_COMPARE_AND_WAIT | ulock::ULF_NO_ERRNO,
+                atomic.as_ptr().cast(),
+                0,
+            );
+        }
+    }
+
+    fn wake_all(atomic: &AtomicU32) {
+        unsafe {
+            ulock::__ulock_wake(
+                ulock::UL_COMPARE_AND_WAIT | ulock::ULF_WAKE_ALL | ulock::ULF_NO_ERRNO,
+                atomic.as_ptr().cast(),
+                0,
+            );
+        }
+    }
+}
+
+/// For targets without a way to sleep on an address, waiting polls the value
+/// instead, and waking does nothing.
+///
+/// Polling starts with a short spin, as the value often changes within a few
+/// hundred cycles, then yields, then sleeps for longer each time up to a
+/// millisecond, so a long wait doesn't keep a core busy. The cost is that a
+/// thread may take that long to notice the change.
+pub struct Spin;
+
+impl Spin {
+    const SPINS: u32 = 6;
+    const YIELDS: u32 = 10;
+    const MAX_SLEEP: Duration = Duration::from_millis(1);
+}
+
+impl Backend for Spin {
+    fn wait(atomic: &AtomicU32, expected: u32, timeout: Option<Duration>) {
+        use std::{sync::atomic::Ordering, thread, time::Instant};
+
+        // A timeout too long to represent may as well be forever.
+        let deadline = timeout.and_then(|timeout| Instant::now().checked_add(timeout));
+        let mut step = 0;
+        while atomic.load(Ordering::Relaxed) == expected {
+            let remaining = match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(remaining) if !remaining.is_zero() => remaining,
+                    _ => return,
+                },
+                None => Duration::MAX,
+            };
+            if step < Self::SPINS {
+                for _ in 0..1 << step {
+                    std::hint::spin_loop();
+                }
+            } else if step < Self::YIELDS {
+                thread::yield_now();
+            } else {
+                let pause = Duration::from_micros(1 << (step - Self::YIELDS).min(10));
+                thread::sleep(pause.min(Self::MAX_SLEEP).min(remaining));
+            }
+            step += 1;
+        }
+    }
+
+    fn wake_one(_atomic: &AtomicU32) {}
+
+    fn wake_all(_atomic: &AtomicU32) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::Ordering,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use super::*;
+
+    // Change the value from another thread and wake, which must end the wait
+    // well before the timeout.
+    fn woken_by_change<B: Backend>() {
+        let atomic = AtomicU32::new(0);
+        let start = Instant::now();
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(20));
+                atomic.store(1, Ordering::Release);
+                B::wake_all(&atomic);
+            });
+            while atomic.load(Ordering::Acquire) == 0 {
+                B::wait(&atomic, 0, Some(Duration::from_secs(10)));
+            }
+        });
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    fn times_out<B: Backend>() {
+        let atomic = AtomicU32::new(0);
+        let timeout = Duration::from_millis(20);
+        let start = Instant::now();
+        // Wakeups may be spurious, so wait out the whole timeout.
+        while start.elapsed() < timeout {
+            B::wait(&atomic, 0, Some(timeout - start.elapsed()));
+        }
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn different_value_returns() {
+        let atomic = AtomicU32::new(1);
+        Native::wait(&atomic, 0, None);
+        Spin::wait(&atomic, 0, None);
+    }
+
+    #[test]
+    fn native_woken() {
+        woken_by_change::<Native>();
+    }
+
+    #[test]
+    fn native_times_out() {
+        times_out::<Native>();
+    }
+
+    #[test]
+    fn spin_woken() {
+        woken_by_change::<Spin>();
+    }
+
+    #[test]
+    fn spin_times_out() {
+        times_out::<Spin>();
+    }
+}
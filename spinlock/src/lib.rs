@@ -1,11 +1,81 @@
+pub mod async_mutex;
+pub mod async_rwlock;
+pub mod bravo;
+pub mod futex;
+pub mod poison;
+#[cfg(feature = "profile")]
+pub mod profile;
+pub mod rwlock;
+pub mod sharded;
+#[cfg(feature = "stats")]
+pub mod stats;
+pub mod word_lock;
+
 use std::{
     cell::UnsafeCell,
+    fmt,
+    marker::PhantomData,
     ops::{Deref, DerefMut},
-    sync::atomic::{AtomicBool, Ordering},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
 };
 
 pub struct Guard<'a, T> {
     lock: &'a SpinLock<T>,
+    // Raw pointers are neither Send nor Sync, this opts the guard out of both so
+    // that they are only implemented explicitly below.
+    _marker: PhantomData<*const ()>,
+}
+
+// Left to the compiler, the guard would be Send and Sync whenever `&SpinLock<T>`
+// is, which only requires `T: Send`. That is wrong for Sync, sharing `&Guard`
+// hands out `&T` to other threads, so `T: Sync` is required instead.
+unsafe impl<T> Sync for Guard<'_, T> where T: Sync {}
+
+// Like std's MutexGuard, a guard must be dropped on the thread which locked it
+// unless the `send_guard` feature is enabled. Nothing in a spinlock's unlock
+// depends on the thread it runs on, so code which moves guards between threads
+// can opt in, at the cost of not being portable to locks where it does.
+#[cfg(feature = "send_guard")]
+unsafe impl<T> Send for Guard<'_, T> where T: Send {}
+
+impl<'a, T> Guard<'a, T> {
+    /// Consume the guard without unlocking, leaving the [`SpinLock`] locked
+    /// forever and returning a mutable reference to the data.
+    ///
+    /// This is useful for once-only initialisation of long-lived state, where
+    /// nothing else should ever be able to lock it again.
+    pub fn leak(guard: Self) -> &'a mut T {
+        let lock = guard.lock;
+        std::mem::forget(guard);
+        // The lock is never released, so this is the only reference that can
+        // ever be handed out.
+        unsafe { &mut *lock.data.get() }
+    }
+
+    /// Temporarily release the lock while running `f`, re-acquiring it before
+    /// returning.
+    ///
+    /// guard: &mut Self is used here so that it must be called as
+    /// Guard::unlocked(&mut guard, ...) to avoid ambiguity with methods on T.
+    pub fn unlocked<R>(guard: &mut Self, f: impl FnOnce() -> R) -> R {
+        // Re-acquire on drop so that the lock is held again even if `f` panics,
+        // otherwise dropping the guard during the unwind would release a lock
+        // which may now belong to another thread.
+        struct Relock<'a, T>(&'a SpinLock<T>);
+        impl<T> Drop for Relock<'_, T> {
+            fn drop(&mut self) {
+                self.0.acquire();
+            }
+        }
+
+        guard.lock.release();
+        let _relock = Relock(guard.lock);
+        f()
+    }
 }
 
 // Implementation of [`Deref`] and [`DerefMut`] enable the [`Guard`] pattern to
@@ -30,7 +100,43 @@ impl<T> DerefMut for Guard<'_, T> {
 impl<T> Drop for Guard<'_, T> {
     fn drop(&mut self) {
         // When the guard is dropped, we should unlock
-        self.lock.locked.store(false, Ordering::Relaxed)
+        self.lock.release()
+    }
+}
+
+/// A [`Guard`] which owns a reference counted [`SpinLock`] instead of borrowing it.
+///
+/// As it has no lifetime tied to the lock, it can be returned from functions,
+/// moved into spawned threads and stored alongside other `'static` data.
+pub struct OwnedGuard<T> {
+    lock: Arc<SpinLock<T>>,
+}
+
+// An owned guard exists to be moved around, so it stays Send whenever `T: Send`,
+// but as with [`Guard`] sharing it hands out `&T`.
+unsafe impl<T> Send for OwnedGuard<T> where T: Send {}
+unsafe impl<T> Sync for OwnedGuard<T> where T: Sync {}
+
+impl<T> Deref for OwnedGuard<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // Existence of the guard implies we have an exclusive lock, so this is
+        // safe to do.
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> DerefMut for OwnedGuard<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // Existence of the guard implies we have an exclusive lock, so this is
+        // safe to do.
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T> Drop for OwnedGuard<T> {
+    fn drop(&mut self) {
+        self.lock.release()
     }
 }
 
@@ -49,6 +155,8 @@ impl<T> Drop for Guard<'_, T> {
 pub struct SpinLock<T> {
     pub data: UnsafeCell<T>,
     locked: AtomicBool,
+    #[cfg(feature = "profile")]
+    profile: Option<profile::Profile>,
 }
 
 // The use of [`UnsafeCell`] means we must promise to the compiler that this
@@ -60,16 +168,144 @@ impl<T> SpinLock<T> {
         Self {
             data: UnsafeCell::new(inner),
             locked: AtomicBool::new(false),
+            #[cfg(feature = "profile")]
+            profile: None,
+        }
+    }
+
+    /// Create a [`SpinLock`] which records how often it is acquired, and how
+    /// long it is waited on and held for, under `name`.
+    ///
+    /// The totals for every lock sharing a name are available through
+    /// [`profile::report`].
+    #[cfg(feature = "profile")]
+    pub fn new_named(name: &'static str, inner: T) -> Self {
+        Self {
+            data: UnsafeCell::new(inner),
+            locked: AtomicBool::new(false),
+            profile: Some(profile::Profile::new(name)),
         }
     }
 
     /// Acquire an exclusive mutable lock as a [`Guard`].
     ///
     /// The returned [`Guard`] enables unlocking the [`SpinLock`] when dropped.
-    pub fn lock(&self) -> Guard<T> {
+    pub fn lock(&self) -> Guard<'_, T> {
+        self.acquire();
+        Guard {
+            lock: self,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Run `f` with exclusive access to the data, releasing the lock as soon as
+    /// it returns.
+    ///
+    /// The lock is held by a [`Guard`] internally, so it is also released if
+    /// `f` panics.
+    pub fn lock_with<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        f(&mut self.lock())
+    }
+
+    /// Acquire an exclusive mutable lock as an [`OwnedGuard`], which keeps the
+    /// [`SpinLock`] alive until it is dropped.
+    pub fn lock_owned(self: Arc<Self>) -> OwnedGuard<T> {
+        self.acquire();
+        OwnedGuard { lock: self }
+    }
+
+    /// Attempt to acquire the lock without spinning.
+    ///
+    /// Returns `None` if the lock is currently held.
+    pub fn try_lock(&self) -> Option<Guard<'_, T>> {
+        if self.locked.swap(true, Ordering::Acquire) {
+            None
+        } else {
+            #[cfg(feature = "profile")]
+            profile::acquired(&self.profile, None);
+            Some(Guard {
+                lock: self,
+                _marker: PhantomData,
+            })
+        }
+    }
+
+    /// Attempt to acquire the lock, spinning for at most `timeout`.
+    pub fn try_lock_for(&self, timeout: Duration) -> Option<Guard<'_, T>> {
+        self.try_lock_until(Instant::now() + timeout)
+    }
+
+    /// Attempt to acquire the lock, spinning until `deadline` has passed.
+    ///
+    /// Retrying against a fixed deadline avoids drift from repeatedly working
+    /// out how much of an overall timeout is left.
+    pub fn try_lock_until(&self, deadline: Instant) -> Option<Guard<'_, T>> {
+        loop {
+            if let Some(guard) = self.try_lock() {
+                return Some(guard);
+            }
+            if Instant::now() >= deadline {
+                return None;
+            }
+            std::hint::spin_loop();
+        }
+    }
+
+    /// Whether the lock is currently held.
+    ///
+    /// This is only a snapshot, the lock may have been acquired or released by
+    /// the time the result is used. It is intended for diagnostics, assertions
+    /// and heuristics, not for synchronisation.
+    pub fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Relaxed)
+    }
+
+    /// Raw pointer to the underlying data, without taking the lock.
+    ///
+    /// This is useful for FFI and intrusive data structures. Reading or writing
+    /// through the pointer is only safe while the caller otherwise guarantees
+    /// exclusive access, such as by holding a [`Guard`].
+    pub fn data_ptr(&self) -> *mut T {
+        self.data.get()
+    }
+
+    fn acquire(&self) {
+        #[cfg(feature = "profile")]
+        let started = profile::start(&self.profile);
         while self.locked.swap(true, Ordering::Acquire) {
             std::hint::spin_loop();
         }
-        Guard { lock: self }
+        #[cfg(feature = "profile")]
+        profile::acquired(&self.profile, started);
+    }
+
+    fn release(&self) {
+        #[cfg(feature = "profile")]
+        profile::released(&self.profile);
+        // Release ordering pairs with the Acquire in `acquire` so that everything
+        // done while holding the lock is visible to the next thread which
+        // acquires it.
+        self.locked.store(false, Ordering::Release);
+    }
+}
+
+// Printed in place of the data when a lock is held elsewhere, so that
+// formatting never blocks.
+pub(crate) struct LockedPlaceholder;
+
+impl fmt::Debug for LockedPlaceholder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("<locked>")
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for SpinLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("SpinLock");
+        match self.try_lock() {
+            Some(guard) => d.field("data", &&*guard),
+            None => d.field("data", &LockedPlaceholder),
+        };
+        d.finish_non_exhaustive()
     }
 }
@@ -0,0 +1,88 @@
+//! Lock poisoning, following std.
+//!
+//! If a thread panics while holding an exclusive lock, the data may have been
+//! left half-updated. The lock is then marked as poisoned, and later attempts to
+//! lock it return a [`PoisonError`] which still gives access to the data, so that
+//! the caller can decide whether to recover.
+//!
+//! Disabling the default `poison` feature removes the flag entirely, so locking
+//! never returns an error. The return types stay the same either way, as cargo
+//! turns the feature on for every user of the crate once any one of them asks
+//! for it. For locks which return guards directly, as parking_lot's do, use
+//! [`crate::rwlock::RawRwLock`] through the `lock_api` feature.
+
+#[cfg(feature = "poison")]
+use std::sync::atomic::{AtomicBool, Ordering};
+
+pub use std::sync::PoisonError;
+
+/// The result of locking, which is an error if the lock was poisoned. Without
+/// the `poison` feature it is always `Ok`.
+pub type LockResult<G> = Result<G, PoisonError<G>>;
+
+/// Poisoned state stored alongside a lock.
+pub(crate) struct Flag {
+    #[cfg(feature = "poison")]
+    poisoned: AtomicBool,
+}
+
+/// Taken when an exclusive lock is acquired and handed back to [`Flag::done`]
+/// when it is released.
+#[derive(Clone, Copy)]
+pub(crate) struct Guard {
+    // Only a panic which starts while the lock is held should poison it, not
+    // one which was already unwinding when it was acquired.
+    #[cfg(feature = "poison")]
+    panicking: bool,
+}
+
+impl Flag {
+    pub(crate) const fn new() -> Self {
+        Self {
+            #[cfg(feature = "poison")]
+            poisoned: AtomicBool::new(false),
+        }
+    }
+
+    pub(crate) fn guard(&self) -> Guard {
+        Guard {
+            #[cfg(feature = "poison")]
+            panicking: std::thread::panicking(),
+        }
+    }
+
+    #[cfg_attr(not(feature = "poison"), allow(unused_variables))]
+    pub(crate) fn done(&self, guard: &Guard) {
+        #[cfg(feature = "poison")]
+        if !guard.panicking && std::thread::panicking() {
+            self.poisoned.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Wrap a newly acquired guard, as an error if the lock is poisoned.
+    pub(crate) fn result<G>(&self, guard: G) -> LockResult<G> {
+        wrap(self.get(), guard)
+    }
+
+    pub(crate) fn get(&self) -> bool {
+        #[cfg(feature = "poison")]
+        return self.poisoned.load(Ordering::Relaxed);
+        #[cfg(not(feature = "poison"))]
+        false
+    }
+
+    pub(crate) fn clear(&self) {
+        #[cfg(feature = "poison")]
+        self.poisoned.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Wrap a guard given whether the lock was poisoned, for guards which own their
+/// lock and so can't be built while its [`Flag`] is borrowed.
+pub(crate) fn wrap<G>(poisoned: bool, guard: G) -> LockResult<G> {
+    if poisoned {
+        Err(PoisonError::new(guard))
+    } else {
+        Ok(guard)
+    }
+}
@@ -0,0 +1,149 @@
+use std::{
+    cell::UnsafeCell,
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// Counters shared by every lock created with the same name.
+struct LockStats {
+    name: &'static str,
+    acquisitions: AtomicU64,
+    wait_nanos: AtomicU64,
+    hold_nanos: AtomicU64,
+}
+
+// Every named lock registers its counters here. Entries are leaked, so they
+// live for the rest of the program and a report can still include locks which
+// have since been dropped.
+static REGISTRY: Mutex<Vec<&'static LockStats>> = Mutex::new(Vec::new());
+
+/// Profiling state carried by a named [`crate::SpinLock`].
+pub(crate) struct Profile {
+    stats: &'static LockStats,
+    // Only read or written by the thread holding the lock.
+    acquired_at: UnsafeCell<Option<Instant>>,
+}
+
+impl Profile {
+    pub(crate) fn new(name: &'static str) -> Self {
+        let mut registry = REGISTRY.lock().unwrap();
+        // Locks sharing a name, such as the shards of a cache, are reported
+        // together.
+        let stats = match registry.iter().find(|stats| stats.name == name) {
+            Some(stats) => *stats,
+            None => {
+                let stats: &'static LockStats = Box::leak(Box::new(LockStats {
+                    name,
+                    acquisitions: AtomicU64::new(0),
+                    wait_nanos: AtomicU64::new(0),
+                    hold_nanos: AtomicU64::new(0),
+                }));
+                registry.push(stats);
+                stats
+            }
+        };
+        Self {
+            stats,
+            acquired_at: UnsafeCell::new(None),
+        }
+    }
+}
+
+/// Called before attempting to acquire a lock, returning when the wait began.
+pub(crate) fn start(profile: &Option<Profile>) -> Option<Instant> {
+    profile.as_ref().map(|_| Instant::now())
+}
+
+/// Called once a lock has been acquired, with the result of [`start`] if the
+/// thread may have had to wait for it.
+pub(crate) fn acquired(profile: &Option<Profile>, started: Option<Instant>) {
+    if let Some(profile) = profile {
+        let now = Instant::now();
+        let stats = profile.stats;
+        stats.acquisitions.fetch_add(1, Ordering::Relaxed);
+        if let Some(started) = started {
+            stats
+                .wait_nanos
+                .fetch_add(nanos(now - started), Ordering::Relaxed);
+        }
+        // We hold the lock, so nothing else can access this.
+        unsafe { *profile.acquired_at.get() = Some(now) };
+    }
+}
+
+/// Called while still holding a lock, just before it is released.
+pub(crate) fn released(profile: &Option<Profile>) {
+    if let Some(profile) = profile {
+        // We hold the lock, so nothing else can access this.
+        if let Some(acquired_at) = unsafe { (*profile.acquired_at.get()).take() } {
+            profile
+                .stats
+                .hold_nanos
+                .fetch_add(nanos(acquired_at.elapsed()), Ordering::Relaxed);
+        }
+    }
+}
+
+fn nanos(duration: Duration) -> u64 {
+    duration.as_nanos().try_into().unwrap_or(u64::MAX)
+}
+
+/// Totals for every lock sharing a name.
+#[derive(Debug, Clone)]
+pub struct LockProfile {
+    pub name: &'static str,
+    pub acquisitions: u64,
+    /// Total time spent waiting to acquire the lock.
+    pub wait: Duration,
+    /// Total time the lock was held for.
+    pub hold: Duration,
+}
+
+/// Snapshot of every named lock, ordered by total wait time so that the most
+/// contended locks come first.
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub locks: Vec<LockProfile>,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<24} {:>14} {:>14} {:>14}",
+            "name", "acquisitions", "wait", "hold"
+        )?;
+        for lock in &self.locks {
+            writeln!(
+                f,
+                "{:<24} {:>14} {:>14} {:>14}",
+                lock.name,
+                lock.acquisitions,
+                format!("{:?}", lock.wait),
+                format!("{:?}", lock.hold)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Collect the current totals for all locks created with
+/// [`crate::SpinLock::new_named`].
+pub fn report() -> Report {
+    let registry = REGISTRY.lock().unwrap();
+    let mut locks: Vec<_> = registry
+        .iter()
+        .map(|stats| LockProfile {
+            name: stats.name,
+            acquisitions: stats.acquisitions.load(Ordering::Relaxed),
+            wait: Duration::from_nanos(stats.wait_nanos.load(Ordering::Relaxed)),
+            hold: Duration::from_nanos(stats.hold_nanos.load(Ordering::Relaxed)),
+        })
+        .collect();
+    locks.sort_by_key(|lock| std::cmp::Reverse(lock.wait));
+    Report { locks }
+}
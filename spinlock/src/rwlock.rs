@@ -0,0 +1,1622 @@
+use std::{
+    cell::UnsafeCell,
+    fmt,
+    marker::PhantomData,
+    mem::MaybeUninit,
+    ops::{Deref, DerefMut},
+    sync::{
+        atomic::{fence, AtomicU32, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "stats")]
+use crate::stats::{RwLockStats, Stats};
+use crate::{
+    futex::{self, wait, wake_all, wake_one},
+    poison::{self, LockResult},
+    LockedPlaceholder,
+};
+
+// Added to the state for each active reader.
+const READER: u32 = 4;
+// Set while an upgradable reader holds the lock.
+const UPGRADABLE: u32 = 2;
+// Set while a writer, or an upgrading reader, is waiting for the lock.
+const WRITER_WAITING: u32 = 1;
+// The most readers which can hold the lock at once. This is one fewer than
+// fits above the flag bits, so that with both flags set the state can never
+// reach WRITE_LOCKED.
+const MAX_READERS: u32 = u32::MAX / READER - 1;
+// The whole state while write-locked. It has the writer-waiting bit set, so
+// readers block on it.
+const WRITE_LOCKED: u32 = u32::MAX;
+// Set in the version while a writer holds the lock.
+const VERSION_WRITING: u32 = 1;
+// Optimistic reads to try before taking a real read lock.
+const OPTIMISTIC_ATTEMPTS: u32 = 4;
+
+/// How a [`RawRwLock`] chooses between readers and writers which are waiting
+/// for it at the same time, picked when the lock is created.
+///
+/// Whichever side is preferred can be starved by a continuous stream of the
+/// other side, so this is a trade-off to make per workload.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Policy {
+    /// New readers join whenever the lock isn't write-locked, even while a
+    /// writer is waiting. This gives the most read throughput, but writers only
+    /// get in once the readers happen to drain, and may never do so.
+    ReaderPreferred,
+    /// New readers wait while a writer is waiting, and a writer releasing the
+    /// lock hands it straight to the next waiting writer before any readers get
+    /// a turn. Suits rare but urgent writes, at the risk of starving readers.
+    WriterPreferred,
+    /// New readers wait while a writer is waiting, so readers can't starve
+    /// writers, but a writer releasing the lock wakes the waiting readers along
+    /// with one writer and lets them race, so writers can't starve readers.
+    #[default]
+    Fair,
+}
+
+/// The state machine behind [`RwLock`], without any data attached.
+///
+/// The state is four times the number of active readers, plus two while an
+/// upgradable reader holds the lock, plus one if a writer is waiting, or
+/// `u32::MAX` when write-locked. Keeping a writer-waiting bit means new readers
+/// can block once a writer is queued, unless the [`Policy`] prefers readers.
+///
+/// Writers, including readers waiting to upgrade, sleep on a separate writer
+/// wake counter rather than the state. Releasing a lock can then wake one
+/// writer with `wake_one` on the counter, and all the readers with `wake_all`
+/// on the state, without stampeding every waiting writer along with them.
+///
+/// With the `lock_api` feature enabled this implements [`lock_api::RawRwLock`]
+/// and its extension traits, so it can be used as `lock_api::RwLock<RawRwLock, T>`
+/// by generic code written against lock_api.
+///
+/// With the `stats` feature enabled each lock also counts its acquisitions, see
+/// [`RawRwLock::stats`].
+pub struct RawRwLock {
+    state: AtomicU32,
+    // Incremented whenever waiting writers should check the state again.
+    writer_wake_counter: AtomicU32,
+    // Number of writers waiting for the lock, only kept up to date with
+    // `Policy::WriterPreferred`, where unlocking needs to know.
+    writers_waiting: AtomicU32,
+    // Incremented when a writer acquires the lock and again when it releases
+    // it, so that optimistic readers can tell whether a writer got in.
+    version: AtomicU32,
+    policy: Policy,
+    #[cfg(feature = "stats")]
+    stats: Stats,
+}
+
+impl RawRwLock {
+    pub const fn new() -> Self {
+        Self::with_policy(Policy::Fair)
+    }
+
+    pub const fn with_policy(policy: Policy) -> Self {
+        Self {
+            state: AtomicU32::new(0),
+            writer_wake_counter: AtomicU32::new(0),
+            writers_waiting: AtomicU32::new(0),
+            version: AtomicU32::new(0),
+            policy,
+            #[cfg(feature = "stats")]
+            stats: Stats::new(),
+        }
+    }
+
+    /// The [`Policy`] the lock was created with.
+    pub fn policy(&self) -> Policy {
+        self.policy
+    }
+
+    /// Snapshot of the lock's contention statistics.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> RwLockStats {
+        self.stats.snapshot()
+    }
+
+    /// Set the lock's contention statistics back to zero, for example to
+    /// measure a single phase of a program.
+    #[cfg(feature = "stats")]
+    pub fn reset_stats(&self) {
+        self.stats.reset()
+    }
+
+    /// Acquire a shared lock, waiting while the lock is write-locked or, unless
+    /// readers are preferred, a writer is waiting for it.
+    pub fn lock_shared(&self) {
+        self.lock_shared_until(None);
+    }
+
+    /// Attempt to acquire a shared lock without waiting.
+    pub fn try_lock_shared(&self) -> bool {
+        let mut s = self.state.load(Ordering::Relaxed);
+        while !self.blocks_readers(s) {
+            match self.state.compare_exchange_weak(
+                s,
+                add_reader(s),
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => {
+                    #[cfg(feature = "stats")]
+                    self.stats.read(s / READER + 1);
+                    return true;
+                }
+                Err(e) => s = e,
+            }
+        }
+        false
+    }
+
+    /// Attempt to acquire a shared lock, waiting until `deadline` has passed.
+    pub fn try_lock_shared_until(&self, deadline: Instant) -> bool {
+        self.lock_shared_until(Some(deadline))
+    }
+
+    /// Acquire a shared lock, only waiting while the lock is write-locked.
+    ///
+    /// See [`RwLock::read_recursive`].
+    pub fn lock_shared_recursive(&self) {
+        let mut s = self.state.load(Ordering::Relaxed);
+        loop {
+            if s != WRITE_LOCKED {
+                match self.state.compare_exchange_weak(
+                    s,
+                    add_reader(s),
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        #[cfg(feature = "stats")]
+                        self.stats.read(s / READER + 1);
+                        return;
+                    }
+                    Err(e) => s = e,
+                }
+            }
+            if s == WRITE_LOCKED {
+                wait(&self.state, s);
+                s = self.state.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Release a shared lock.
+    ///
+    /// # Safety
+    ///
+    /// The caller must hold a shared lock.
+    pub unsafe fn unlock_shared(&self) {
+        // When the last reader leaves while a writer is waiting, the writer
+        // needs waking.
+        let s = self.state.fetch_sub(READER, Ordering::Release) - READER;
+        if s & WRITER_WAITING != 0 && s < READER {
+            self.wake_writer(s);
+        }
+    }
+
+    /// Acquire an exclusive lock, waiting while there are any readers or
+    /// another writer.
+    pub fn lock_exclusive(&self) {
+        self.lock_exclusive_until(None);
+    }
+
+    /// Attempt to acquire an exclusive lock without waiting.
+    pub fn try_lock_exclusive(&self) -> bool {
+        let mut s = self.state.load(Ordering::Relaxed);
+        while s <= WRITER_WAITING {
+            match self.state.compare_exchange_weak(
+                s,
+                WRITE_LOCKED,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => {
+                    self.begin_write();
+                    #[cfg(feature = "stats")]
+                    self.stats.write(Duration::ZERO);
+                    return true;
+                }
+                Err(e) => s = e,
+            }
+        }
+        false
+    }
+
+    /// Attempt to acquire an exclusive lock, waiting until `deadline` has
+    /// passed.
+    pub fn try_lock_exclusive_until(&self, deadline: Instant) -> bool {
+        self.lock_exclusive_until(Some(deadline))
+    }
+
+    /// Release an exclusive lock.
+    ///
+    /// # Safety
+    ///
+    /// The caller must hold an exclusive lock.
+    pub unsafe fn unlock_exclusive(&self) {
+        self.end_write();
+        if self.policy == Policy::WriterPreferred && self.writers_waiting.load(Ordering::SeqCst) > 0
+        {
+            // Leave only the writer-waiting bit set, which keeps readers out
+            // while the next writer is woken to take over.
+            self.state.store(WRITER_WAITING, Ordering::SeqCst);
+            // A waiting writer which times out clears the bit after counting
+            // itself out, so either it sees our store and clears the bit, or we
+            // see that nobody is left to hand over to.
+            if self.writers_waiting.load(Ordering::SeqCst) > 0 {
+                self.writer_wake_counter.fetch_add(1, Ordering::Release);
+                wake_one(&self.writer_wake_counter);
+                return;
+            }
+            // If this fails, a writer which hadn't started waiting yet has
+            // already taken the lock, and wakes everyone else when it unlocks.
+            if self
+                .state
+                .compare_exchange(WRITER_WAITING, 0, Ordering::Release, Ordering::Relaxed)
+                .is_err()
+            {
+                return;
+            }
+        } else {
+            self.state.store(0, Ordering::Release);
+        }
+        // Wake one writer along with all the readers, and let them race for the
+        // lock. Nothing can hold an upgradable lock alongside a writer, so only
+        // plain writers can be waiting on the counter.
+        self.writer_wake_counter.fetch_add(1, Ordering::Release);
+        wake_one(&self.writer_wake_counter);
+        wake_all(&self.state);
+    }
+
+    /// Convert an exclusive lock into a shared one without releasing it.
+    ///
+    /// # Safety
+    ///
+    /// The caller must hold an exclusive lock.
+    pub unsafe fn downgrade(&self) {
+        self.end_write();
+        // Going straight from write-locked to a single reader drops the
+        // writer-waiting bit, which a waiting writer sets again once woken.
+        self.state.store(READER, Ordering::Release);
+        self.writer_wake_counter.fetch_add(1, Ordering::Release);
+        wake_one(&self.writer_wake_counter);
+        wake_all(&self.state);
+    }
+
+    /// Acquire an upgradable shared lock, waiting while the lock is
+    /// write-locked, another upgradable reader holds it or, unless readers are
+    /// preferred, a writer is waiting.
+    pub fn lock_upgradable(&self) {
+        let mut s = self.state.load(Ordering::Relaxed);
+        loop {
+            // Write-locked is all bits set, so this covers writers too.
+            if s & UPGRADABLE == 0 && !self.blocks_readers(s) {
+                match self.state.compare_exchange_weak(
+                    s,
+                    s | UPGRADABLE,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        #[cfg(feature = "stats")]
+                        self.stats.read(s / READER + 1);
+                        return;
+                    }
+                    Err(e) => s = e,
+                }
+            }
+            if s & UPGRADABLE != 0 || self.blocks_readers(s) {
+                wait(&self.state, s);
+                s = self.state.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Attempt to acquire an upgradable shared lock without waiting.
+    pub fn try_lock_upgradable(&self) -> bool {
+        let mut s = self.state.load(Ordering::Relaxed);
+        while s & UPGRADABLE == 0 && !self.blocks_readers(s) {
+            match self.state.compare_exchange_weak(
+                s,
+                s | UPGRADABLE,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => {
+                    #[cfg(feature = "stats")]
+                    self.stats.read(s / READER + 1);
+                    return true;
+                }
+                Err(e) => s = e,
+            }
+        }
+        false
+    }
+
+    /// Release an upgradable shared lock.
+    ///
+    /// # Safety
+    ///
+    /// The caller must hold an upgradable lock.
+    pub unsafe fn unlock_upgradable(&self) {
+        let s = self.state.fetch_sub(UPGRADABLE, Ordering::Release) - UPGRADABLE;
+        // Other upgradable readers may be waiting for the bit, and can take it
+        // whenever readers aren't blocked, which with readers preferred is
+        // even while a writer is waiting.
+        if !self.blocks_readers(s) {
+            wake_all(&self.state);
+        }
+        if s & WRITER_WAITING != 0 && s < READER {
+            self.wake_writer(s);
+        }
+    }
+
+    /// Upgrade an upgradable lock to an exclusive one, waiting for the
+    /// remaining readers to leave.
+    ///
+    /// # Safety
+    ///
+    /// The caller must hold an upgradable lock.
+    pub unsafe fn upgrade(&self) {
+        #[cfg(feature = "stats")]
+        let started = Instant::now();
+        let mut s = self.state.load(Ordering::Relaxed);
+        loop {
+            // Holding the upgradable bit keeps other writers out, so only the
+            // readers need to drain.
+            if s < READER {
+                match self.state.compare_exchange(
+                    s,
+                    WRITE_LOCKED,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        self.begin_write();
+                        #[cfg(feature = "stats")]
+                        self.stats.write(started.elapsed());
+                        return;
+                    }
+                    Err(e) => {
+                        s = e;
+                        continue;
+                    }
+                }
+            }
+            // Stop new readers from joining while we wait for the rest.
+            if s & WRITER_WAITING == 0 {
+                match self.state.compare_exchange(
+                    s,
+                    s | WRITER_WAITING,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {}
+                    Err(e) => {
+                        s = e;
+                        continue;
+                    }
+                }
+            }
+            // Load the counter before checking the state again, so that a
+            // reader leaving in between is guaranteed to change it.
+            let w = self.writer_wake_counter.load(Ordering::Acquire);
+            s = self.state.load(Ordering::Relaxed);
+            if s >= READER {
+                wait(&self.writer_wake_counter, w);
+                s = self.state.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Attempt to upgrade an upgradable lock to an exclusive one without
+    /// waiting.
+    ///
+    /// # Safety
+    ///
+    /// The caller must hold an upgradable lock.
+    pub unsafe fn try_upgrade(&self) -> bool {
+        let mut s = self.state.load(Ordering::Relaxed);
+        while s < READER {
+            match self.state.compare_exchange_weak(
+                s,
+                WRITE_LOCKED,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => {
+                    self.begin_write();
+                    #[cfg(feature = "stats")]
+                    self.stats.write(Duration::ZERO);
+                    return true;
+                }
+                Err(e) => s = e,
+            }
+        }
+        false
+    }
+
+    /// Whether the lock is currently held by anything, shared or exclusive.
+    ///
+    /// This is only a snapshot, intended for diagnostics and assertions.
+    pub fn is_locked(&self) -> bool {
+        self.state.load(Ordering::Relaxed) > WRITER_WAITING
+    }
+
+    /// Number of readers currently holding the lock, counting an upgradable
+    /// reader, or zero while it is write-locked.
+    ///
+    /// Like [`RawRwLock::is_locked`], this is only a snapshot.
+    pub fn reader_count(&self) -> u32 {
+        let s = self.state.load(Ordering::Relaxed);
+        if s == WRITE_LOCKED {
+            return 0;
+        }
+        s / READER + u32::from(s & UPGRADABLE != 0)
+    }
+
+    /// Whether a writer currently holds the lock.
+    ///
+    /// Like [`RawRwLock::is_locked`], this is only a snapshot.
+    pub fn is_write_locked(&self) -> bool {
+        self.state.load(Ordering::Relaxed) == WRITE_LOCKED
+    }
+
+    // Returns false if `deadline` passed before the lock could be acquired.
+    fn lock_shared_until(&self, deadline: Option<Instant>) -> bool {
+        let mut s = self.state.load(Ordering::Relaxed);
+        loop {
+            if !self.blocks_readers(s) {
+                match self.state.compare_exchange_weak(
+                    s,
+                    add_reader(s),
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        #[cfg(feature = "stats")]
+                        self.stats.read(s / READER + 1);
+                        return true;
+                    }
+                    Err(e) => s = e,
+                }
+            }
+            if self.blocks_readers(s) {
+                if !wait_until(&self.state, s, deadline) {
+                    return false;
+                }
+                s = self.state.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    // Returns false if `deadline` passed before the lock could be acquired.
+    fn lock_exclusive_until(&self, deadline: Option<Instant>) -> bool {
+        #[cfg(feature = "stats")]
+        let started = Instant::now();
+        let count = self.policy == Policy::WriterPreferred;
+        let mut counted = false;
+        let mut s = self.state.load(Ordering::Relaxed);
+        loop {
+            // Try to lock if unlocked, whether or not a writer is waiting.
+            if s <= WRITER_WAITING {
+                match self.state.compare_exchange(
+                    s,
+                    WRITE_LOCKED,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        if counted {
+                            self.writers_waiting.fetch_sub(1, Ordering::Relaxed);
+                        }
+                        self.begin_write();
+                        #[cfg(feature = "stats")]
+                        self.stats.write(started.elapsed());
+                        return true;
+                    }
+                    Err(e) => {
+                        s = e;
+                        continue;
+                    }
+                }
+            }
+            // Stop new readers from joining, by setting the writer-waiting bit.
+            if s & WRITER_WAITING == 0 {
+                match self.state.compare_exchange(
+                    s,
+                    s | WRITER_WAITING,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {}
+                    Err(e) => {
+                        s = e;
+                        continue;
+                    }
+                }
+            }
+            if count && !counted {
+                self.writers_waiting.fetch_add(1, Ordering::SeqCst);
+                counted = true;
+            }
+            // Load the counter before checking the state again, so that an
+            // unlock in between is guaranteed to change it.
+            let w = self.writer_wake_counter.load(Ordering::Acquire);
+            s = self.state.load(Ordering::Relaxed);
+            if s > WRITER_WAITING {
+                if !wait_until(&self.writer_wake_counter, w, deadline) {
+                    // The writer-waiting bit we set would otherwise keep
+                    // blocking readers. It may be shared with other waiting
+                    // writers, and we may have taken a wakeup meant for one of
+                    // them, so wake them all to set it again if still waiting.
+                    if counted {
+                        self.writers_waiting.fetch_sub(1, Ordering::SeqCst);
+                    }
+                    self.state
+                        .fetch_update(Ordering::SeqCst, Ordering::Relaxed, |s| {
+                            (s != WRITE_LOCKED).then_some(s & !WRITER_WAITING)
+                        })
+                        .ok();
+                    self.writer_wake_counter.fetch_add(1, Ordering::Release);
+                    wake_all(&self.writer_wake_counter);
+                    wake_all(&self.state);
+                    return false;
+                }
+                s = self.state.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    // The version to validate an optimistic read against, or `None` if a
+    // writer currently holds the lock.
+    pub(crate) fn read_version(&self) -> Option<u32> {
+        let v = self.version.load(Ordering::Acquire);
+        (v & VERSION_WRITING == 0).then_some(v)
+    }
+
+    // Whether no writer has acquired the lock since `read_version` returned
+    // `version`, meaning anything read in between was not being written.
+    pub(crate) fn validate_version(&self, version: u32) -> bool {
+        // Keeps the reads being validated from moving after the check.
+        fence(Ordering::Acquire);
+        self.version.load(Ordering::Relaxed) == version
+    }
+
+    // Called just after acquiring an exclusive lock. The fence stops the
+    // writer's changes moving before the version is bumped, so an optimistic
+    // reader which saw any of them will also see the new version.
+    fn begin_write(&self) {
+        self.version.fetch_add(1, Ordering::Relaxed);
+        fence(Ordering::Release);
+    }
+
+    // Called just before releasing an exclusive lock.
+    fn end_write(&self) {
+        self.version.fetch_add(1, Ordering::Release);
+    }
+
+    // Whether a new reader has to wait given the state `s`.
+    fn blocks_readers(&self, s: u32) -> bool {
+        match self.policy {
+            Policy::ReaderPreferred => s == WRITE_LOCKED,
+            Policy::WriterPreferred | Policy::Fair => s & WRITER_WAITING != 0,
+        }
+    }
+
+    // Wake a writer once the readers have drained, given the state `s` left
+    // behind. If an upgradable reader is waiting to upgrade it must be the one
+    // woken, but a plain writer may be waiting too, so wake them all.
+    fn wake_writer(&self, s: u32) {
+        self.writer_wake_counter.fetch_add(1, Ordering::Release);
+        if s & UPGRADABLE != 0 {
+            wake_all(&self.writer_wake_counter);
+        } else {
+            wake_one(&self.writer_wake_counter);
+        }
+    }
+}
+
+// The state after adding a reader to `s`, which must not be write-locked.
+//
+// Leaking read guards in a loop could otherwise wrap the count into the
+// write-locked state. Unlike `Arc::clone`, which aborts because the count has
+// already been incremented, this is checked before the state changes, so the
+// panic leaves the lock intact.
+fn add_reader(s: u32) -> u32 {
+    if s / READER >= MAX_READERS {
+        reader_overflow();
+    }
+    s + READER
+}
+
+#[cold]
+#[inline(never)]
+fn reader_overflow() -> ! {
+    panic!("too many readers holding the RwLock");
+}
+
+// Sleep while `atomic` is `value`, returning false without sleeping if
+// `deadline` has already passed. Both waits go through the same futex backend,
+// so the timed one is woken by the same calls as the untimed one.
+fn wait_until(atomic: &AtomicU32, value: u32, deadline: Option<Instant>) -> bool {
+    match deadline {
+        None => wait(atomic, value),
+        Some(deadline) => {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            futex::wait_timeout(atomic, value, deadline - now);
+        }
+    }
+    true
+}
+
+impl Default for RawRwLock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// The lock_api traits mostly forward to the inherent methods above, which take
+// priority over trait methods of the same name.
+#[cfg(feature = "lock_api")]
+unsafe impl lock_api::RawRwLock for RawRwLock {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const INIT: Self = Self::new();
+    // Unlocking only touches the futex word, so it doesn't matter which thread
+    // a guard is dropped on.
+    type GuardMarker = lock_api::GuardSend;
+
+    fn lock_shared(&self) {
+        self.lock_shared()
+    }
+    fn try_lock_shared(&self) -> bool {
+        self.try_lock_shared()
+    }
+    unsafe fn unlock_shared(&self) {
+        self.unlock_shared()
+    }
+    fn lock_exclusive(&self) {
+        self.lock_exclusive()
+    }
+    fn try_lock_exclusive(&self) -> bool {
+        self.try_lock_exclusive()
+    }
+    unsafe fn unlock_exclusive(&self) {
+        self.unlock_exclusive()
+    }
+    fn is_locked(&self) -> bool {
+        self.is_locked()
+    }
+}
+
+#[cfg(feature = "lock_api")]
+unsafe impl lock_api::RawRwLockRecursive for RawRwLock {
+    fn lock_shared_recursive(&self) {
+        self.lock_shared_recursive()
+    }
+    fn try_lock_shared_recursive(&self) -> bool {
+        let mut s = self.state.load(Ordering::Relaxed);
+        while s != WRITE_LOCKED {
+            match self.state.compare_exchange_weak(
+                s,
+                add_reader(s),
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => {
+                    #[cfg(feature = "stats")]
+                    self.stats.read(s / READER + 1);
+                    return true;
+                }
+                Err(e) => s = e,
+            }
+        }
+        false
+    }
+}
+
+#[cfg(feature = "lock_api")]
+unsafe impl lock_api::RawRwLockDowngrade for RawRwLock {
+    unsafe fn downgrade(&self) {
+        self.downgrade()
+    }
+}
+
+#[cfg(feature = "lock_api")]
+unsafe impl lock_api::RawRwLockTimed for RawRwLock {
+    type Duration = Duration;
+    type Instant = Instant;
+
+    fn try_lock_shared_for(&self, timeout: Duration) -> bool {
+        self.try_lock_shared_until(Instant::now() + timeout)
+    }
+    fn try_lock_shared_until(&self, deadline: Instant) -> bool {
+        self.try_lock_shared_until(deadline)
+    }
+    fn try_lock_exclusive_for(&self, timeout: Duration) -> bool {
+        self.try_lock_exclusive_until(Instant::now() + timeout)
+    }
+    fn try_lock_exclusive_until(&self, deadline: Instant) -> bool {
+        self.try_lock_exclusive_until(deadline)
+    }
+}
+
+#[cfg(feature = "lock_api")]
+unsafe impl lock_api::RawRwLockUpgrade for RawRwLock {
+    fn lock_upgradable(&self) {
+        self.lock_upgradable()
+    }
+    fn try_lock_upgradable(&self) -> bool {
+        self.try_lock_upgradable()
+    }
+    unsafe fn unlock_upgradable(&self) {
+        self.unlock_upgradable()
+    }
+    unsafe fn upgrade(&self) {
+        self.upgrade()
+    }
+    unsafe fn try_upgrade(&self) -> bool {
+        self.try_upgrade()
+    }
+}
+
+/// Implementation of a reader-writer lock.
+///
+/// Any number of readers can hold the lock at the same time, or a single writer
+/// can hold it exclusively. Rather than spinning, threads which cannot acquire
+/// the lock are put to sleep with a futex wait on the state and woken again when
+/// it changes. The locking itself is done by a [`RawRwLock`].
+pub struct RwLock<T> {
+    raw: RawRwLock,
+    poison: poison::Flag,
+    value: UnsafeCell<T>,
+}
+
+// Readers share `&T` between threads, so unlike a mutex `T: Sync` is needed
+// as well as `T: Send`.
+unsafe impl<T> Sync for RwLock<T> where T: Send + Sync {}
+
+impl<T> RwLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self::with_policy(value, Policy::Fair)
+    }
+
+    /// Create a lock which chooses between waiting readers and writers
+    /// according to `policy`.
+    pub const fn with_policy(value: T, policy: Policy) -> Self {
+        Self {
+            raw: RawRwLock::with_policy(policy),
+            poison: poison::Flag::new(),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Acquire a shared lock as a [`ReadGuard`], waiting while the lock is
+    /// write-locked or, unless readers are preferred, a writer is waiting.
+    pub fn read(&self) -> LockResult<ReadGuard<'_, T>> {
+        self.raw.lock_shared();
+        self.poison.result(ReadGuard { rwlock: self })
+    }
+
+    /// Acquire an exclusive lock as a [`WriteGuard`], waiting while there are
+    /// any readers or another writer.
+    pub fn write(&self) -> LockResult<WriteGuard<'_, T>> {
+        self.raw.lock_exclusive();
+        self.poison.result(WriteGuard::new(self))
+    }
+
+    /// Snapshot of the lock's contention statistics, see [`crate::stats`].
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> RwLockStats {
+        self.raw.stats()
+    }
+
+    /// Run `f` on a copy of the data read without taking the lock, falling back
+    /// to [`RwLock::with_read`] if writers keep getting in the way.
+    ///
+    /// The copy is taken while writers may be changing the data, and is only
+    /// trusted once the lock's version shows that no writer acquired the lock
+    /// in the meantime, as with a seqlock. Readers then never write to shared
+    /// memory at all, so they don't contend with each other. This needs `T:
+    /// Copy`, as a copy taken mid-write is discarded without being dropped, and
+    /// suits small values read far more often than they are written.
+    ///
+    /// Strictly, the copy is a data race under Rust's memory model: it is a
+    /// plain (volatile) read which can overlap a writer's plain write through
+    /// the [`WriteGuard`], and race detectors will report it. This relies on
+    /// the same assumption as crossbeam's `AtomicCell` and most seqlocks, that
+    /// such a read only yields some arbitrary bytes, which are thrown away
+    /// unless the version shows no writer was there. Copying through atomic
+    /// loads wouldn't remove the race, as writers don't store atomically, and
+    /// can't load the padding bytes many `T`s have.
+    pub fn try_optimistic_read<R>(&self, f: impl FnOnce(&T) -> R) -> LockResult<R>
+    where
+        T: Copy,
+    {
+        for _ in 0..OPTIMISTIC_ATTEMPTS {
+            if let Some(version) = self.raw.read_version() {
+                // The copy may be torn, so it stays uninitialised until
+                // validated. Volatile stops the compiler assuming the data
+                // can't change underneath it, see above for why the race is
+                // tolerated.
+                let copy =
+                    unsafe { std::ptr::read_volatile(self.value.get() as *const MaybeUninit<T>) };
+                if self.raw.validate_version(version) {
+                    // No writer got in, so the copy is a whole value.
+                    let value = unsafe { copy.assume_init() };
+                    return poison::wrap(self.poison.get(), f(&value));
+                }
+            }
+            std::hint::spin_loop();
+        }
+        self.with_read(f)
+    }
+
+    /// Run `f` with shared access to the data, releasing the lock as soon as it
+    /// returns.
+    ///
+    /// `f` runs even if the lock is poisoned, in which case its result is
+    /// returned inside the `PoisonError` instead.
+    pub fn with_read<R>(&self, f: impl FnOnce(&T) -> R) -> LockResult<R> {
+        self.raw.lock_shared();
+        let guard = ReadGuard { rwlock: self };
+        poison::wrap(self.poison.get(), f(&guard))
+    }
+
+    /// Run `f` with exclusive access to the data, releasing the lock as soon
+    /// as it returns.
+    ///
+    /// The lock is held by a [`WriteGuard`] internally, so it is also released,
+    /// and poisoned, if `f` panics. As with [`RwLock::with_read`], `f` runs
+    /// even if the lock is already poisoned.
+    pub fn with_write<R>(&self, f: impl FnOnce(&mut T) -> R) -> LockResult<R> {
+        self.raw.lock_exclusive();
+        let mut guard = WriteGuard::new(self);
+        poison::wrap(self.poison.get(), f(&mut guard))
+    }
+
+    /// Acquire a shared lock as a [`ReadGuard`], even if a writer is waiting.
+    ///
+    /// A thread which already holds a read lock and calls [`RwLock::read`]
+    /// again will deadlock if a writer has started waiting in between, the
+    /// writer waits for the first read lock and the second waits for the writer.
+    /// This only waits while the lock is write-locked, at the cost of letting
+    /// readers starve writers if it is used for every read.
+    pub fn read_recursive(&self) -> LockResult<ReadGuard<'_, T>> {
+        self.raw.lock_shared_recursive();
+        self.poison.result(ReadGuard { rwlock: self })
+    }
+
+    /// Attempt to acquire a shared lock, waiting for at most `timeout`.
+    ///
+    /// The thread sleeps in [`crate::futex::wait_timeout`] while it waits, so
+    /// on targets without a native backend it polls rather than sleeping.
+    pub fn try_read_for(&self, timeout: Duration) -> Option<LockResult<ReadGuard<'_, T>>> {
+        self.try_read_until(Instant::now() + timeout)
+    }
+
+    /// Attempt to acquire a shared lock, waiting until `deadline` has passed.
+    pub fn try_read_until(&self, deadline: Instant) -> Option<LockResult<ReadGuard<'_, T>>> {
+        // Only build the guard on success, dropping one would unlock.
+        if self.raw.try_lock_shared_until(deadline) {
+            Some(self.poison.result(ReadGuard { rwlock: self }))
+        } else {
+            None
+        }
+    }
+
+    /// Attempt to acquire an exclusive lock, waiting for at most `timeout`.
+    pub fn try_write_for(&self, timeout: Duration) -> Option<LockResult<WriteGuard<'_, T>>> {
+        self.try_write_until(Instant::now() + timeout)
+    }
+
+    /// Attempt to acquire an exclusive lock, waiting until `deadline` has
+    /// passed.
+    pub fn try_write_until(&self, deadline: Instant) -> Option<LockResult<WriteGuard<'_, T>>> {
+        if self.raw.try_lock_exclusive_until(deadline) {
+            Some(self.poison.result(WriteGuard::new(self)))
+        } else {
+            None
+        }
+    }
+
+    /// Acquire a shared lock on each of `locks`, returning the guards in the
+    /// same order as the locks were given.
+    ///
+    /// See [`RwLock::write_many`], the locks are acquired in address order for
+    /// the same reason. Returns an error holding every guard if any of the
+    /// locks is poisoned.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the same lock appears more than once.
+    pub fn read_many<'a>(locks: &[&'a RwLock<T>]) -> LockResult<Vec<ReadGuard<'a, T>>> {
+        Self::lock_many(locks, |rwlock| {
+            rwlock.raw.lock_shared();
+            ReadGuard { rwlock }
+        })
+    }
+
+    /// Acquire an exclusive lock on each of `locks`, returning the guards in
+    /// the same order as the locks were given.
+    ///
+    /// Two threads locking the same pair of locks in opposite orders, such as
+    /// when transferring between two accounts in each direction, can deadlock
+    /// each holding one and waiting for the other. Here the locks are always
+    /// acquired in order of their address, whatever order they are given in,
+    /// so any number of threads can lock overlapping sets safely. Returns an
+    /// error holding every guard if any of the locks is poisoned.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the same lock appears more than once, which would otherwise
+    /// deadlock.
+    pub fn write_many<'a>(locks: &[&'a RwLock<T>]) -> LockResult<Vec<WriteGuard<'a, T>>> {
+        Self::lock_many(locks, |rwlock| {
+            rwlock.raw.lock_exclusive();
+            WriteGuard::new(rwlock)
+        })
+    }
+
+    fn lock_many<'a, G>(
+        locks: &[&'a RwLock<T>],
+        lock: impl Fn(&'a RwLock<T>) -> G,
+    ) -> LockResult<Vec<G>> {
+        let mut order: Vec<usize> = (0..locks.len()).collect();
+        order.sort_by_key(|&i| locks[i] as *const Self);
+        assert!(
+            order
+                .windows(2)
+                .all(|w| !std::ptr::eq(locks[w[0]], locks[w[1]])),
+            "the same RwLock was given more than once"
+        );
+
+        let mut guards: Vec<Option<G>> = locks.iter().map(|_| None).collect();
+        let mut poisoned = false;
+        for i in order {
+            guards[i] = Some(lock(locks[i]));
+            poisoned |= locks[i].poison.get();
+        }
+        let guards = guards.into_iter().map(Option::unwrap).collect();
+        poison::wrap(poisoned, guards)
+    }
+
+    /// Acquire a shared lock as an [`OwnedReadGuard`], which keeps the
+    /// [`RwLock`] alive until it is dropped.
+    ///
+    /// As the guard has no lifetime tied to the lock, it can be stored or moved
+    /// into spawned threads.
+    pub fn read_owned(self: Arc<Self>) -> LockResult<OwnedReadGuard<T>> {
+        self.raw.lock_shared();
+        poison::wrap(self.poison.get(), OwnedReadGuard { rwlock: self })
+    }
+
+    /// Acquire an exclusive lock as an [`OwnedWriteGuard`], which keeps the
+    /// [`RwLock`] alive until it is dropped.
+    pub fn write_owned(self: Arc<Self>) -> LockResult<OwnedWriteGuard<T>> {
+        self.raw.lock_exclusive();
+        let poisoned = self.poison.get();
+        let poison = self.poison.guard();
+        poison::wrap(
+            poisoned,
+            OwnedWriteGuard {
+                rwlock: self,
+                poison,
+            },
+        )
+    }
+
+    /// Acquire a shared lock as an [`UpgradableReadGuard`], which can later be
+    /// upgraded to a [`WriteGuard`] without releasing the lock in between.
+    ///
+    /// Only one upgradable reader may hold the lock at a time, alongside any
+    /// number of plain readers. This waits while the lock is write-locked, a
+    /// writer is waiting or another upgradable reader holds it.
+    ///
+    /// This is what database lock managers call shared with intent exclusive
+    /// (SIX): it reserves the right to write without blocking readers until
+    /// [`UpgradableReadGuard::upgrade`] actually needs exclusive access.
+    pub fn upgradable_read(&self) -> LockResult<UpgradableReadGuard<'_, T>> {
+        self.raw.lock_upgradable();
+        self.poison.result(UpgradableReadGuard { rwlock: self })
+    }
+
+    /// Attempt to acquire an upgradable shared lock without waiting.
+    ///
+    /// Returns `None` if the lock is write-locked, a writer is waiting or
+    /// another upgradable reader holds it.
+    pub fn try_upgradable_read(&self) -> Option<LockResult<UpgradableReadGuard<'_, T>>> {
+        if self.raw.try_lock_upgradable() {
+            Some(self.poison.result(UpgradableReadGuard { rwlock: self }))
+        } else {
+            None
+        }
+    }
+
+    /// Attempt to acquire a shared lock without waiting.
+    ///
+    /// Returns `None` if the lock is write-locked or a writer is waiting.
+    pub fn try_read(&self) -> Option<LockResult<ReadGuard<'_, T>>> {
+        if self.raw.try_lock_shared() {
+            Some(self.poison.result(ReadGuard { rwlock: self }))
+        } else {
+            None
+        }
+    }
+
+    /// Attempt to acquire an exclusive lock without waiting.
+    ///
+    /// Returns `None` if there are any readers or another writer.
+    pub fn try_write(&self) -> Option<LockResult<WriteGuard<'_, T>>> {
+        if self.raw.try_lock_exclusive() {
+            Some(self.poison.result(WriteGuard::new(self)))
+        } else {
+            None
+        }
+    }
+
+    /// Consume the lock, returning the data without any locking.
+    ///
+    /// Owning the lock proves nothing else can be using it. If the lock is
+    /// poisoned the data is still returned, inside the error.
+    pub fn into_inner(self) -> LockResult<T> {
+        let Self { poison, value, .. } = self;
+        poison.result(value.into_inner())
+    }
+
+    /// Mutable access to the data without any locking, as the `&mut self`
+    /// borrow already guarantees exclusive access.
+    pub fn get_mut(&mut self) -> LockResult<&mut T> {
+        self.poison.result(self.value.get_mut())
+    }
+
+    /// Raw pointer to the underlying data, without taking the lock, see
+    /// [`crate::SpinLock::data_ptr`].
+    ///
+    /// Reading through the pointer needs a read or write lock to be held, and
+    /// writing through it a write lock, or some other guarantee that no other
+    /// thread is accessing the data. Poisoning is not checked.
+    pub fn data_ptr(&self) -> *mut T {
+        self.value.get()
+    }
+
+    /// Number of readers currently holding the lock, see
+    /// [`RawRwLock::reader_count`].
+    pub fn reader_count(&self) -> u32 {
+        self.raw.reader_count()
+    }
+
+    /// Whether a writer currently holds the lock, see
+    /// [`RawRwLock::is_write_locked`].
+    pub fn is_write_locked(&self) -> bool {
+        self.raw.is_write_locked()
+    }
+
+    /// Whether a thread panicked while holding a write lock. Always `false`
+    /// without the `poison` feature.
+    pub fn is_poisoned(&self) -> bool {
+        self.poison.get()
+    }
+
+    /// Clear the poisoned state, once the data has been checked or repaired
+    /// through the guard inside a [`poison::PoisonError`].
+    pub fn clear_poison(&self) {
+        self.poison.clear();
+    }
+}
+
+impl<T: Default> Default for RwLock<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for RwLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("RwLock");
+        // Like `try_read`, so formatting never blocks, but showing the data
+        // whether or not the lock is poisoned.
+        if self.raw.try_lock_shared() {
+            let guard = ReadGuard { rwlock: self };
+            d.field("data", &&*guard);
+        } else {
+            d.field("data", &LockedPlaceholder);
+        }
+        d.field("poisoned", &self.poison.get());
+        d.finish_non_exhaustive()
+    }
+}
+
+pub struct ReadGuard<'a, T> {
+    rwlock: &'a RwLock<T>,
+}
+
+impl<'a, T> ReadGuard<'a, T> {
+    /// Consume the guard without unlocking, leaving the [`RwLock`] read-locked
+    /// forever and returning a shared reference to the data.
+    ///
+    /// Other readers can still lock it, but no writer ever can again, so the
+    /// reference stays valid for as long as the lock itself. This suits data
+    /// which is initialised once and then only read, or references handed to
+    /// code which never gives them back.
+    pub fn leak(guard: Self) -> &'a T {
+        let rwlock = guard.rwlock;
+        std::mem::forget(guard);
+        // The shared lock is never released, so no writer can invalidate this.
+        unsafe { &*rwlock.value.get() }
+    }
+
+    /// Narrow the guard to part of the locked data, such as a single field.
+    ///
+    /// guard: Self is used here so that it must be called as
+    /// ReadGuard::map(guard, ...) to avoid ambiguity with methods on T.
+    pub fn map<U>(guard: Self, f: impl FnOnce(&T) -> &U) -> MappedReadGuard<'a, U> {
+        let rwlock = guard.rwlock;
+        std::mem::forget(guard);
+        // The forgotten guard held a shared lock, which the mapped guard now
+        // releases instead.
+        let value = f(unsafe { &*rwlock.value.get() });
+        MappedReadGuard {
+            raw: &rwlock.raw,
+            value,
+        }
+    }
+
+    /// Like [`ReadGuard::map`], but `f` may decline by returning `None`, in
+    /// which case the original guard is handed back.
+    pub fn try_map<U>(
+        guard: Self,
+        f: impl FnOnce(&T) -> Option<&U>,
+    ) -> Result<MappedReadGuard<'a, U>, Self> {
+        let rwlock = guard.rwlock;
+        // Existence of the guard implies nobody holds a write lock.
+        match f(unsafe { &*rwlock.value.get() }) {
+            Some(value) => {
+                std::mem::forget(guard);
+                Ok(MappedReadGuard {
+                    raw: &rwlock.raw,
+                    value,
+                })
+            }
+            None => Err(guard),
+        }
+    }
+}
+
+impl<T> Deref for ReadGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // Existence of the guard implies nobody holds a write lock, so this is
+        // safe to do.
+        unsafe { &*self.rwlock.value.get() }
+    }
+}
+
+impl<T> Drop for ReadGuard<'_, T> {
+    fn drop(&mut self) {
+        // Existence of the guard implies we hold a shared lock.
+        unsafe { self.rwlock.raw.unlock_shared() }
+    }
+}
+
+/// A shared lock which can be atomically upgraded to an exclusive one.
+///
+/// Dropping a [`ReadGuard`] and then calling [`RwLock::write`] leaves a window
+/// where another writer can get in first and change the data the decision to
+/// write was based on. Upgrading instead keeps other writers out throughout.
+pub struct UpgradableReadGuard<'a, T> {
+    rwlock: &'a RwLock<T>,
+}
+
+impl<'a, T> UpgradableReadGuard<'a, T> {
+    /// Upgrade to a [`WriteGuard`], waiting for the remaining readers to leave.
+    ///
+    /// guard: Self is used here so that it must be called as
+    /// UpgradableReadGuard::upgrade(guard) to avoid ambiguity with methods on T.
+    pub fn upgrade(guard: Self) -> WriteGuard<'a, T> {
+        let rwlock = guard.rwlock;
+        std::mem::forget(guard);
+        // The forgotten guard held an upgradable lock.
+        unsafe { rwlock.raw.upgrade() };
+        WriteGuard::new(rwlock)
+    }
+
+    /// Attempt to upgrade to a [`WriteGuard`] without waiting.
+    ///
+    /// Returns the guard unchanged if there are still other readers.
+    pub fn try_upgrade(guard: Self) -> Result<WriteGuard<'a, T>, Self> {
+        let rwlock = guard.rwlock;
+        // Existence of the guard implies we hold an upgradable lock.
+        if unsafe { rwlock.raw.try_upgrade() } {
+            std::mem::forget(guard);
+            Ok(WriteGuard::new(rwlock))
+        } else {
+            Err(guard)
+        }
+    }
+}
+
+impl<T> Deref for UpgradableReadGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // Existence of the guard implies nobody holds a write lock, so this is
+        // safe to do.
+        unsafe { &*self.rwlock.value.get() }
+    }
+}
+
+impl<T> Drop for UpgradableReadGuard<'_, T> {
+    fn drop(&mut self) {
+        // Existence of the guard implies we hold an upgradable lock.
+        unsafe { self.rwlock.raw.unlock_upgradable() }
+    }
+}
+
+pub struct WriteGuard<'a, T> {
+    rwlock: &'a RwLock<T>,
+    poison: poison::Guard,
+}
+
+impl<'a, T> WriteGuard<'a, T> {
+    fn new(rwlock: &'a RwLock<T>) -> Self {
+        Self {
+            rwlock,
+            poison: rwlock.poison.guard(),
+        }
+    }
+
+    /// Convert to a [`ReadGuard`] without releasing the lock, so that no other
+    /// writer can get in between publishing a change and reading it back.
+    ///
+    /// guard: Self is used here so that it must be called as
+    /// WriteGuard::downgrade(guard) to avoid ambiguity with methods on T.
+    pub fn downgrade(guard: Self) -> ReadGuard<'a, T> {
+        let rwlock = guard.rwlock;
+        rwlock.poison.done(&guard.poison);
+        std::mem::forget(guard);
+        // The forgotten guard held an exclusive lock.
+        unsafe { rwlock.raw.downgrade() };
+        ReadGuard { rwlock }
+    }
+}
+
+impl<'a, T> WriteGuard<'a, T> {
+    /// Consume the guard without unlocking, leaving the [`RwLock`] write-locked
+    /// forever and returning a mutable reference to the data.
+    ///
+    /// See [`crate::Guard::leak`].
+    pub fn leak(guard: Self) -> &'a mut T {
+        let rwlock = guard.rwlock;
+        std::mem::forget(guard);
+        // The lock is never released, so this is the only reference that can
+        // ever be handed out.
+        unsafe { &mut *rwlock.value.get() }
+    }
+
+    /// Narrow the guard to part of the locked data, such as a single field.
+    ///
+    /// guard: Self is used here so that it must be called as
+    /// WriteGuard::map(guard, ...) to avoid ambiguity with methods on T.
+    pub fn map<U>(guard: Self, f: impl FnOnce(&mut T) -> &mut U) -> MappedWriteGuard<'a, U> {
+        let rwlock = guard.rwlock;
+        let poison = guard.poison;
+        std::mem::forget(guard);
+        // The forgotten guard held an exclusive lock, which the mapped guard
+        // now releases instead.
+        let value = f(unsafe { &mut *rwlock.value.get() });
+        MappedWriteGuard {
+            raw: &rwlock.raw,
+            flag: &rwlock.poison,
+            poison,
+            value,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Like [`WriteGuard::map`], but `f` may decline by returning `None`, in
+    /// which case the original guard is handed back.
+    pub fn try_map<U>(
+        guard: Self,
+        f: impl FnOnce(&mut T) -> Option<&mut U>,
+    ) -> Result<MappedWriteGuard<'a, U>, Self> {
+        let rwlock = guard.rwlock;
+        let poison = guard.poison;
+        // Existence of the guard implies we have an exclusive lock.
+        match f(unsafe { &mut *rwlock.value.get() }) {
+            Some(value) => {
+                std::mem::forget(guard);
+                Ok(MappedWriteGuard {
+                    raw: &rwlock.raw,
+                    flag: &rwlock.poison,
+                    poison,
+                    value,
+                    _marker: PhantomData,
+                })
+            }
+            None => Err(guard),
+        }
+    }
+}
+
+impl<T> Deref for WriteGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // Existence of the guard implies we have an exclusive lock, so this is
+        // safe to do.
+        unsafe { &*self.rwlock.value.get() }
+    }
+}
+
+impl<T> DerefMut for WriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // Existence of the guard implies we have an exclusive lock, so this is
+        // safe to do.
+        unsafe { &mut *self.rwlock.value.get() }
+    }
+}
+
+impl<T> Drop for WriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.rwlock.poison.done(&self.poison);
+        // Existence of the guard implies we have an exclusive lock.
+        unsafe { self.rwlock.raw.unlock_exclusive() }
+    }
+}
+
+/// A [`ReadGuard`] which owns a reference counted [`RwLock`] instead of
+/// borrowing it.
+pub struct OwnedReadGuard<T> {
+    rwlock: Arc<RwLock<T>>,
+}
+
+impl<T> Deref for OwnedReadGuard<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // Existence of the guard implies nobody holds a write lock, so this is
+        // safe to do.
+        unsafe { &*self.rwlock.value.get() }
+    }
+}
+
+impl<T> Drop for OwnedReadGuard<T> {
+    fn drop(&mut self) {
+        // Existence of the guard implies we hold a shared lock.
+        unsafe { self.rwlock.raw.unlock_shared() }
+    }
+}
+
+/// A [`WriteGuard`] which owns a reference counted [`RwLock`] instead of
+/// borrowing it.
+pub struct OwnedWriteGuard<T> {
+    rwlock: Arc<RwLock<T>>,
+    poison: poison::Guard,
+}
+
+impl<T> Deref for OwnedWriteGuard<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // Existence of the guard implies we have an exclusive lock, so this is
+        // safe to do.
+        unsafe { &*self.rwlock.value.get() }
+    }
+}
+
+impl<T> DerefMut for OwnedWriteGuard<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // Existence of the guard implies we have an exclusive lock, so this is
+        // safe to do.
+        unsafe { &mut *self.rwlock.value.get() }
+    }
+}
+
+impl<T> Drop for OwnedWriteGuard<T> {
+    fn drop(&mut self) {
+        self.rwlock.poison.done(&self.poison);
+        // Existence of the guard implies we have an exclusive lock.
+        unsafe { self.rwlock.raw.unlock_exclusive() }
+    }
+}
+
+/// A [`ReadGuard`] narrowed to part of the locked data by [`ReadGuard::map`].
+pub struct MappedReadGuard<'a, U> {
+    raw: &'a RawRwLock,
+    value: &'a U,
+}
+
+impl<U> Deref for MappedReadGuard<'_, U> {
+    type Target = U;
+    fn deref(&self) -> &U {
+        self.value
+    }
+}
+
+impl<U> Drop for MappedReadGuard<'_, U> {
+    fn drop(&mut self) {
+        // Existence of the guard implies we hold a shared lock.
+        unsafe { self.raw.unlock_shared() }
+    }
+}
+
+/// A [`WriteGuard`] narrowed to part of the locked data by [`WriteGuard::map`].
+pub struct MappedWriteGuard<'a, U> {
+    raw: &'a RawRwLock,
+    flag: &'a poison::Flag,
+    poison: poison::Guard,
+    // Kept as a raw pointer so that `Deref` can hand out `&U` from `&self`
+    // while `DerefMut` hands out `&mut U`.
+    value: *mut U,
+    _marker: PhantomData<&'a mut U>,
+}
+
+// The raw pointer opts out of both, but the guard behaves like `&mut U`.
+unsafe impl<U> Send for MappedWriteGuard<'_, U> where U: Send {}
+unsafe impl<U> Sync for MappedWriteGuard<'_, U> where U: Sync {}
+
+impl<U> Deref for MappedWriteGuard<'_, U> {
+    type Target = U;
+    fn deref(&self) -> &U {
+        // Existence of the guard implies we have an exclusive lock, so this is
+        // safe to do.
+        unsafe { &*self.value }
+    }
+}
+
+impl<U> DerefMut for MappedWriteGuard<'_, U> {
+    fn deref_mut(&mut self) -> &mut U {
+        // Existence of the guard implies we have an exclusive lock, so this is
+        // safe to do.
+        unsafe { &mut *self.value }
+    }
+}
+
+impl<U> Drop for MappedWriteGuard<'_, U> {
+    fn drop(&mut self) {
+        self.flag.done(&self.poison);
+        // Existence of the guard implies we have an exclusive lock.
+        unsafe { self.raw.unlock_exclusive() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    use super::*;
+
+    // Filling the lock with real guards would take billions of them, so put the
+    // state straight into its fullest form.
+    fn full(flags: u32) -> RwLock<()> {
+        let lock = RwLock::new(());
+        lock.raw
+            .state
+            .store((MAX_READERS * READER) | flags, Ordering::Relaxed);
+        lock
+    }
+
+    #[test]
+    fn last_reader_fits() {
+        let lock = RwLock::new(());
+        lock.raw
+            .state
+            .store((MAX_READERS - 1) * READER, Ordering::Relaxed);
+        std::mem::forget(lock.read());
+        assert_eq!(lock.raw.state.load(Ordering::Relaxed), MAX_READERS * READER);
+    }
+
+    #[test]
+    #[should_panic(expected = "too many readers")]
+    fn read_overflow_panics() {
+        let _ = full(0).read();
+    }
+
+    #[test]
+    #[should_panic(expected = "too many readers")]
+    fn try_read_overflow_panics() {
+        let _ = full(0).try_read();
+    }
+
+    #[test]
+    #[should_panic(expected = "too many readers")]
+    fn read_recursive_overflow_panics() {
+        let _ = full(WRITER_WAITING).read_recursive();
+    }
+
+    #[test]
+    fn overflow_leaves_state_intact() {
+        let lock = full(UPGRADABLE);
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            let _ = lock.read();
+        }));
+        assert!(result.is_err());
+        let state = lock.raw.state.load(Ordering::Relaxed);
+        assert_eq!(state, (MAX_READERS * READER) | UPGRADABLE);
+        assert_ne!(state | WRITER_WAITING, WRITE_LOCKED);
+    }
+
+    #[test]
+    fn timed_write_times_out() {
+        let lock = RwLock::new(0);
+        let _read = lock.read();
+        let timeout = Duration::from_millis(20);
+        let start = Instant::now();
+        assert!(lock.try_write_for(timeout).is_none());
+        assert!(start.elapsed() >= timeout);
+    }
+
+    #[test]
+    fn timed_write_woken_by_unlock() {
+        let lock = RwLock::new(0);
+        let read = lock.read();
+        std::thread::scope(|s| {
+            let writer = s.spawn(|| {
+                let start = Instant::now();
+                let guard = lock.try_write_for(Duration::from_secs(10));
+                assert!(guard.is_some());
+                start.elapsed()
+            });
+            std::thread::sleep(Duration::from_millis(20));
+            drop(read);
+            assert!(writer.join().unwrap() < Duration::from_secs(5));
+        });
+    }
+
+    #[test]
+    fn reader_preferred_upgradable_woken_past_waiting_writer() {
+        let lock = Arc::new(RwLock::with_policy(0, Policy::ReaderPreferred));
+        let read = lock.read();
+        let upgradable = lock.upgradable_read();
+
+        // A writer queues behind the reader, setting the writer-waiting bit.
+        let writer = std::thread::spawn({
+            let lock = Arc::clone(&lock);
+            move || drop(lock.write())
+        });
+        while lock.raw.state.load(Ordering::Relaxed) & WRITER_WAITING == 0 {
+            std::thread::yield_now();
+        }
+
+        // A second upgradable reader waits for the first to let go.
+        let (tx, rx) = std::sync::mpsc::channel();
+        std::thread::spawn({
+            let lock = Arc::clone(&lock);
+            move || {
+                let _upgradable = lock.upgradable_read();
+                tx.send(()).unwrap();
+            }
+        });
+        std::thread::sleep(Duration::from_millis(20));
+        drop(upgradable);
+        // Readers are preferred, so the waiting writer doesn't hold it back.
+        rx.recv_timeout(Duration::from_secs(5))
+            .expect("upgradable reader was not woken");
+
+        drop(read);
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn panicking_writer_poisons_only_with_feature() {
+        let lock = RwLock::new(0);
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            let _write = lock.write();
+            panic!("while holding the write lock");
+        }));
+        assert!(result.is_err());
+        let poisoned = cfg!(feature = "poison");
+        assert_eq!(lock.is_poisoned(), poisoned);
+        assert_eq!(lock.read().is_err(), poisoned);
+        assert_eq!(lock.try_write().unwrap().is_err(), poisoned);
+        lock.clear_poison();
+        assert!(lock.read().is_ok());
+    }
+}
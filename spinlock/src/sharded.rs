@@ -0,0 +1,171 @@
+use std::{
+    cell::UnsafeCell,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::rwlock::RawRwLock;
+
+// Hands out a different index to each thread the first time it reads.
+static NEXT_THREAD: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static THREAD_INDEX: usize = NEXT_THREAD.fetch_add(1, Ordering::Relaxed);
+}
+
+// Padded out to a cache line so that readers of neighbouring shards don't
+// contend on the same line anyway.
+#[repr(align(64))]
+struct Shard(RawRwLock);
+
+/// A reader-writer lock split into one lock per shard, for read-mostly data.
+///
+/// Each thread is assigned a shard, and a reader only locks that one, so readers
+/// on different threads mostly touch different cache lines and reads scale with
+/// the number of cores. A writer has to lock every shard, in order, making writes
+/// proportionally more expensive.
+///
+/// This suits data which is read constantly from many threads but only written
+/// occasionally, such as configuration.
+pub struct ShardedRwLock<T> {
+    shards: Box<[Shard]>,
+    value: UnsafeCell<T>,
+}
+
+// Readers share `&T` between threads, so unlike a mutex `T: Sync` is needed
+// as well as `T: Send`.
+unsafe impl<T> Sync for ShardedRwLock<T> where T: Send + Sync {}
+
+impl<T> ShardedRwLock<T> {
+    /// Create a lock with one shard per available CPU.
+    pub fn new(value: T) -> Self {
+        let shards = std::thread::available_parallelism().map_or(1, |n| n.get());
+        Self::with_shards(shards, value)
+    }
+
+    /// Create a lock with a fixed number of shards.
+    pub fn with_shards(shards: usize, value: T) -> Self {
+        assert!(shards > 0, "ShardedRwLock needs at least one shard");
+        Self {
+            shards: (0..shards).map(|_| Shard(RawRwLock::new())).collect(),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Acquire a shared lock on this thread's shard as a [`ShardedReadGuard`].
+    pub fn read(&self) -> ShardedReadGuard<'_, T> {
+        let index = THREAD_INDEX.with(|index| *index) % self.shards.len();
+        let shard = &self.shards[index].0;
+        shard.lock_shared();
+        ShardedReadGuard { lock: self, shard }
+    }
+
+    /// Acquire an exclusive lock on every shard as a [`ShardedWriteGuard`].
+    pub fn write(&self) -> ShardedWriteGuard<'_, T> {
+        // Always locking in the same order means two writers can't deadlock
+        // each holding some of the shards.
+        for shard in self.shards.iter() {
+            shard.0.lock_exclusive();
+        }
+        ShardedWriteGuard { lock: self }
+    }
+
+    /// Number of shards the lock is split into.
+    pub fn shards(&self) -> usize {
+        self.shards.len()
+    }
+}
+
+pub struct ShardedReadGuard<'a, T> {
+    lock: &'a ShardedRwLock<T>,
+    shard: &'a RawRwLock,
+}
+
+impl<T> Deref for ShardedReadGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // Existence of the guard implies nobody holds a write lock, so this is
+        // safe to do.
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T> Drop for ShardedReadGuard<'_, T> {
+    fn drop(&mut self) {
+        // Existence of the guard implies we hold a shared lock on the shard.
+        unsafe { self.shard.unlock_shared() }
+    }
+}
+
+pub struct ShardedWriteGuard<'a, T> {
+    lock: &'a ShardedRwLock<T>,
+}
+
+impl<T> Deref for ShardedWriteGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // Existence of the guard implies we have an exclusive lock, so this is
+        // safe to do.
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T> DerefMut for ShardedWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // Existence of the guard implies we have an exclusive lock, so this is
+        // safe to do.
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T> Drop for ShardedWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        for shard in self.lock.shards.iter().rev() {
+            // Existence of the guard implies we hold every shard exclusively.
+            unsafe { shard.0.unlock_exclusive() }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{thread, time::Duration};
+
+    #[test]
+    fn write_excludes_readers_on_every_shard() {
+        let lock = ShardedRwLock::with_shards(4, 0);
+        let mut writer = lock.write();
+        for shard in lock.shards.iter() {
+            assert!(!shard.0.try_lock_shared());
+        }
+        thread::scope(|s| {
+            // Readers are spread over the shards by thread, so start more of
+            // them than there are shards.
+            let readers: Vec<_> = (0..8).map(|_| s.spawn(|| *lock.read())).collect();
+            thread::sleep(Duration::from_millis(20));
+            assert!(readers.iter().all(|reader| !reader.is_finished()));
+            *writer = 1;
+            drop(writer);
+            for reader in readers {
+                assert_eq!(reader.join().unwrap(), 1);
+            }
+        });
+    }
+
+    #[test]
+    fn readers_on_different_shards_share_the_lock() {
+        let lock = ShardedRwLock::with_shards(2, ());
+        let reader = lock.read();
+        // Another thread's reader goes ahead, whichever shard it is on.
+        thread::scope(|s| {
+            s.spawn(|| drop(lock.read())).join().unwrap();
+        });
+        drop(reader);
+        drop(lock.write());
+        for shard in lock.shards.iter() {
+            assert!(shard.0.try_lock_shared());
+            unsafe { shard.0.unlock_shared() };
+        }
+    }
+}
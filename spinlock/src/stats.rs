@@ -0,0 +1,120 @@
+//! Contention statistics for [`crate::rwlock::RawRwLock`].
+//!
+//! These are meant to help decide what a busy lock should become. Mostly reads
+//! with large herds of readers suggest sharding it, while writers spending a
+//! long time waiting behind readers suggest a seqlock.
+
+use std::{
+    fmt,
+    sync::atomic::{AtomicU32, AtomicU64, Ordering},
+    time::Duration,
+};
+
+/// Counters stored alongside each lock.
+pub(crate) struct Stats {
+    reads: AtomicU64,
+    writes: AtomicU64,
+    write_wait_nanos: AtomicU64,
+    // Sum over every read of the readers holding the lock once it joined, so
+    // that dividing by `reads` gives the average herd.
+    herd_total: AtomicU64,
+    herd_max: AtomicU32,
+}
+
+impl Stats {
+    pub(crate) const fn new() -> Self {
+        Self {
+            reads: AtomicU64::new(0),
+            writes: AtomicU64::new(0),
+            write_wait_nanos: AtomicU64::new(0),
+            herd_total: AtomicU64::new(0),
+            herd_max: AtomicU32::new(0),
+        }
+    }
+
+    /// Called once a shared lock has been acquired, with the number of readers
+    /// now holding it, including this one.
+    pub(crate) fn read(&self, readers: u32) {
+        self.reads.fetch_add(1, Ordering::Relaxed);
+        self.herd_total
+            .fetch_add(u64::from(readers), Ordering::Relaxed);
+        self.herd_max.fetch_max(readers, Ordering::Relaxed);
+    }
+
+    /// Called once an exclusive lock has been acquired, with how long the
+    /// writer waited for it.
+    pub(crate) fn write(&self, waited: Duration) {
+        let waited = waited.as_nanos().try_into().unwrap_or(u64::MAX);
+        self.writes.fetch_add(1, Ordering::Relaxed);
+        self.write_wait_nanos.fetch_add(waited, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> RwLockStats {
+        RwLockStats {
+            reads: self.reads.load(Ordering::Relaxed),
+            writes: self.writes.load(Ordering::Relaxed),
+            write_wait: Duration::from_nanos(self.write_wait_nanos.load(Ordering::Relaxed)),
+            herd_total: self.herd_total.load(Ordering::Relaxed),
+            max_herd: self.herd_max.load(Ordering::Relaxed),
+        }
+    }
+
+    pub(crate) fn reset(&self) {
+        self.reads.store(0, Ordering::Relaxed);
+        self.writes.store(0, Ordering::Relaxed);
+        self.write_wait_nanos.store(0, Ordering::Relaxed);
+        self.herd_total.store(0, Ordering::Relaxed);
+        self.herd_max.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Snapshot of the counters for one lock since it was created or last reset.
+///
+/// The counters are updated independently of each other, so a snapshot taken
+/// while the lock is in use may be very slightly inconsistent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RwLockStats {
+    /// Shared locks acquired, including upgradable ones.
+    pub reads: u64,
+    /// Exclusive locks acquired, including upgrades.
+    pub writes: u64,
+    /// Total time writers spent waiting before acquiring the lock.
+    pub write_wait: Duration,
+    herd_total: u64,
+    /// The most readers seen holding the lock at once.
+    pub max_herd: u32,
+}
+
+impl RwLockStats {
+    /// Average number of readers holding the lock at once, as seen by each
+    /// reader when it joined.
+    pub fn mean_herd(&self) -> f64 {
+        if self.reads == 0 {
+            return 0.0;
+        }
+        self.herd_total as f64 / self.reads as f64
+    }
+
+    /// Average time a writer waited for the lock.
+    pub fn mean_write_wait(&self) -> Duration {
+        if self.writes == 0 {
+            return Duration::ZERO;
+        }
+        let nanos = self.write_wait.as_nanos() / u128::from(self.writes);
+        Duration::from_nanos(nanos as u64)
+    }
+}
+
+impl fmt::Display for RwLockStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} reads (mean herd {:.1}, max {}), {} writes (mean wait {:?})",
+            self.reads,
+            self.mean_herd(),
+            self.max_herd,
+            self.writes,
+            self.mean_write_wait()
+        )
+    }
+}
@@ -0,0 +1,615 @@
+use std::{
+    cell::UnsafeCell,
+    collections::VecDeque,
+    fmt,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, Thread},
+    time::{Duration, Instant},
+};
+
+use crate::LockedPlaceholder;
+
+const LOCKED: u8 = 0b0001;
+const PARKED: u8 = 0b0010;
+const HANDOFF: u8 = 0b0100;
+const WAKING: u8 = 0b1000;
+const NO_SPIN: u8 = 0b1_0000;
+
+// Bits set at construction which never change afterwards.
+const POLICY: u8 = HANDOFF | NO_SPIN;
+
+// Values of a parked thread's wakeup flag.
+const WAITING: u8 = 0;
+const WOKEN: u8 = 1;
+const HANDED_OFF: u8 = 2;
+const TIMED_OUT: u8 = 3;
+
+// Number of spins attempted before a thread parks itself.
+const SPIN_LIMIT: u32 = 100;
+
+struct Waiter {
+    addr: usize,
+    thread: Thread,
+    unparked: Arc<AtomicU8>,
+}
+
+/// A single bucket of the parking table.
+///
+/// Many locks share each bucket, so waiters record the address of the lock they
+/// are waiting on and only the matching waiters are woken.
+struct Bucket {
+    waiters: Mutex<VecDeque<Waiter>>,
+}
+
+const BUCKETS: usize = 64;
+
+/// Global parking table, keyed by the address of the lock.
+///
+/// Keeping the queue of waiting threads outside of the lock is what allows the
+/// lock itself to be a single byte. The cost is that unrelated locks hashing to
+/// the same bucket briefly contend on it while parking or unparking, which only
+/// happens on the slow path.
+static TABLE: [Bucket; BUCKETS] = [const {
+    Bucket {
+        waiters: Mutex::new(VecDeque::new()),
+    }
+}; BUCKETS];
+
+// Whether a thread which found the lock held in `state` should spin again
+// rather than park, having spun `spins` times since it last parked.
+//
+// Threads arriving while others are parked go straight to the queue rather
+// than competing with them, but a thread which has just been woken gets to spin
+// so that it is likely to acquire the lock before having to park again.
+fn should_spin(state: u8, spins: u32, woken: bool) -> bool {
+    state & NO_SPIN == 0 && spins < SPIN_LIMIT && (state & PARKED == 0 || woken)
+}
+
+fn bucket(addr: usize) -> &'static Bucket {
+    // Locks are at least byte aligned and commonly sit next to one another in
+    // memory, so mix the bits before picking a bucket.
+    let hash = addr.wrapping_mul(0x9E37_79B9_7F4A_7C15_u64 as usize);
+    &TABLE[hash >> (usize::BITS - BUCKETS.trailing_zeros())]
+}
+
+/// Implementation of a compact, parking lock.
+///
+/// The entire lock state is a single [`AtomicU8`], holding a bit for whether
+/// the lock is held, a bit for whether any threads are parked waiting on it, a
+/// bit for whether a woken thread has yet to run and bits for the construction
+/// time policies (see [`WordLock::with_handoff`] and [`WordLock::without_spinning`]).
+/// This makes `WordLock<T>` only a byte larger than `T` (plus any padding for
+/// alignment), which matters when there are millions of fine-grained locks.
+///
+/// Unlike [`crate::SpinLock`], a thread which fails to acquire the lock after a
+/// short spin is parked in a global table keyed by the lock's address, so that
+/// a long held lock does not burn CPU time in its waiters.
+pub struct WordLock<T> {
+    state: AtomicU8,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T> Sync for WordLock<T> where T: Send {}
+
+impl<T> WordLock<T> {
+    pub const fn new(inner: T) -> Self {
+        Self {
+            state: AtomicU8::new(0),
+            data: UnsafeCell::new(inner),
+        }
+    }
+
+    /// Create a [`WordLock`] which hands the lock directly to a parked thread
+    /// when it is unlocked.
+    ///
+    /// By default an unlocking thread wakes a waiter and leaves the lock free,
+    /// so a newly arriving thread can take it before the woken one is scheduled.
+    /// That gives better throughput, but a waiter can lose out repeatedly. With
+    /// handoff the lock stays held and ownership is transferred to the waiter,
+    /// which bounds how long a parked thread can wait at the cost of throughput.
+    pub const fn with_handoff(inner: T) -> Self {
+        Self {
+            state: AtomicU8::new(HANDOFF),
+            data: UnsafeCell::new(inner),
+        }
+    }
+
+    /// Disable the spinning phase, so that a thread which finds the lock held
+    /// parks straight away.
+    ///
+    /// On a single core, or a heavily oversubscribed system, the thread holding
+    /// the lock can't run while another thread spins, so spinning is pure waste.
+    /// This can be combined with the other policies, for example
+    /// `WordLock::with_handoff(inner).without_spinning()`.
+    pub fn without_spinning(mut self) -> Self {
+        *self.state.get_mut() |= NO_SPIN;
+        self
+    }
+
+    /// Acquire an exclusive mutable lock as a [`WordLockGuard`].
+    pub fn lock(&self) -> WordLockGuard<'_, T> {
+        if self.state.fetch_or(LOCKED, Ordering::Acquire) & LOCKED != 0 {
+            self.lock_contended(None);
+        }
+        WordLockGuard {
+            lock: self,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Run `f` with exclusive access to the data, releasing the lock as soon as
+    /// it returns, or if it panics.
+    pub fn lock_with<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        f(&mut self.lock())
+    }
+
+    /// Attempt to acquire the lock without spinning or parking.
+    ///
+    /// Returns `None` if the lock is currently held.
+    pub fn try_lock(&self) -> Option<WordLockGuard<'_, T>> {
+        if self.state.fetch_or(LOCKED, Ordering::Acquire) & LOCKED == 0 {
+            Some(WordLockGuard {
+                lock: self,
+                _marker: PhantomData,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Attempt to acquire the lock, giving up once `timeout` has elapsed.
+    pub fn try_lock_for(&self, timeout: Duration) -> Option<WordLockGuard<'_, T>> {
+        self.try_lock_until(Instant::now() + timeout)
+    }
+
+    /// Attempt to acquire the lock, giving up once `deadline` has passed.
+    pub fn try_lock_until(&self, deadline: Instant) -> Option<WordLockGuard<'_, T>> {
+        if self.state.fetch_or(LOCKED, Ordering::Acquire) & LOCKED == 0
+            || self.lock_contended(Some(deadline))
+        {
+            Some(WordLockGuard {
+                lock: self,
+                _marker: PhantomData,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Whether the lock is currently held, see [`crate::SpinLock::is_locked`].
+    pub fn is_locked(&self) -> bool {
+        self.state.load(Ordering::Relaxed) & LOCKED != 0
+    }
+
+    /// Raw pointer to the underlying data, without taking the lock, see
+    /// [`crate::SpinLock::data_ptr`].
+    pub fn data_ptr(&self) -> *mut T {
+        self.data.get()
+    }
+
+    /// Returns `false` if `deadline` passed before the lock was acquired.
+    #[cold]
+    fn lock_contended(&self, deadline: Option<Instant>) -> bool {
+        let mut spins = 0;
+        let mut woken = false;
+        loop {
+            let state = self.state.load(Ordering::Relaxed);
+            if state & LOCKED == 0 {
+                // Keep the other bits, threads may still be parked.
+                if self
+                    .state
+                    .compare_exchange_weak(
+                        state,
+                        state | LOCKED,
+                        Ordering::Acquire,
+                        Ordering::Relaxed,
+                    )
+                    .is_ok()
+                {
+                    return true;
+                }
+                continue;
+            }
+
+            // Giving up is only done while the lock is held by another thread,
+            // whose unlock will then wake a waiter in our place if needed.
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return false;
+            }
+
+            if should_spin(state, spins, woken) {
+                spins += 1;
+                std::hint::spin_loop();
+                continue;
+            }
+
+            match self.park(deadline) {
+                HANDED_OFF => return true,
+                WOKEN => {
+                    // Allow unlocking threads to wake another waiter again.
+                    self.state.fetch_and(!WAKING, Ordering::Relaxed);
+                    woken = true;
+                }
+                _ => woken = false,
+            }
+            spins = 0;
+        }
+    }
+
+    /// Park the current thread until it is woken by an unlock.
+    ///
+    /// Returns how the thread was woken, [`TIMED_OUT`] if `deadline` passed
+    /// first, or [`WAITING`] if it didn't need to park because the lock was
+    /// released in the meantime.
+    fn park(&self, deadline: Option<Instant>) -> u8 {
+        let addr = self as *const Self as usize;
+        let unparked = Arc::new(AtomicU8::new(WAITING));
+        {
+            let mut waiters = bucket(addr).waiters.lock().unwrap();
+            // Set the parked bit while holding the bucket lock. An unlocking
+            // thread must take the same bucket lock before clearing it, so it
+            // is guaranteed to find us in the queue. If the lock was released
+            // in the meantime the exchange fails and we go back to trying to
+            // acquire it.
+            let state = self.state.load(Ordering::Relaxed);
+            if state & LOCKED == 0
+                || self
+                    .state
+                    .compare_exchange(state, state | PARKED, Ordering::Relaxed, Ordering::Relaxed)
+                    .is_err()
+            {
+                return WAITING;
+            }
+            waiters.push_back(Waiter {
+                addr,
+                thread: thread::current(),
+                unparked: Arc::clone(&unparked),
+            });
+        }
+        // park() can wake spuriously, so wait for the flag.
+        loop {
+            let woken = unparked.load(Ordering::Acquire);
+            if woken != WAITING {
+                return woken;
+            }
+            let Some(deadline) = deadline else {
+                thread::park();
+                continue;
+            };
+            let now = Instant::now();
+            if now < deadline {
+                thread::park_timeout(deadline - now);
+                continue;
+            }
+
+            let mut waiters = bucket(addr).waiters.lock().unwrap();
+            // An unlocking thread removes its chosen waiter from the queue before
+            // setting the flag. If we are no longer queued the flag is about to
+            // be set, and we must wait for it so that a handoff isn't lost.
+            let ours = waiters
+                .iter()
+                .position(|w| Arc::ptr_eq(&w.unparked, &unparked));
+            if let Some(position) = ours {
+                waiters.remove(position);
+                if !waiters.iter().any(|w| w.addr == addr) {
+                    self.state.fetch_and(!PARKED, Ordering::Relaxed);
+                }
+                return TIMED_OUT;
+            }
+        }
+    }
+
+    fn unlock(&self) {
+        // While a woken thread is on its way to take the lock, there is no need
+        // to wake anyone else. Doing so would only have several threads race
+        // for the lock, with all but one of them going straight back to sleep.
+        let state = self.state.load(Ordering::Relaxed);
+        if state & (PARKED | WAKING) == PARKED
+            || self
+                .state
+                .compare_exchange(state, state & !LOCKED, Ordering::Release, Ordering::Relaxed)
+                .is_err()
+        {
+            self.unlock_contended();
+        }
+    }
+
+    #[cold]
+    fn unlock_contended(&self) {
+        let addr = self as *const Self as usize;
+        let (waiter, handoff) = {
+            let mut waiters = bucket(addr).waiters.lock().unwrap();
+            let position = waiters.iter().position(|w| w.addr == addr);
+            let waiter = position.and_then(|p| waiters.remove(p));
+            let more = waiters.iter().any(|w| w.addr == addr);
+            let handoff = self.state.load(Ordering::Relaxed) & HANDOFF != 0 && waiter.is_some();
+            // Holding the bucket stops any other thread from parking, but a
+            // previously woken thread may still clear its waking bit, so the
+            // update has to be atomic.
+            let _ = self
+                .state
+                .fetch_update(Ordering::Release, Ordering::Relaxed, |state| {
+                    let mut next = state & (POLICY | WAKING);
+                    // Only keep the parked bit if there are still others waiting
+                    // behind the thread being woken.
+                    if more {
+                        next |= PARKED;
+                    }
+                    if handoff {
+                        // The lock is never released, the woken thread now owns it.
+                        next |= LOCKED;
+                    } else if waiter.is_some() {
+                        next |= WAKING;
+                    }
+                    Some(next)
+                });
+            (waiter, handoff)
+        };
+        if let Some(waiter) = waiter {
+            let woken = if handoff { HANDED_OFF } else { WOKEN };
+            waiter.unparked.store(woken, Ordering::Release);
+            waiter.thread.unpark();
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for WordLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("WordLock");
+        match self.try_lock() {
+            Some(guard) => d.field("data", &&*guard),
+            None => d.field("data", &LockedPlaceholder),
+        };
+        d.finish_non_exhaustive()
+    }
+}
+
+pub struct WordLockGuard<'a, T> {
+    lock: &'a WordLock<T>,
+    _marker: PhantomData<*const ()>,
+}
+
+// Sharing a guard between threads shares `&T`, so this must only be allowed
+// when `T` itself can be shared.
+unsafe impl<T> Sync for WordLockGuard<'_, T> where T: Sync {}
+
+// Guards are only Send with the `send_guard` feature, as with [`crate::Guard`].
+#[cfg(feature = "send_guard")]
+unsafe impl<T> Send for WordLockGuard<'_, T> where T: Send {}
+
+impl<'a, T> WordLockGuard<'a, T> {
+    /// Consume the guard without unlocking, see [`crate::Guard::leak`].
+    pub fn leak(guard: Self) -> &'a mut T {
+        let lock = guard.lock;
+        std::mem::forget(guard);
+        unsafe { &mut *lock.data.get() }
+    }
+}
+
+impl<T> Deref for WordLockGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // Existence of the guard implies we have an exclusive lock, so this is
+        // safe to do.
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> DerefMut for WordLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // Existence of the guard implies we have an exclusive lock, so this is
+        // safe to do.
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T> Drop for WordLockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.unlock();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+
+    use super::*;
+
+    fn addr<T>(lock: &WordLock<T>) -> usize {
+        lock as *const WordLock<T> as usize
+    }
+
+    // Number of threads parked on `lock`, ignoring any other lock which shares
+    // its bucket.
+    fn parked<T>(lock: &WordLock<T>) -> usize {
+        let addr = addr(lock);
+        let waiters = bucket(addr).waiters.lock().unwrap();
+        waiters.iter().filter(|w| w.addr == addr).count()
+    }
+
+    fn wait_for_parked<T>(lock: &WordLock<T>, count: usize) {
+        while parked(lock) != count {
+            thread::yield_now();
+        }
+    }
+
+    #[test]
+    fn mutual_exclusion_under_contention() {
+        let lock = WordLock::new(0u64);
+        let inside = AtomicBool::new(false);
+        thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| {
+                    for i in 0..10_000 {
+                        let mut guard = lock.lock();
+                        assert!(!inside.swap(true, Ordering::Relaxed));
+                        // A plain read then write, which loses updates unless
+                        // the lock keeps the other threads out in between.
+                        let value = *guard;
+                        if i % 100 == 0 {
+                            thread::yield_now();
+                        }
+                        *guard = value + 1;
+                        inside.store(false, Ordering::Relaxed);
+                    }
+                });
+            }
+        });
+        assert_eq!(*lock.lock(), 80_000);
+        assert_eq!(lock.state.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn timeout_expires_while_parked() {
+        let lock = WordLock::new(());
+        let guard = lock.lock();
+        thread::scope(|s| {
+            let waiter = s.spawn(|| {
+                let start = Instant::now();
+                let acquired = lock.try_lock_for(Duration::from_millis(50)).is_some();
+                (acquired, start.elapsed())
+            });
+            // It spins briefly, then parks until the timeout.
+            while parked(&lock) == 0 {
+                assert!(!waiter.is_finished(), "timed out without parking");
+                thread::yield_now();
+            }
+            let (acquired, elapsed) = waiter.join().unwrap();
+            assert!(!acquired);
+            assert!(elapsed >= Duration::from_millis(50));
+        });
+        // The waiter took itself out of the queue, and being the last one
+        // cleared the parked bit, so unlocking takes the fast path.
+        assert_eq!(parked(&lock), 0);
+        assert_eq!(lock.state.load(Ordering::Relaxed), LOCKED);
+        drop(guard);
+        assert!(lock.try_lock().is_some());
+    }
+
+    #[test]
+    fn locks_sharing_a_bucket() {
+        // There are more locks than buckets, so at least two of them share one.
+        let locks: Vec<WordLock<usize>> = (0..BUCKETS + 1).map(WordLock::new).collect();
+        let (a, b) = (0..locks.len())
+            .flat_map(|i| (i + 1..locks.len()).map(move |j| (i, j)))
+            .map(|(i, j)| (&locks[i], &locks[j]))
+            .find(|(a, b)| std::ptr::eq(bucket(addr(a)), bucket(addr(b))))
+            .unwrap();
+
+        let guard_a = a.lock();
+        let guard_b = b.lock();
+        thread::scope(|s| {
+            let waiter_a = s.spawn(|| *a.lock());
+            let waiter_b = s.spawn(|| *b.lock());
+            wait_for_parked(a, 1);
+            wait_for_parked(b, 1);
+
+            // Unlocking b only wakes the thread waiting on b, and leaves a's
+            // parked bit alone.
+            drop(guard_b);
+            assert_eq!(waiter_b.join().unwrap(), *b.lock());
+            assert_eq!(parked(a), 1);
+            assert_ne!(a.state.load(Ordering::Relaxed) & PARKED, 0);
+
+            drop(guard_a);
+            assert_eq!(waiter_a.join().unwrap(), *a.lock());
+        });
+        assert_eq!(parked(a), 0);
+        assert_eq!(parked(b), 0);
+    }
+
+    #[test]
+    fn handoff_gives_the_lock_to_the_waiter() {
+        let lock = WordLock::with_handoff(0);
+        let guard = lock.lock();
+        let (release_tx, release) = std::sync::mpsc::channel::<()>();
+        thread::scope(|s| {
+            let lock = &lock;
+            let waiter = s.spawn(move || {
+                let mut guard = lock.lock();
+                release.recv().unwrap();
+                *guard += 1;
+            });
+            wait_for_parked(lock, 1);
+            drop(guard);
+            // The lock was never released, so a thread arriving now can't barge
+            // in ahead of the woken waiter, however slow it is to run.
+            assert!(lock.try_lock().is_none());
+            release_tx.send(()).unwrap();
+            waiter.join().unwrap();
+        });
+        assert_eq!(*lock.lock(), 1);
+        assert_eq!(lock.state.load(Ordering::Relaxed), HANDOFF);
+    }
+
+    #[test]
+    fn one_waiter_woken_per_unlock() {
+        let lock = WordLock::new(());
+        let guard = lock.lock();
+        let (acquired_tx, acquired) = std::sync::mpsc::channel();
+        let (release_tx, release) = std::sync::mpsc::channel::<()>();
+        let release = Mutex::new(release);
+        thread::scope(|s| {
+            for _ in 0..2 {
+                let acquired_tx = acquired_tx.clone();
+                let (lock, release) = (&lock, &release);
+                s.spawn(move || {
+                    let _guard = lock.lock();
+                    acquired_tx.send(()).unwrap();
+                    release.lock().unwrap().recv().unwrap();
+                });
+            }
+            wait_for_parked(&lock, 2);
+
+            // While a woken thread is still on its way, unlocking wakes nobody.
+            lock.state.fetch_or(WAKING, Ordering::Relaxed);
+            drop(guard);
+            assert!(!lock.is_locked());
+            assert_eq!(parked(&lock), 2);
+            lock.state.fetch_and(!WAKING, Ordering::Relaxed);
+
+            // Otherwise exactly one is taken off the queue and woken. It can
+            // only go back to it if the lock is taken, and nothing else takes it.
+            let guard = lock.try_lock().unwrap();
+            drop(guard);
+            assert_eq!(parked(&lock), 1);
+            acquired.recv().unwrap();
+            assert_eq!(parked(&lock), 1);
+            assert!(acquired.try_recv().is_err());
+
+            // Which wakes the other when it unlocks in turn.
+            release_tx.send(()).unwrap();
+            acquired.recv().unwrap();
+            release_tx.send(()).unwrap();
+        });
+        assert_eq!(lock.state.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn no_spin_parks_straight_away() {
+        assert!(should_spin(LOCKED, 0, false));
+        assert!(!should_spin(LOCKED, SPIN_LIMIT, false));
+        assert!(!should_spin(LOCKED | PARKED, 0, false));
+        assert!(should_spin(LOCKED | PARKED, 0, true));
+        // Not even the first spin, and not even for a thread which was woken.
+        assert!(!should_spin(LOCKED | NO_SPIN, 0, false));
+        assert!(!should_spin(LOCKED | PARKED | NO_SPIN, 0, true));
+
+        // The policy bits survive the lock being contended and handed off.
+        let lock = WordLock::with_handoff(0).without_spinning();
+        let guard = lock.lock();
+        thread::scope(|s| {
+            let waiter = s.spawn(|| *lock.lock() += 1);
+            wait_for_parked(&lock, 1);
+            drop(guard);
+            waiter.join().unwrap();
+        });
+        assert_eq!(*lock.lock(), 1);
+        assert_eq!(lock.state.load(Ordering::Relaxed), HANDOFF | NO_SPIN);
+    }
+}